@@ -0,0 +1,157 @@
+use kvstore::protocol::{Command, Response};
+use serde_json::{Map, Value};
+
+// 响应的输出格式
+#[derive(Clone, Copy)]
+pub enum OutputFormat {
+    // 按服务端返回的原样输出
+    Raw,
+    // 将值解析为 JSON 并格式化输出
+    Json,
+    // 将列表和结构体渲染为表格
+    Table,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> OutputFormat {
+        match name {
+            "json" => OutputFormat::Json,
+            "table" => OutputFormat::Table,
+            _ => OutputFormat::Raw,
+        }
+    }
+}
+
+// 按照指定格式渲染服务端的响应，command 用于在 MGET 的输出中显示对应的键
+pub fn render(response: &Response, command: &Command, format: OutputFormat) -> String {
+    match (format, response) {
+        (OutputFormat::Raw, _) => response.to_string(),
+        (OutputFormat::Json, Response::Value(value)) => pretty(&parse(value)),
+        (OutputFormat::Json, Response::Values(values)) => pretty(&Value::Object(
+            keyed_values(command, values)
+                .into_iter()
+                .map(|(key, value)| (key, value.map(|value| parse(&value)).unwrap_or(Value::Null)))
+                .collect(),
+        )),
+        (OutputFormat::Table, Response::Value(value)) => render_table(&parse(value)),
+        (OutputFormat::Table, Response::Values(values)) => {
+            let rows: Vec<Vec<String>> = keyed_values(command, values)
+                .into_iter()
+                .map(|(key, value)| vec![key, value.unwrap_or_else(|| String::from("nil"))])
+                .collect();
+            table(&[String::from("key"), String::from("value")], &rows)
+        }
+        _ => response.to_string(),
+    }
+}
+
+// 将 MGET 的每个结果与请求中的键对应起来
+fn keyed_values(command: &Command, values: &[Option<String>]) -> Vec<(String, Option<String>)> {
+    let keys: Vec<String> = match command {
+        Command::MGet { keys } => keys.clone(),
+        _ => (0..values.len()).map(|i| i.to_string()).collect(),
+    };
+    keys.into_iter().zip(values.iter().cloned()).collect()
+}
+
+// 能解析为 JSON 的值按 JSON 处理，否则当作普通字符串
+fn parse(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()))
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap()
+}
+
+// 表格中的一个单元格，字符串不加引号，其余的值使用紧凑的 JSON 表示
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        other => other.to_string(),
+    }
+}
+
+fn is_flat(object: &Map<String, Value>) -> bool {
+    object
+        .values()
+        .all(|value| !value.is_array() && !value.is_object())
+}
+
+// 字段都是标量的结构体渲染为 "字段 | 值" 两列，
+// 元素都是这类结构体的列表渲染为每个字段一列，其余的列表渲染为 "下标 | 值" 两列
+fn render_table(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            let objects: Vec<&Map<String, Value>> =
+                items.iter().filter_map(|item| item.as_object()).collect();
+            if !items.is_empty()
+                && objects.len() == items.len()
+                && objects.iter().all(|o| is_flat(o))
+            {
+                let mut columns: Vec<String> = Vec::new();
+                for object in &objects {
+                    for key in object.keys() {
+                        if !columns.contains(key) {
+                            columns.push(key.clone());
+                        }
+                    }
+                }
+                let rows: Vec<Vec<String>> = objects
+                    .iter()
+                    .map(|object| {
+                        columns
+                            .iter()
+                            .map(|column| object.get(column).map(cell).unwrap_or_default())
+                            .collect()
+                    })
+                    .collect();
+                table(&columns, &rows)
+            } else {
+                let rows: Vec<Vec<String>> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| vec![i.to_string(), cell(item)])
+                    .collect();
+                table(&[String::from("#"), String::from("value")], &rows)
+            }
+        }
+        Value::Object(object) => {
+            let rows: Vec<Vec<String>> = object
+                .iter()
+                .map(|(key, value)| vec![key.clone(), cell(value)])
+                .collect();
+            table(&[String::from("field"), String::from("value")], &rows)
+        }
+        other => cell(other),
+    }
+}
+
+fn table(header: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, value) in row.iter().enumerate() {
+            widths[i] = widths[i].max(value.chars().count());
+        }
+    }
+
+    let line = |row: &[String]| {
+        row.iter()
+            .enumerate()
+            .map(|(i, value)| format!("{:width$}", value, width = widths[i]))
+            .collect::<Vec<String>>()
+            .join(" | ")
+            .trim_end()
+            .to_owned()
+    };
+
+    let mut lines = vec![line(header)];
+    lines.push(
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<String>>()
+            .join("-+-"),
+    );
+    lines.extend(rows.iter().map(|row| line(row)));
+    lines.join("\n")
+}
@@ -0,0 +1,173 @@
+use std::io::{self, BufRead, Read, Write};
+
+// 读取 REPL 的一行输入。标准输入是终端时关闭行缓冲和回显，逐个读取按键，按 Tab 时补全光标前的最后一个词：
+// complete 收到这个词之前的文本和这个词本身，返回所有可以替换这个词的候选。只有一个候选时直接替换，
+// 有多个候选时补全到它们的公共前缀，无法继续补全时列出所有候选。
+// 支持退格、Ctrl-U（清空当前行）、Ctrl-C（放弃当前行）和 Ctrl-D（空行时退出），不支持移动光标。
+// 标准输入不是终端（例如通过管道输入）或者不是 unix 时按行读取，没有补全。
+// 读到输入的末尾时返回 None。
+pub fn read_line<F>(prompt: &str, complete: F) -> io::Result<Option<String>>
+where
+    F: FnMut(&str, &str) -> Vec<String>,
+{
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    #[cfg(unix)]
+    {
+        if let Some(_raw) = raw::RawMode::enable() {
+            return edit(prompt, complete);
+        }
+    }
+    let _ = complete;
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+#[cfg(unix)]
+fn edit<F>(prompt: &str, mut complete: F) -> io::Result<Option<String>>
+where
+    F: FnMut(&str, &str) -> Vec<String>,
+{
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
+    let mut line: Vec<u8> = Vec::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        if stdin.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'\r' | b'\n' => {
+                writeln!(stdout)?;
+                return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+            }
+            // Ctrl-D
+            4 if line.is_empty() => {
+                writeln!(stdout)?;
+                return Ok(None);
+            }
+            // Ctrl-C
+            3 => {
+                line.clear();
+                write!(stdout, "^C\n{}", prompt)?;
+            }
+            // Ctrl-U
+            21 => {
+                line.clear();
+                redraw(&mut stdout, prompt, &line)?;
+            }
+            // 退格，删除最后一个完整的字符
+            8 | 127 => {
+                while let Some(last) = line.pop() {
+                    if last & 0xC0 != 0x80 {
+                        break;
+                    }
+                }
+                redraw(&mut stdout, prompt, &line)?;
+            }
+            b'\t' => {
+                let text = String::from_utf8_lossy(&line).into_owned();
+                let start = text.rfind(' ').map_or(0, |pos| pos + 1);
+                let (before, word) = text.split_at(start);
+                let candidates = complete(before, word);
+                match candidates.as_slice() {
+                    [] => write!(stdout, "\x07")?,
+                    [only] => {
+                        line = format!("{}{} ", before, only).into_bytes();
+                        redraw(&mut stdout, prompt, &line)?;
+                    }
+                    _ => {
+                        let common = common_prefix(&candidates);
+                        if common.len() > word.len() {
+                            line = format!("{}{}", before, common).into_bytes();
+                        } else {
+                            write!(stdout, "\n{}\n", candidates.join("  "))?;
+                        }
+                        redraw(&mut stdout, prompt, &line)?;
+                    }
+                }
+            }
+            // 忽略方向键等转义序列
+            27 => {
+                if stdin.read(&mut byte)? == 1 && byte[0] == b'[' {
+                    while stdin.read(&mut byte)? == 1 && !(0x40..=0x7E).contains(&byte[0]) {}
+                }
+            }
+            byte if byte < 32 => (),
+            byte => {
+                line.push(byte);
+                stdout.write_all(&[byte])?;
+            }
+        }
+        stdout.flush()?;
+    }
+}
+
+#[cfg(unix)]
+fn redraw(stdout: &mut io::Stdout, prompt: &str, line: &[u8]) -> io::Result<()> {
+    write!(stdout, "\r\x1b[K{}", prompt)?;
+    stdout.write_all(line)
+}
+
+// 所有候选共同的最长前缀，不会拆开一个字符
+fn common_prefix(candidates: &[String]) -> &str {
+    let first = &candidates[0];
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((pos, a), _)| pos + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    &first[..len]
+}
+
+#[cfg(unix)]
+mod raw {
+    // 在读取一行的过程中关闭终端的行缓冲和回显，drop 时恢复原来的设置。
+    // 同时关闭 ISIG，Ctrl-C 作为普通按键读取，进程不会在 raw 模式下被信号结束而留下没有回显的终端。
+    pub struct RawMode {
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        // 标准输入不是终端时返回 None
+        pub fn enable() -> Option<RawMode> {
+            unsafe {
+                if libc::isatty(libc::STDIN_FILENO) != 1 {
+                    return None;
+                }
+                let mut original: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                    return None;
+                }
+                let mut raw = original;
+                raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+                raw.c_cc[libc::VMIN] = 1;
+                raw.c_cc[libc::VTIME] = 0;
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                    return None;
+                }
+                Some(RawMode { original })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            }
+        }
+    }
+}
@@ -1,35 +1,635 @@
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+mod format;
+mod lineedit;
+
+use clap::{App, Arg, ArgMatches, Shell, SubCommand};
+use format::OutputFormat;
+use kvstore::protocol::{self, Command, Request, Response};
+use kvstore::{keys, KeyValueDb, KeyValueDbDumpPolicy, SerializationMethod};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process;
 use std::str;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-fn main() {
-    let mut stream = TcpStream::connect("127.0.0.1:4567").expect("Could not connect to server");
-    let mut input = String::new();
-    let mut reader = BufReader::new(stream.try_clone().expect("Could not clone stream"));
+const DEFAULT_SERVER: &str = "127.0.0.1:4567";
 
-    loop {
-        print!("kvstore> ");
-        std::io::stdout().flush().unwrap();
+// 列出键时每个 SCAN 请求返回的键的数量
+const SCAN_PAGE: usize = 1000;
+
+// REPL 中按 Tab 补全的命令名
+const COMMANDS: [&str; 26] = [
+    "BACKUP",
+    "BGSAVE",
+    "DECR",
+    "DECRBY",
+    "DEL",
+    "DUMP",
+    "EXPIRE",
+    "FLUSHDB",
+    "GET",
+    "INCR",
+    "INCRBY",
+    "LASTSAVE",
+    "LOCK",
+    "LRANGE",
+    "MGET",
+    "MIGRATE",
+    "PERSIST",
+    "RATELIMIT",
+    "RENEW",
+    "RESTORE",
+    "SCAN",
+    "SELECT",
+    "SET",
+    "SHUTDOWN",
+    "TTL",
+    "UNLOCK",
+];
+
+// 按照命令行给出的顺序解析所有服务端地址，一个域名可能解析出多个地址，
+// 解析结果保持原有的优先级顺序。
+fn resolve_servers(servers: &[String]) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+    for server in servers {
+        match server.to_socket_addrs() {
+            Ok(resolved) => addrs.extend(resolved),
+            Err(err) => eprintln!("Could not resolve {}: {}", server, err),
+        }
+    }
+    addrs
+}
+
+// 到服务端的连接，连接断开时按优先级重新连接
+struct Connection {
+    servers: Vec<String>,
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
 
-        // 从用户获取输入
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
-        let mut input_string = input.trim().to_owned();
+impl Connection {
+    // 依次尝试连接每一个地址，返回第一个连接成功的结果
+    fn open(servers: Vec<String>) -> io::Result<Connection> {
+        let addrs = resolve_servers(&servers);
+        for addr in &addrs {
+            match TcpStream::connect(addr) {
+                Ok(stream) => {
+                    eprintln!("Connected to {}", addr);
+                    let reader = BufReader::new(stream.try_clone()?);
+                    return Ok(Connection {
+                        servers,
+                        stream,
+                        reader,
+                    });
+                }
+                Err(err) => eprintln!("Could not connect to {}: {}", addr, err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "Could not connect to any server",
+        ))
+    }
+
+    // 发送一条请求并读取响应，连接断开时重新连接并重试一次
+    fn send(&mut self, request: &Request) -> io::Result<Response> {
+        match self.send_once(request) {
+            Ok(response) => Ok(response),
+            Err(err) => {
+                eprintln!("Lost connection: {}", err);
+                *self = Connection::open(self.servers.clone())?;
+                self.send_once(request)
+            }
+        }
+    }
 
-        // 发送命令到服务端
-        stream
-            .write_all(input_string.as_bytes())
-            .expect("Failed to write to server");
+    // 发送一条请求并读取一行响应，服务端关闭连接时视为错误
+    fn send_once(&mut self, request: &Request) -> io::Result<Response> {
+        let encoded = protocol::encode(request)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        self.stream.write_all(encoded.as_bytes())?;
 
-        // 读取服务端的响应并显示
         let mut buffer: Vec<u8> = Vec::new();
-        reader
-            .read_until(b'\n', &mut buffer)
+        if self.reader.read_until(b'\n', &mut buffer)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "Server closed the connection",
+            ));
+        }
+        protocol::decode(str::from_utf8(&buffer).expect("Invalid UTF-8"))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+
+    // 获取服务端上名为 name 的锁，返回隔离令牌，锁已经被持有时返回 None
+    fn lock(&mut self, name: &str, ttl: Duration) -> io::Result<Option<u64>> {
+        let request = Request::new(Command::Lock {
+            name: String::from(name),
+            ttl_ms: ttl.as_millis() as u64,
+        });
+        match self.send(&request)? {
+            Response::Integer(token) => Ok(Some(token as u64)),
+            Response::Nil => Ok(None),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    // 释放 lock 获取的锁，锁已经过期或者被其他人重新获取时返回 false
+    fn unlock(&mut self, name: &str, token: u64) -> io::Result<bool> {
+        let request = Request::new(Command::Unlock {
+            name: String::from(name),
+            token,
+        });
+        match self.send(&request)? {
+            Response::Integer(released) => Ok(released == 1),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> io::Error {
+    match response {
+        Response::Error(err) => io::Error::other(err),
+        response => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected response {:?}", response),
+        ),
+    }
+}
+
+fn servers(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .values_of("servers")
+        .unwrap()
+        .map(String::from)
+        .collect()
+}
+
+// 用 SCAN 列出服务端上以 prefix 开头的所有键，按字典序排列
+fn scan_keys(connection: &mut Connection, prefix: &str) -> io::Result<Vec<String>> {
+    let mut cursor = String::from("0");
+    let mut found = Vec::new();
+    loop {
+        let request = Request::new(Command::Scan {
+            cursor,
+            count: SCAN_PAGE,
+        });
+        match connection.send(&request)? {
+            Response::Scan { cursor: next, keys } => {
+                found.extend(keys.into_iter().filter(|key| key.starts_with(prefix)));
+                if next == "0" {
+                    break;
+                }
+                cursor = next;
+            }
+            response => return Err(unexpected(response)),
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+// REPL 的补全：第一个词补全命令名，之后的词补全服务端上的键。
+// 输入的词和候选都是文本协议中转义之后的形式，例如 "a%20b"
+fn complete(connection: &mut Connection, before: &str, word: &str) -> Vec<String> {
+    if before.trim().is_empty() {
+        let word = word.to_uppercase();
+        return COMMANDS
+            .iter()
+            .filter(|name| name.starts_with(&word))
+            .map(|name| String::from(*name))
+            .collect();
+    }
+    let prefix = keys::unescape(word).unwrap_or_else(|| String::from(word));
+    match scan_keys(connection, &prefix) {
+        Ok(found) => found.iter().map(|key| keys::escape_key(key)).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn repl(servers: Vec<String>, format: OutputFormat) {
+    let mut connection = Connection::open(servers).expect("Could not connect to server");
+
+    // 从用户获取输入，按 Tab 补全命令名和键
+    while let Some(input) = lineedit::read_line("kvstore> ", |before, word| {
+        complete(&mut connection, before, word)
+    })
+    .expect("Failed to read line")
+    {
+        if input.trim().is_empty() {
+            continue;
+        }
+        let request = match input.trim().parse::<Command>() {
+            Ok(command) => Request::new(command),
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+
+        // 发送命令到服务端并显示响应
+        let response = connection
+            .send(&request)
             .expect("Could not read from server");
-        let response = str::from_utf8(&buffer).expect("Invalid UTF-8");
-        println!("{}", response.trim());
+        println!("{}", format::render(&response, &request.command, format));
+    }
+}
+
+// 逐行读取命令文件，空行和以 '#' 开头的行会被忽略。
+// 执行之前会先检查所有命令的语法，任何一行有错误都不会执行任何命令。
+fn read_commands(file: &str) -> Result<Vec<Command>, String> {
+    let content = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+    let mut commands = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.parse::<Command>() {
+            Ok(command) => commands.push(command),
+            Err(err) => return Err(format!("{}:{}: {}", file, number + 1, err)),
+        }
+    }
+    Ok(commands)
+}
+
+// 执行命令文件。
+// 指定了本地数据库文件时，所有命令都成功后才会一次性写入文件，任何一条命令失败时文件保持不变；
+// 否则依次发送到服务端执行，遇到第一个失败的命令时停止（服务端没有事务，之前的命令不会回滚）。
+fn exec(
+    file: &str,
+    db_path: Option<&str>,
+    servers: Vec<String>,
+    format: OutputFormat,
+) -> Result<(), String> {
+    let commands = read_commands(file)?;
+
+    match db_path {
+        Some(db_path) => {
+            let mut db = if Path::new(db_path).exists() {
+                KeyValueDb::load(
+                    db_path,
+                    KeyValueDbDumpPolicy::DumpUponRequest,
+                    SerializationMethod::Json,
+                )
+                .map_err(|err| format!("{}: {}", db_path, err))?
+            } else {
+                KeyValueDb::new(
+                    db_path,
+                    KeyValueDbDumpPolicy::DumpUponRequest,
+                    SerializationMethod::Json,
+                )
+            };
+
+            for command in commands {
+                let response = protocol::execute(&mut db, command.clone());
+                println!("{}", format::render(&response, &command, format));
+                if let Response::Error(err) = response {
+                    return Err(format!("Aborted, {} was not modified: {}", db_path, err));
+                }
+            }
+            db.dump().map_err(|err| format!("{}: {}", db_path, err))
+        }
+
+        None => {
+            let mut connection = Connection::open(servers).map_err(|err| err.to_string())?;
+            for command in commands {
+                let request = Request::new(command);
+                let response = connection.send(&request).map_err(|err| err.to_string())?;
+                println!("{}", format::render(&response, &request.command, format));
+                if let Response::Error(err) = response {
+                    return Err(format!("Aborted: {}", err));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// 读取数据库文件中以 prefix 开头的所有键，每个键对应它导出后的字节数据，用于比较前后两次的变化
+fn watch_snapshot(db: &KeyValueDb, prefix: &str) -> HashMap<String, Vec<u8>> {
+    db.get_all()
+        .into_iter()
+        .filter(|key| key.starts_with(prefix))
+        .filter_map(|key| match db.export_key(&key) {
+            Ok(Some(data)) => Some((key, data)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn describe(db: &KeyValueDb, key: &str) -> String {
+    if db.lexists(key) {
+        format!("<list of {} items>", db.llen(key))
+    } else {
+        match db.get::<serde_json::Value>(key) {
+            Some(value) => value.to_string(),
+            None => String::from("<unreadable value>"),
+        }
+    }
+}
+
+// 每隔 interval 检查一次数据库文件，文件被修改后重新读取并打印以 prefix 开头的键的变化。
+// 文件是整体重写的，因此这里只能看到两次检查之间的最终结果，看不到中间状态。
+// 指定了 timeout 时最多观察这么长时间，期间没有任何变化时返回错误。
+fn watch(
+    db_path: &str,
+    prefix: &str,
+    interval: Duration,
+    timeout: Option<Duration>,
+) -> Result<(), String> {
+    let load = || {
+        KeyValueDb::load_read_only(db_path, SerializationMethod::Json)
+            .map_err(|err| format!("{}: {}", db_path, err))
+    };
+    let modified = || fs::metadata(db_path).and_then(|meta| meta.modified()).ok();
+
+    let mut last_modified: Option<SystemTime> = modified();
+    let mut last = watch_snapshot(&load()?, prefix);
+    println!("Watching {} keys in {}", last.len(), db_path);
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut changed = false;
+    loop {
+        let pause = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => interval.min(remaining),
+                _ if changed => return Ok(()),
+                _ => return Err(format!("No changes within {:?}", timeout.unwrap())),
+            },
+            None => interval,
+        };
+        thread::sleep(pause);
+        let current_modified = modified();
+        if current_modified == last_modified {
+            continue;
+        }
+        last_modified = current_modified;
+
+        // 文件可能正在被替换，读取失败时等待下一次检查
+        let db = match load() {
+            Ok(db) => db,
+            Err(_) => continue,
+        };
+        let current = watch_snapshot(&db, prefix);
+
+        let mut keys: Vec<&String> = last.keys().chain(current.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            match (last.get(key), current.get(key)) {
+                (None, Some(_)) => println!("+ {} = {}", key, describe(&db, key)),
+                (Some(_), None) => println!("- {}", key),
+                (Some(old), Some(new)) if old != new => {
+                    println!("~ {} = {}", key, describe(&db, key))
+                }
+                _ => continue,
+            }
+            changed = true;
+        }
+        last = current;
+    }
+}
+
+// 用 SCAN 列出服务端上以 prefix 开头的所有键，每行一个，按字典序排列，
+// 可以在 shell 的补全函数中调用，例如：clapgui keys session: --server host:4567。
+// 键按照文本协议转义，输出的每一行都可以直接作为命令的参数
+fn list_keys(servers: Vec<String>, prefix: &str) -> Result<(), String> {
+    let mut connection = Connection::open(servers).map_err(|err| err.to_string())?;
+    let found = scan_keys(&mut connection, prefix).map_err(|err| err.to_string())?;
+    for key in found {
+        println!("{}", keys::escape_key(&key));
+    }
+    Ok(())
+}
+
+// 持有服务端上的锁运行一个命令，命令结束后释放锁，例如在多台主机的 crontab 中：
+// clapgui lock nightly-report --ttl 600000 -- ./report.sh
+// 锁已经被持有时不运行命令并返回错误。租约不会自动续期，ttl 应该比命令的运行时间更长；
+// 命令可以通过环境变量 KVSTORE_LOCK_TOKEN 读取隔离令牌。
+fn run_locked(
+    servers: Vec<String>,
+    name: &str,
+    ttl: Duration,
+    command: &[&str],
+) -> Result<(), String> {
+    // 服务端一次只处理一个连接，因此运行命令期间不能保持连接，释放锁时重新连接
+    let mut connection = Connection::open(servers.clone()).map_err(|err| err.to_string())?;
+    let token = match connection.lock(name, ttl).map_err(|err| err.to_string())? {
+        Some(token) => token,
+        None => return Err(format!("Lock '{}' is held by someone else", name)),
+    };
+    drop(connection);
+
+    let status = process::Command::new(command[0])
+        .args(&command[1..])
+        .env("KVSTORE_LOCK_TOKEN", token.to_string())
+        .status();
+
+    let released = Connection::open(servers)
+        .and_then(|mut connection| connection.unlock(name, token))
+        .map_err(|err| err.to_string())?;
+    let status = status.map_err(|err| format!("{}: {}", command[0], err))?;
+    if !released {
+        return Err(format!(
+            "Lock '{}' expired before the command finished",
+            name
+        ));
+    }
+    if !status.success() {
+        return Err(format!("{} exited with {}", command[0], status));
+    }
+    Ok(())
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("clapgui")
+        .about("Interactive client for the kvstore example server")
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["raw", "json", "table"])
+                .default_value("raw")
+                .env("KVSTORE_FORMAT")
+                .help("How to print values returned by the server"),
+        )
+        .arg(
+            Arg::with_name("servers")
+                .multiple(true)
+                .default_value(DEFAULT_SERVER)
+                .env("KVSTORE_SERVERS")
+                .use_delimiter(true)
+                .help("Server addresses in order of preference"),
+        )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Runs a file of commands, one per line")
+                .arg(Arg::with_name("file").required(true))
+                .arg(
+                    Arg::with_name("db")
+                        .help("Run against this local database file instead of a server"),
+                )
+                .arg(
+                    Arg::with_name("servers")
+                        .long("server")
+                        .takes_value(true)
+                        .multiple(true)
+                        .default_value(DEFAULT_SERVER)
+                        .env("KVSTORE_SERVERS")
+                        .use_delimiter(true)
+                        .help("Server addresses in order of preference"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Prints changes to a database file as they happen")
+                .arg(Arg::with_name("db").required(true))
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("Only show keys starting with this prefix"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("500")
+                        .help("Polling interval in milliseconds"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .takes_value(true)
+                        .help("Stop after this many milliseconds, failing if nothing changed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Prints a shell completion script to stdout")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&Shell::variants()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keys")
+                .about("Lists the keys on the server, one per line")
+                .arg(Arg::with_name("prefix").default_value(""))
+                .arg(
+                    Arg::with_name("servers")
+                        .long("server")
+                        .takes_value(true)
+                        .multiple(true)
+                        .default_value(DEFAULT_SERVER)
+                        .env("KVSTORE_SERVERS")
+                        .use_delimiter(true)
+                        .help("Server addresses in order of preference"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lock")
+                .about("Runs a command while holding a lock on the server")
+                .arg(Arg::with_name("name").required(true))
+                .arg(
+                    Arg::with_name("ttl")
+                        .long("ttl")
+                        .takes_value(true)
+                        .default_value("60000")
+                        .help("Lease time in milliseconds"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .required(true)
+                        .multiple(true)
+                        .last(true),
+                )
+                .arg(
+                    Arg::with_name("servers")
+                        .long("server")
+                        .takes_value(true)
+                        .multiple(true)
+                        .default_value(DEFAULT_SERVER)
+                        .env("KVSTORE_SERVERS")
+                        .use_delimiter(true)
+                        .help("Server addresses in order of preference"),
+                ),
+        )
+}
+
+fn main() {
+    // 服务端地址按优先级从命令行传入，例如：clapgui --format table primary:4567 replica:4567
+    // 执行命令文件：clapgui exec commands.txt db.json，或者 clapgui exec commands.txt --server host:4567
+    // 查看数据库文件的变化：clapgui watch db.json --prefix session:
+    // 默认的服务端地址和输出格式也可以通过环境变量 KVSTORE_SERVERS（以逗号分隔）和 KVSTORE_FORMAT 指定
+    // 生成 shell 补全脚本：clapgui completions bash > /etc/bash_completion.d/clapgui
+    // 持有锁运行命令：clapgui lock nightly-report --ttl 600000 -- ./report.sh
+    // 列出服务端上的键：clapgui keys session: --server host:4567，在交互模式中也可以按 Tab 补全命令名和键
+    let matches = app().get_matches();
+    let format = OutputFormat::from_name(matches.value_of("format").unwrap());
+
+    let result = match matches.subcommand() {
+        ("exec", Some(exec_matches)) => {
+            let file = exec_matches.value_of("file").unwrap();
+            let db_path = exec_matches.value_of("db");
+            exec(file, db_path, servers(exec_matches), format)
+        }
+        ("watch", Some(watch_matches)) => {
+            let db_path = watch_matches.value_of("db").unwrap();
+            let prefix = watch_matches.value_of("prefix").unwrap();
+            let timeout = match watch_matches.value_of("timeout").map(str::parse) {
+                Some(Ok(millis)) => Ok(Some(Duration::from_millis(millis))),
+                Some(Err(err)) => Err(format!("Invalid timeout: {}", err)),
+                None => Ok(None),
+            };
+            match (watch_matches.value_of("interval").unwrap().parse(), timeout) {
+                (Ok(millis), Ok(timeout)) => {
+                    watch(db_path, prefix, Duration::from_millis(millis), timeout)
+                }
+                (Err(err), _) => Err(format!("Invalid interval: {}", err)),
+                (_, Err(err)) => Err(err),
+            }
+        }
+        ("completions", Some(completions_matches)) => {
+            let shell: Shell = completions_matches
+                .value_of("shell")
+                .unwrap()
+                .parse()
+                .unwrap();
+            app().gen_completions_to("clapgui", shell, &mut io::stdout());
+            Ok(())
+        }
+        ("keys", Some(keys_matches)) => list_keys(
+            servers(keys_matches),
+            keys_matches.value_of("prefix").unwrap(),
+        ),
+        ("lock", Some(lock_matches)) => {
+            let name = lock_matches.value_of("name").unwrap();
+            let command: Vec<&str> = lock_matches.values_of("command").unwrap().collect();
+            match lock_matches.value_of("ttl").unwrap().parse() {
+                Ok(millis) => run_locked(
+                    servers(lock_matches),
+                    name,
+                    Duration::from_millis(millis),
+                    &command,
+                ),
+                Err(err) => Err(format!("Invalid ttl: {}", err)),
+            }
+        }
+        _ => {
+            repl(servers(&matches), format);
+            Ok(())
+        }
+    };
 
-        input.clear();
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
     }
 }
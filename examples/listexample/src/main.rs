@@ -54,12 +54,16 @@ fn main() {
         .unwrap()
         // add an integer item to the list
         .ladd(&200)
+        .unwrap()
         // add an floating point item to the list
         .ladd(&2.1)
+        .unwrap()
         // add a string to the list
         .ladd(&String::from("my list"))
+        .unwrap()
         // add a vector of chars to the list
         .ladd(&vec!['a', 'b', 'c'])
+        .unwrap()
         // add multiple values to the list: add 3 rectangles
         .lextend(&[
             Rectangle {
@@ -74,7 +78,8 @@ fn main() {
                 width: 1,
                 length: 22,
             },
-        ]);
+        ])
+        .unwrap();
 
     // print the list length
     println!("list1 length is: {}", db.llen("list1"));
@@ -104,7 +109,7 @@ fn main() {
     );
 
     // create a new list
-    db.lcreate("list2").unwrap().lextend(&[1, 2, 3, 4]);
+    db.lcreate("list2").unwrap().lextend(&[1, 2, 3, 4]).unwrap();
 
     // iterate over the items in list2
     for item_iter in db.liter("list2") {
@@ -0,0 +1,226 @@
+use crate::log;
+use kvstore::{KeyValueDbDumpPolicy, KeyValueDbEvictionPolicy};
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+// 服务端的配置。优先级从低到高依次为：默认值、配置文件、环境变量、命令行参数。
+//
+// 配置文件使用 TOML 的一个子集，每行一个 "键 = 值"，'#' 开头的行是注释，例如：
+//
+//     addr = "0.0.0.0:4567"
+//     db_path = "/var/lib/kvstore/data.db"
+//     dump_policy = "periodic"   # never、auto、upon_request 或 periodic
+//     dump_interval_ms = 1000    # 只在 dump_policy 为 periodic 时使用
+//     pid_file = "/run/kvstore.pid"
+//     log_file = "/var/log/kvstore.log"
+//     max_key_len = 1024         # 键的最大长度（字节），默认不限制
+//     max_value_bytes = 1048576  # 单个值的最大大小（字节），默认不限制
+//     max_memory_bytes = 67108864  # 每个逻辑数据库的内存上限（字节），默认不限制
+//     eviction_policy = "lru"    # 超过 max_memory_bytes 时：reject（拒绝写入，默认）、lru 或 lfu
+//     log_format = "json"        # text 或 json，json 时每个事件输出一行 JSON 对象
+//     log_fields = "ts,event,peer,request,duration_us"  # JSON 日志包含的字段，默认输出所有字段
+//     backup_dir = "/var/lib/kvstore/backups"  # BACKUP 只能写入这个目录，默认为当前目录
+//
+// 对应的环境变量为 KVSTORE_ 加上大写的键名，例如 KVSTORE_ADDR、KVSTORE_DUMP_INTERVAL_MS。
+pub struct Config {
+    pub addr: String,
+    pub db_path: String,
+    pub dump_policy: String,
+    pub dump_interval_ms: u64,
+    pub pid_file: Option<String>,
+    pub log_file: Option<String>,
+    pub max_key_len: Option<usize>,
+    pub max_value_bytes: Option<usize>,
+    pub max_memory_bytes: Option<usize>,
+    pub eviction_policy: String,
+    pub log_format: String,
+    pub log_fields: Vec<String>,
+    pub backup_dir: String,
+}
+
+const KEYS: [&str; 13] = [
+    "addr",
+    "db_path",
+    "dump_policy",
+    "dump_interval_ms",
+    "pid_file",
+    "log_file",
+    "max_key_len",
+    "max_value_bytes",
+    "max_memory_bytes",
+    "eviction_policy",
+    "log_format",
+    "log_fields",
+    "backup_dir",
+];
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            addr: String::from("127.0.0.1:4567"),
+            db_path: String::from("keyvaluedb.db"),
+            dump_policy: String::from("auto"),
+            dump_interval_ms: 1000,
+            pid_file: None,
+            log_file: None,
+            max_key_len: None,
+            max_value_bytes: None,
+            max_memory_bytes: None,
+            eviction_policy: String::from("reject"),
+            log_format: String::from("text"),
+            log_fields: Vec::new(),
+            backup_dir: String::from("."),
+        }
+    }
+}
+
+impl Config {
+    // 读取配置文件（未指定时使用默认值），然后应用环境变量
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let mut config = Config::default();
+        if let Some(path) = path {
+            let content = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+            config
+                .apply_file(&content)
+                .map_err(|err| format!("{}: {}", path, err))?;
+        }
+        for key in KEYS {
+            let name = format!("KVSTORE_{}", key.to_uppercase());
+            if let Ok(value) = env::var(&name) {
+                config
+                    .set(key, &value)
+                    .map_err(|err| format!("{}: {}", name, err))?;
+            }
+        }
+        config.dump_policy()?;
+        config.eviction_policy()?;
+        config.json_log_fields()?;
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, content: &str) -> Result<(), String> {
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value.trim())),
+                None => return Err(format!("line {}: expected key = value", number + 1)),
+            };
+            self.set(key, &value?)
+                .map_err(|err| format!("line {}: {}", number + 1, err))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "addr" => self.addr = value.to_owned(),
+            "db_path" => self.db_path = value.to_owned(),
+            "dump_policy" => self.dump_policy = value.to_owned(),
+            "dump_interval_ms" => {
+                self.dump_interval_ms = value
+                    .parse()
+                    .map_err(|_| format!("Invalid dump_interval_ms '{}'", value))?
+            }
+            "pid_file" => self.pid_file = Some(value.to_owned()),
+            "log_file" => self.log_file = Some(value.to_owned()),
+            "max_key_len" => {
+                self.max_key_len = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid max_key_len '{}'", value))?,
+                )
+            }
+            "max_value_bytes" => {
+                self.max_value_bytes = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid max_value_bytes '{}'", value))?,
+                )
+            }
+            "max_memory_bytes" => {
+                self.max_memory_bytes = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid max_memory_bytes '{}'", value))?,
+                )
+            }
+            "eviction_policy" => self.eviction_policy = value.to_owned(),
+            "log_format" => self.log_format = value.to_owned(),
+            "log_fields" => {
+                self.log_fields = value
+                    .split(',')
+                    .map(|field| field.trim().to_owned())
+                    .filter(|field| !field.is_empty())
+                    .collect()
+            }
+            "backup_dir" => self.backup_dir = value.to_owned(),
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    pub fn dump_policy(&self) -> Result<KeyValueDbDumpPolicy, String> {
+        match self.dump_policy.as_str() {
+            "never" => Ok(KeyValueDbDumpPolicy::NeverDump),
+            "auto" => Ok(KeyValueDbDumpPolicy::AutoDump),
+            "upon_request" => Ok(KeyValueDbDumpPolicy::DumpUponRequest),
+            "periodic" => Ok(KeyValueDbDumpPolicy::PeriodicDump(Duration::from_millis(
+                self.dump_interval_ms,
+            ))),
+            other => Err(format!("Unknown dump_policy '{}'", other)),
+        }
+    }
+
+    pub fn eviction_policy(&self) -> Result<KeyValueDbEvictionPolicy, String> {
+        match self.eviction_policy.as_str() {
+            "reject" => Ok(KeyValueDbEvictionPolicy::RejectWrites),
+            "lru" => Ok(KeyValueDbEvictionPolicy::Lru),
+            "lfu" => Ok(KeyValueDbEvictionPolicy::Lfu),
+            other => Err(format!("Unknown eviction_policy '{}'", other)),
+        }
+    }
+
+    // log_format 为 json 时返回 JSON 日志包含的字段（为空表示所有字段），为 text 时返回 None
+    pub fn json_log_fields(&self) -> Result<Option<Vec<String>>, String> {
+        match self.log_format.as_str() {
+            "text" => Ok(None),
+            "json" => {
+                if let Some(field) = self
+                    .log_fields
+                    .iter()
+                    .find(|field| !log::FIELDS.contains(&field.as_str()))
+                {
+                    return Err(format!("Unknown log field '{}'", field));
+                }
+                Ok(Some(self.log_fields.clone()))
+            }
+            other => Err(format!("Unknown log_format '{}'", other)),
+        }
+    }
+}
+
+// 去掉行尾的注释，带引号的值去掉引号，其余的值（数字等）原样返回
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(rest) = value.strip_prefix('"') {
+        let end = rest
+            .find('"')
+            .ok_or_else(|| format!("Unterminated string {}", value))?;
+        let after = rest[end + 1..].trim();
+        if after.is_empty() || after.starts_with('#') {
+            Ok(rest[..end].to_owned())
+        } else {
+            Err(format!("Unexpected characters after {}", value))
+        }
+    } else {
+        let value = value.split('#').next().unwrap().trim();
+        if value.is_empty() {
+            Err(String::from("Missing value"))
+        } else {
+            Ok(value.to_owned())
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 服务端的日志默认输出到标准输出，指定了 log_file 时追加写入该文件。
+// 备份在后台线程中进行，因此日志文件需要用 Mutex 保护。
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+// log_format 为 json 时保存 log_fields 中的字段名（为空表示输出所有字段），None 表示输出纯文本
+static JSON_FIELDS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+// JSON 日志中可能出现的字段，并不是每个事件都包含所有字段：
+// ts 是 Unix 时间戳（单位为毫秒），event 是事件名，msg 是纯文本格式下输出的内容
+pub const FIELDS: [&str; 12] = [
+    "ts",
+    "level",
+    "event",
+    "msg",
+    "addr",
+    "peer",
+    "db",
+    "request",
+    "response",
+    "duration_us",
+    "path",
+    "error",
+];
+
+pub fn init(path: &str) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+// 之后的日志每行输出一个 JSON 对象，只包含 fields 中的字段，fields 为空时输出所有字段
+pub fn use_json(fields: Vec<String>) {
+    *JSON_FIELDS.lock().unwrap() = Some(fields);
+}
+
+pub fn write_line(line: &str) {
+    match LOG_FILE.lock().unwrap().as_mut() {
+        // 写日志失败时没有更好的地方报告错误，直接忽略
+        Some(file) => {
+            let _ = writeln!(file, "{}", line);
+        }
+        None => println!("{}", line),
+    }
+}
+
+// 启动失败等错误信息，没有日志文件时输出到标准错误
+pub fn write_error(line: &str) {
+    let line = format_event("error", "error", line, Vec::new());
+    match LOG_FILE.lock().unwrap().as_mut() {
+        Some(file) => {
+            let _ = writeln!(file, "{}", line);
+        }
+        None => eprintln!("{}", line),
+    }
+}
+
+// 记录一个事件，纯文本格式只输出 text，JSON 格式同时输出 fields 中的字段
+pub fn event(event: &str, text: &str, fields: Vec<(&str, Value)>) {
+    write_line(&format_event("info", event, text, fields));
+}
+
+fn format_event(level: &str, event: &str, text: &str, fields: Vec<(&str, Value)>) -> String {
+    let selected = JSON_FIELDS.lock().unwrap();
+    let selected = match selected.as_ref() {
+        Some(selected) => selected,
+        None => return text.to_owned(),
+    };
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+    let common = vec![
+        ("ts", Value::from(ts)),
+        ("level", Value::from(level)),
+        ("event", Value::from(event)),
+        ("msg", Value::from(text)),
+    ];
+    let mut object = Map::new();
+    for (name, value) in common.into_iter().chain(fields) {
+        if selected.is_empty() || selected.iter().any(|field| field == name) {
+            object.insert(name.to_owned(), value);
+        }
+    }
+    Value::Object(object).to_string()
+}
+
+// 用法：log!("事件名", [("字段", 值), ...], 与 println! 相同的参数)，
+// 值可以是任何能转换为 serde_json::Value 的类型
+macro_rules! log {
+    ($event:expr, [$(($name:expr, $value:expr)),* $(,)?], $($arg:tt)*) => {
+        $crate::log::event(
+            $event,
+            &format!($($arg)*),
+            vec![$(($name, serde_json::Value::from($value))),*],
+        )
+    };
+}
@@ -1,99 +1,481 @@
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use kvstore::{KeyValueDb, KeyValueDbDumpPolicy, SerializationMethod};
+#[macro_use]
+mod log;
+mod config;
+mod systemd;
+
+use config::Config;
+use kvstore::protocol::{self, Command, Request, Response, PROTOCOL_VERSION};
+use kvstore::{KeyValueDb, SerializationMethod};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 服务端的状态：数据库本身以及最近一次备份成功的时间（Unix 时间戳，单位为秒），
+// 备份在后台线程中完成，因此 last_save 需要在线程之间共享，backups 是还没有等待过的备份线程。
+// BACKUP 的路径相对于 backup_dir，不能离开这个目录。
+// 收到 SHUTDOWN 命令并保存数据库后 shutdown 为 true，服务端在返回响应并等待所有备份完成后退出。
+struct Server {
+    db: KeyValueDb,
+    addr: SocketAddr,
+    last_save: Arc<Mutex<Option<u64>>>,
+    backups: Vec<JoinHandle<()>>,
+    backup_dir: PathBuf,
+    pid_file: Option<String>,
+    shutdown: bool,
+}
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:4567").unwrap();
-    println!("Server listening on 127.0.0.1:4567");
+    // 用法：server [--config server.toml] [--daemon] [--pid-file file] [--log-file file] [addr] [db_path]
+    // 例如：server 127.0.0.1:4568 other.db
+    // 命令行参数的优先级最高，其次是环境变量和配置文件，详见 config.rs
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let daemon = take_flag(&mut args, "--daemon");
+    let child_args = args.clone();
+    let config_path = take_option(&mut args, "--config");
+    let pid_file = take_option(&mut args, "--pid-file");
+    let log_file = take_option(&mut args, "--log-file");
+
+    let mut config = Config::load(config_path.as_deref()).unwrap_or_else(|err| exit_with(&err));
+    let mut args = args.into_iter();
+    if let Some(addr) = args.next() {
+        config.addr = addr;
+    }
+    if let Some(db_path) = args.next() {
+        config.db_path = db_path;
+    }
+    if pid_file.is_some() {
+        config.pid_file = pid_file;
+    }
+    if log_file.is_some() {
+        config.log_file = log_file;
+    }
+    let dump_policy = config.dump_policy().unwrap_or_else(|err| exit_with(&err));
+    let eviction_policy = config
+        .eviction_policy()
+        .unwrap_or_else(|err| exit_with(&err));
+    if let Some(fields) = config
+        .json_log_fields()
+        .unwrap_or_else(|err| exit_with(&err))
+    {
+        log::use_json(fields);
+    }
 
-    let mut db = KeyValueDb::new(
-        "keyvaluedb.db",
-        KeyValueDbDumpPolicy::AutoDump,
-        SerializationMethod::Json,
+    if daemon {
+        daemonize(&child_args);
+    }
+    if let Some(path) = &config.log_file {
+        if let Err(err) = log::init(path) {
+            exit_with(&format!("{}: {}", path, err));
+        }
+    }
+
+    // 由 systemd 套接字激活启动时使用 systemd 传入的套接字，此时忽略 addr
+    let listener = match systemd::listener() {
+        Some(listener) => listener,
+        None => TcpListener::bind(&config.addr).unwrap_or_else(|err| {
+            exit_with(&format!("Could not listen on {}: {}", config.addr, err))
+        }),
+    };
+    let local_addr = listener.local_addr().unwrap();
+    let addr = local_addr.to_string();
+    log!(
+        "listening",
+        [("addr", addr.as_str())],
+        "Server listening on {}",
+        addr
     );
 
-    let mut buffer = [0; 1024]; // 将 buffer 的定义移动到更高的作用域中
+    if let Some(path) = &config.pid_file {
+        if let Err(err) = fs::write(path, format!("{}\n", process::id())) {
+            exit_with(&format!("{}: {}", path, err));
+        }
+    }
+
+    // 数据库文件已经存在时加载其中的数据，加载失败时退出，不会用空数据库覆盖原来的文件
+    let mut db = if Path::new(&config.db_path).exists() {
+        KeyValueDb::load(&config.db_path, dump_policy, SerializationMethod::Json)
+            .unwrap_or_else(|err| exit_with(&err.to_string()))
+    } else {
+        KeyValueDb::new(&config.db_path, dump_policy, SerializationMethod::Json)
+    };
+    if let Some(max_key_len) = config.max_key_len {
+        db = db.with_max_key_len(max_key_len);
+    }
+    if let Some(max_value_bytes) = config.max_value_bytes {
+        db = db.with_max_value_bytes(max_value_bytes);
+    }
+    if let Some(max_memory_bytes) = config.max_memory_bytes {
+        db = db.with_max_memory(max_memory_bytes, eviction_policy);
+    }
+    let mut server = Server {
+        db,
+        addr: local_addr,
+        last_save: Arc::new(Mutex::new(None)),
+        backups: Vec::new(),
+        backup_dir: PathBuf::from(config.backup_dir),
+        pid_file: config.pid_file,
+        shutdown: false,
+    };
+    systemd::notify("READY=1");
 
     for stream in listener.incoming() {
         match stream {
-            Ok(mut stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
-                let mut data_received = false;
-
-                loop {
-                    if !data_received {
-                        println!("[+] processing");
-                        let bytes_read = stream.read(&mut buffer).unwrap();
-                        if bytes_read == 0 {
-                            break;
-                        }
-
-                        let command = String::from_utf8_lossy(&buffer[..bytes_read]).trim().to_owned();
-                        println!("cmd: {:?}", command);
-                        let response = process_command(&mut db, command);
-
-                        stream.write_all(response.as_bytes()).unwrap();
-                        println!("rsp: {:?}", response);
-                        stream.flush().unwrap();
-
-                        data_received = true;
-                    } else {
-                        println!("[+] waiting");
-                        // Check if there is new data available
-                        let mut buf = [0; 1];
-                        match stream.peek(&mut buf) {
-                            Ok(0) => break, // No new data, break the loop
-                            Ok(_) => { 
-                                if let Ok(s) = std::str::from_utf8(&buf) {
-                                    println!("buf: {:?}", s);
-                                }
-                                data_received = false;
-                                continue;
-                            },
-                            Err(_) => break, // Error reading data, break the loop
-                        }
-                    }
-                }
+            Ok(stream) => {
+                let peer = stream.peer_addr().unwrap().to_string();
+                log!(
+                    "connect",
+                    [("peer", peer.as_str())],
+                    "New connection: {}",
+                    peer
+                );
+                handle_client(&mut server, stream, &peer);
+                log!(
+                    "disconnect",
+                    [("peer", peer.as_str())],
+                    "Connection closed: {}",
+                    peer
+                );
             }
             Err(e) => {
-                println!("Connection failed: {}", e);
+                log!(
+                    "connect_failed",
+                    [("error", e.to_string())],
+                    "Connection failed: {}",
+                    e
+                );
             }
         }
     }
 }
 
+// 从参数中取出一个不带值的选项，返回它是否存在
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
 
+// 从参数中取出一个带值的选项，例如 "--config server.toml"
+fn take_option(args: &mut Vec<String>, option: &str) -> Option<String> {
+    match args.iter().position(|arg| arg == option) {
+        Some(pos) if pos + 1 < args.len() => {
+            let value = args.remove(pos + 1);
+            args.remove(pos);
+            Some(value)
+        }
+        Some(_) => exit_with(&format!("{} requires a value", option)),
+        None => None,
+    }
+}
 
-fn process_command(db: &mut KeyValueDb, command: String) -> String {
-    let tokens: Vec<&str> = command.split_whitespace().collect();
-    let mut response = String::new();
+// 在后台重新启动当前程序（去掉 --daemon），子进程的标准输入输出都重定向到 /dev/null，
+// 并放入新的进程组，关闭终端时不会被一起结束。父进程打印子进程的 pid 后立即退出，
+// 因此子进程启动失败（例如端口被占用）时只会记录在日志文件中。
+fn daemonize(args: &[String]) -> ! {
+    let exe = env::current_exe().unwrap_or_else(|err| exit_with(&err.to_string()));
+    let mut command = process::Command::new(exe);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
 
-    match tokens[0] {
-        "SET" => {
-            let key = tokens[1];
-            let value = tokens[2..].join(" ");
-            db.set(key, &value);
-            response = "OK".to_owned()
+    match command.spawn() {
+        Ok(child) => {
+            println!("Server started in the background, pid {}", child.id());
+            process::exit(0);
         }
-        "GET" => {
-            let key = tokens[1];
-            match db.get::<String>(&key) {
-                Some(value) => response = value.to_owned(),
-                None => response = "nil".to_owned(),
-            }
+        Err(err) => exit_with(&format!("Could not start the server: {}", err)),
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    log::write_error(message);
+    process::exit(1);
+}
+
+// 数据库已经在处理 SHUTDOWN 时保存，这里等待正在进行的备份写完，删除 pid 文件后退出
+fn stop(server: &mut Server) -> ! {
+    systemd::notify("STOPPING=1");
+    for backup in server.backups.drain(..) {
+        let _ = backup.join();
+    }
+    if let Some(path) = &server.pid_file {
+        let _ = fs::remove_file(path);
+    }
+    log!("stopped", [], "Server stopped");
+    process::exit(0);
+}
+
+// 每一行是一条请求，逐行读取并返回一行响应，直到客户端断开连接。
+// 每个连接都从逻辑数据库 0 开始，SELECT 只影响当前连接。
+// 每条请求在处理完成后记录一次，JSON 日志中包含请求、响应和处理时间。
+fn handle_client(server: &mut Server, mut stream: TcpStream, peer: &str) {
+    let mut selected = 0;
+    let reader = match stream.try_clone() {
+        Ok(stream) => BufReader::new(stream),
+        Err(err) => {
+            log!(
+                "clone_failed",
+                [("peer", peer), ("error", err.to_string())],
+                "Could not clone stream for {}: {}",
+                peer,
+                err
+            );
+            return;
+        }
+    };
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let started = Instant::now();
+        let db = selected;
+
+        // 兼容 v1 文本协议，文本请求以纯文本响应
+        let (response, encoded) = if protocol::is_v1_text(&line) {
+            let response = match protocol::parse_v1(&line) {
+                Ok(command) => process_command(server, &mut selected, command),
+                Err(err) => Response::Error(err),
+            };
+            let encoded = protocol::encode_v1(&response);
+            (response, encoded)
+        } else {
+            let response = match protocol::decode::<Request>(&line) {
+                Ok(request) if request.version != PROTOCOL_VERSION => Response::Error(format!(
+                    "Unsupported protocol version {}, expected {}",
+                    request.version, PROTOCOL_VERSION
+                )),
+                Ok(request) => process_command(server, &mut selected, request.command),
+                Err(err) => Response::Error(err.to_string()),
+            };
+            let encoded = protocol::encode(&response).unwrap();
+            (response, encoded)
+        };
+        log!(
+            "request",
+            [
+                ("peer", peer),
+                ("db", db),
+                ("request", line.as_str()),
+                (
+                    "response",
+                    serde_json::to_value(&response).unwrap_or_default()
+                ),
+                ("duration_us", started.elapsed().as_micros() as u64),
+            ],
+            "req: {:?}\nrsp: {:?}",
+            line,
+            response
+        );
+        if server.shutdown {
+            let _ = stream.write_all(encoded.as_bytes());
+            stop(server);
+        }
+        if stream.write_all(encoded.as_bytes()).is_err() {
+            break;
         }
-        "DEL" => {
-            let key = tokens[1];
-            if db.exists(&key) {
-                db.rem(&key);
-                response = "OK".to_owned()
-            } else {
-                response = "nil".to_owned()
+    }
+}
+
+// 需要访问服务端状态的命令在这里处理，其余的命令直接在数据库上执行
+fn process_command(server: &mut Server, selected: &mut usize, command: Command) -> Response {
+    if let Command::Select { index } = command {
+        *selected = index;
+        return Response::Ok;
+    }
+
+    let db = &mut server.db.database(*selected);
+    match command {
+        Command::Migrate { target, prefix } => match migrate(db, server.addr, &target, &prefix) {
+            Ok(count) => Response::Integer(count as i64),
+            Err(err) => Response::Error(err),
+        },
+        Command::Backup { path } => match backup_path(&server.backup_dir, &path) {
+            Ok(path) => match db.snapshot() {
+                Ok(snapshot) => {
+                    server.backups.retain(|backup| !backup.is_finished());
+                    let backup = backup(snapshot, path, Arc::clone(&server.last_save));
+                    server.backups.push(backup);
+                    Response::Ok
+                }
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Err(err) => Response::Error(err),
+        },
+        Command::LastSave => match *server.last_save.lock().unwrap() {
+            Some(time) => Response::Integer(time as i64),
+            None => Response::Nil,
+        },
+        Command::Shutdown => match db.dump() {
+            Ok(_) => {
+                server.shutdown = true;
+                Response::Ok
+            }
+            Err(err) => Response::Error(err.to_string()),
+        },
+        command => protocol::execute(db, command),
+    }
+}
+
+// BACKUP 的路径必须是 backup_dir 中的相对路径，拒绝绝对路径和包含 ".." 的路径
+fn backup_path(backup_dir: &Path, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    let inside = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if path.is_empty() || !inside {
+        return Err(format!(
+            "Invalid backup path '{}': expected a relative path inside the backup directory",
+            path
+        ));
+    }
+    Ok(backup_dir.join(relative))
+}
+
+// 每个备份使用不同的临时文件，同时进行的两个备份不会写入同一个临时文件
+static BACKUP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 在后台线程中将快照写入 path，先写临时文件再重命名，避免留下写了一半的备份文件。
+// 返回备份线程，服务端退出之前需要等待它完成
+fn backup(snapshot: Vec<u8>, path: PathBuf, last_save: Arc<Mutex<Option<u64>>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(format!(
+            ".temp.{}.{}",
+            process::id(),
+            BACKUP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = fs::write(&temp_path, snapshot).and_then(|_| fs::rename(&temp_path, &path));
+        let path = path.display().to_string();
+        match result {
+            Ok(_) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                *last_save.lock().unwrap() = Some(now);
+                log!(
+                    "backup",
+                    [("path", path.as_str())],
+                    "Backup saved to {}",
+                    path
+                );
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path);
+                log!(
+                    "backup_failed",
+                    [("path", path.as_str()), ("error", err.to_string())],
+                    "Backup to {} failed: {}",
+                    path,
+                    err
+                )
             }
         }
-        _ => response = "Invalid command".to_owned(),
+    })
+}
+
+// 连接目标服务端、发送数据和等待响应各自最多等待的时间
+const MIGRATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 将以 prefix 开头的键导出并发送到目标服务端，返回迁移的键的数量。
+// 服务端同一时间只处理一个连接，迁移到自己会一直等待自己的响应，因此拒绝 target 指向服务端自己的地址。
+// 目标服务端确认导入了所有导出的键之后才从本地删除；发送失败、超时或者导入的数量不一致时本地的键保持不变，
+// 本地删除失败时 rem_prefix 会恢复被删除的键，此时这些键同时存在于两个服务端。
+fn migrate(
+    db: &mut KeyValueDb,
+    own_addr: SocketAddr,
+    target: &str,
+    prefix: &str,
+) -> Result<usize, String> {
+    let target_addrs: Vec<SocketAddr> = target
+        .to_socket_addrs()
+        .map_err(|err| format!("{}: {}", target, err))?
+        .collect();
+    if target_addrs.iter().any(|addr| is_own_addr(own_addr, *addr)) {
+        return Err(format!("Cannot migrate to the server itself ({})", target));
+    }
+
+    let expected = db
+        .get_all()
+        .iter()
+        .filter(|key| key.starts_with(prefix))
+        .count();
+    if expected == 0 {
+        return Ok(0);
+    }
+    let data = db.export_keys(prefix).map_err(|err| err.to_string())?;
+    let request = Request::new(Command::Import { data });
+
+    let mut stream = connect(&target_addrs).map_err(|err| format!("{}: {}", target, err))?;
+    stream
+        .set_write_timeout(Some(MIGRATE_TIMEOUT))
+        .and_then(|_| stream.set_read_timeout(Some(MIGRATE_TIMEOUT)))
+        .map_err(|err| err.to_string())?;
+    let encoded = protocol::encode(&request).map_err(|err| err.to_string())?;
+    stream
+        .write_all(encoded.as_bytes())
+        .map_err(|err| err.to_string())?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|err| err.to_string())?;
+    match protocol::decode::<Response>(&line).map_err(|err| err.to_string())? {
+        Response::Integer(count) if count as usize == expected => (),
+        Response::Integer(count) => {
+            return Err(format!(
+                "{} imported {} of {} keys, keeping the local keys",
+                target, count, expected
+            ))
+        }
+        Response::Error(err) => return Err(err),
+        other => return Err(format!("Unexpected response from {}: {:?}", target, other)),
     }
 
-    response.push('\n');
-    response
-}
\ No newline at end of file
+    db.rem_prefix(prefix).map_err(|err| {
+        format!(
+            "Keys were copied to {} but could not be removed locally: {}",
+            target, err
+        )
+    })?;
+    Ok(expected)
+}
+
+// 服务端监听 0.0.0.0 或者 [::] 时，本机回环地址上的同一个端口也是服务端自己
+fn is_own_addr(own_addr: SocketAddr, addr: SocketAddr) -> bool {
+    addr.port() == own_addr.port()
+        && (addr.ip() == own_addr.ip()
+            || (own_addr.ip().is_unspecified()
+                && (addr.ip().is_loopback() || addr.ip().is_unspecified())))
+}
+
+fn connect(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to");
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, MIGRATE_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
@@ -0,0 +1,111 @@
+// 可选的 systemd 集成，只有在由 systemd 启动时才会生效，直接运行时什么都不做。
+//
+// 套接字激活：使用 .socket 单元时，systemd 预先监听端口并通过 LISTEN_PID 和 LISTEN_FDS
+// 把已经打开的套接字（从文件描述符 3 开始）交给服务端，重启服务端期间新的连接会在队列中等待，不会被拒绝。
+// 通知：使用 Type=notify 时，服务端在开始接受连接后向 NOTIFY_SOCKET 发送 READY=1，
+// 收到 SHUTDOWN 命令退出之前发送 STOPPING=1。
+//
+// 例如：
+//
+//     # kvstore.socket
+//     [Socket]
+//     ListenStream=127.0.0.1:4567
+//
+//     # kvstore.service
+//     [Service]
+//     Type=notify
+//     ExecStart=/usr/local/bin/server --config /etc/kvstore/server.toml
+use std::env;
+use std::net::TcpListener;
+
+// 返回 systemd 传入的第一个监听套接字，没有使用套接字激活时返回 None
+#[cfg(unix)]
+pub fn listener() -> Option<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+    use std::process;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != process::id() || fds < 1 {
+        return None;
+    }
+    // 避免子进程误以为这些套接字是传给它的
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // 描述符 3 由 systemd 打开并交给本进程，此后只归这个 TcpListener 所有
+    Some(unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+#[cfg(not(unix))]
+pub fn listener() -> Option<TcpListener> {
+    None
+}
+
+// 向 systemd 发送一条状态通知，例如 "READY=1"，没有设置 NOTIFY_SOCKET 时什么都不做
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(err) => {
+            log!(
+                "notify_failed",
+                [("error", err.to_string())],
+                "Could not notify systemd: {}",
+                err
+            );
+            return;
+        }
+    };
+
+    let result = match path.strip_prefix('@') {
+        Some(name) => send_abstract(&socket, name, state),
+        None => socket.send_to(state.as_bytes(), &path).map(|_| ()),
+    };
+    if let Err(err) = result {
+        log!(
+            "notify_failed",
+            [("error", err.to_string())],
+            "Could not notify systemd: {}",
+            err
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+// 以 '@' 开头的 NOTIFY_SOCKET 是 Linux 的抽象套接字地址
+#[cfg(target_os = "linux")]
+fn send_abstract(
+    socket: &std::os::unix::net::UnixDatagram,
+    name: &str,
+    state: &str,
+) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    socket.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(
+    _socket: &std::os::unix::net::UnixDatagram,
+    _name: &str,
+    _state: &str,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Abstract socket addresses are only supported on Linux",
+    ))
+}
@@ -0,0 +1,97 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// KeyValueDbScheduler、KeyValueDbSweeper、DbManager 和 LeaderElector 共用的后台线程。
+// stop 或者 drop 时通知线程停止并等待它退出，线程在两次执行之间通过 StopSignal 等待停止的通知。
+pub(crate) struct BackgroundTask {
+    stop: Sender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundTask {
+    // 每隔 interval 调用一次 tick，第一次调用在启动 interval 之后
+    pub(crate) fn every<F>(interval: Duration, mut tick: F) -> BackgroundTask
+    where
+        F: FnMut() + Send + 'static,
+    {
+        BackgroundTask::spawn(move |stopped| {
+            while !stopped.wait(interval) {
+                tick();
+            }
+        })
+    }
+
+    // 由 run 自己控制循环，用于需要在第一次等待之前执行或者在退出之前清理的线程
+    pub(crate) fn spawn<F>(run: F) -> BackgroundTask
+    where
+        F: FnOnce(StopSignal) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || run(StopSignal(stopped)));
+        BackgroundTask {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    // 通知线程停止并等待它退出，正在进行的一次执行会先完成
+    pub(crate) fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = self.stop.send(());
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for BackgroundTask {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+pub(crate) struct StopSignal(Receiver<()>);
+
+impl StopSignal {
+    // 最多等待 timeout，收到停止的通知（或者 BackgroundTask 已经被 drop）时返回 true
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        !matches!(self.0.recv_timeout(timeout), Err(RecvTimeoutError::Timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn stop_interrupts_the_wait() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&ticks);
+        let mut task = BackgroundTask::every(Duration::from_secs(60), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        task.stop();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(ticks.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn ticks_until_dropped() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&ticks);
+        let task = BackgroundTask::every(Duration::from_millis(1), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        while ticks.load(Ordering::SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(task);
+        let after_drop = ticks.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(ticks.load(Ordering::SeqCst), after_drop);
+    }
+}
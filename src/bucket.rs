@@ -0,0 +1,76 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Result;
+use crate::iterators::KeyValueDbIterator;
+use crate::keyvaluedb::KeyValueDb;
+
+// 数据库中的一个命名空间，由 KeyValueDb::bucket 返回。
+// 所有的键都会自动加上 "name:" 前缀，返回的键则去掉了这个前缀，
+// 例如 db.bucket("users").set("42", &user) 实际写入的键是 "users:42"。
+// bucket 按原样使用名字，名为 "a" 的命名空间中的 "b:c" 与名为 "a:b" 的命名空间中的 "c" 是同一个键；
+// bucket_escaped 用 keys::escape 转义名字，名为 "a:b" 的命名空间的前缀是 "a%3Ab:"，不会与名为 "a" 的命名空间重叠。
+// 命名空间中的键都按原样写入，由多个部分组成的键可以用 keys::join 生成。
+pub struct KeyValueDbBucket<'a> {
+    pub(crate) db: &'a mut KeyValueDb,
+    pub(crate) name: String,
+    pub(crate) prefix: String,
+}
+
+impl<'a> KeyValueDbBucket<'a> {
+    // 命名空间的名字（没有转义）
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set<V>(&mut self, key: &str, value: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        let key = self.full_key(key);
+        self.db.set(&key, value)
+    }
+
+    pub fn get<V>(&self, key: &str) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        self.db.get(&self.full_key(key))
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.db.exists(&self.full_key(key))
+    }
+
+    pub fn rem(&mut self, key: &str) -> Result<bool> {
+        let key = self.full_key(key);
+        self.db.rem(&key)
+    }
+
+    // 返回命名空间中所有的键（包括普通值和列表），不包括前缀
+    pub fn get_all(&self) -> Vec<String> {
+        self.db
+            .keys_with_prefix(&self.prefix)
+            .map(|key| String::from(&key[self.prefix.len()..]))
+            .collect()
+    }
+
+    pub fn total_keys(&self) -> usize {
+        self.db.keys_with_prefix(&self.prefix).count()
+    }
+
+    // 删除命名空间中所有的键，数据库中的其他键不受影响
+    pub fn clear(&mut self) -> Result<()> {
+        self.db.rem_prefix(&self.prefix).map(|_| ())
+    }
+
+    // 遍历命名空间中的普通键值对，返回的键不包括前缀
+    pub fn iter(&self) -> KeyValueDbIterator<'_> {
+        let mut iter = self.db.iter_prefix(&self.prefix);
+        iter.strip_len = self.prefix.len();
+        iter
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::{Duration, Instant};
+
+use crate::glob::glob_match;
+
+// buffer_writes 注册的一组键：写入这些键时不会立即 dump，而是最多每隔 flush_every 才 dump 一次
+pub(crate) struct WriteBuffer {
+    pub(crate) pattern: String,
+    pub(crate) flush_every: Duration,
+    pub(crate) last_flush: Instant,
+    // 自上次 dump 以来是否有匹配的键被写入
+    pub(crate) pending: bool,
+}
+
+impl WriteBuffer {
+    pub(crate) fn new(pattern: &str, flush_every: Duration) -> WriteBuffer {
+        WriteBuffer {
+            pattern: String::from(pattern),
+            flush_every,
+            last_flush: Instant::now(),
+            pending: false,
+        }
+    }
+
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        glob_match(&self.pattern, key)
+    }
+
+    pub(crate) fn is_due(&self) -> bool {
+        self.last_flush.elapsed() >= self.flush_every
+    }
+
+    // 数据库被 dump 之后调用，之前缓冲的写入都已经写入文件
+    pub(crate) fn flushed(&mut self) {
+        self.last_flush = Instant::now();
+        self.pending = false;
+    }
+}
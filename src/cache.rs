@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use crate::error::Result;
+use crate::keyvaluedb::KeyValueDb;
+
+// 通用的缓存接口，值是任意的字节数据，例如 HTTP 响应或者编译产物。
+// ttl 为 None 的条目不会过期，但仍然可能因为缓存的大小限制而被淘汰。
+pub trait Cache {
+    // 返回 key 对应的数据，不存在、已经过期或者已经被淘汰时返回 None
+    fn get(&self, key: &str) -> Option<Vec<u8>>;
+
+    // 写入 key 对应的数据，覆盖原来的数据
+    fn put(&mut self, key: &str, value: &[u8], ttl: Option<Duration>) -> Result<()>;
+
+    // 删除 key 对应的数据，不存在时返回 false
+    fn invalidate(&mut self, key: &str) -> Result<bool>;
+}
+
+// 使用 KeyValueDb 实现的 Cache，整个数据库都用作缓存。
+// 可以限制条目的数量（with_max_entries）和所有条目的 size_of 之和（with_max_bytes），
+// 每次 put 之后如果超过了限制，先删除已经过期的条目，再按写入时间从早到晚淘汰条目，直到满足限制。
+// 一次 put 最多触发两次 dump（写入和淘汰各一次），是否写入文件由数据库的存储策略决定。
+pub struct KeyValueDbCache {
+    db: KeyValueDb,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+impl KeyValueDbCache {
+    pub fn new(db: KeyValueDb) -> KeyValueDbCache {
+        KeyValueDbCache {
+            db,
+            max_entries: None,
+            max_bytes: None,
+        }
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> KeyValueDbCache {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> KeyValueDbCache {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    // 底层的数据库，例如用于 dump
+    pub fn db(&mut self) -> &mut KeyValueDb {
+        &mut self.db
+    }
+
+    pub fn into_inner(self) -> KeyValueDb {
+        self.db
+    }
+
+    fn is_full(&self, entries: usize, bytes: usize) -> bool {
+        self.max_entries.is_some_and(|max| entries > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+
+    // 按写入时间从早到晚淘汰条目，直到满足大小限制
+    fn evict(&mut self) -> Result<()> {
+        let mut entries = self.db.total_keys();
+        let mut bytes = self.db.total_bytes();
+        if !self.is_full(entries, bytes) {
+            return Ok(());
+        }
+
+        self.db.purge_expired()?;
+        entries = self.db.total_keys();
+        bytes = self.db.total_bytes();
+
+        let mut keys: Vec<(SystemTime, String)> = self
+            .db
+            .get_all()
+            .into_iter()
+            .map(|key| {
+                let modified = match self.db.key_info(&key) {
+                    Some(meta) => meta.modified(),
+                    None => SystemTime::UNIX_EPOCH,
+                };
+                (modified, key)
+            })
+            .collect();
+        keys.sort();
+
+        let mut victims = HashSet::new();
+        for (_, key) in keys {
+            if !self.is_full(entries, bytes) {
+                break;
+            }
+            entries -= 1;
+            bytes -= self.db.size_of(&key).unwrap_or(0);
+            victims.insert(key);
+        }
+        if !victims.is_empty() {
+            self.db.rem_where("evict", |key| victims.contains(key))?;
+        }
+        Ok(())
+    }
+}
+
+impl Cache for KeyValueDbCache {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.db.get(key)
+    }
+
+    fn put(&mut self, key: &str, value: &[u8], ttl: Option<Duration>) -> Result<()> {
+        match ttl {
+            Some(ttl) => self.db.set_with_ttl(key, &value, ttl)?,
+            None => self.db.set(key, &value)?,
+        }
+        self.evict()
+    }
+
+    fn invalidate(&mut self, key: &str) -> Result<bool> {
+        self.db.rem(key)
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::keyvaluedb::KeyValueDb;
+
+type Validator = Box<dyn Fn(&dyn Any) -> std::result::Result<(), String>>;
+type Listener = Box<dyn Fn(&dyn Any)>;
+
+// 在 KeyValueDb 上保存应用程序配置，每个配置段（section）是一个以段名为键的类型化的值，例如：
+//
+//     let mut store = ConfigStore::new(db);
+//     store.validate("smtp", |smtp: &SmtpConfig| match smtp.port {
+//         0 => Err(String::from("port must not be 0")),
+//         _ => Ok(()),
+//     });
+//     store.on_change("smtp", |smtp: &SmtpConfig| reconnect(smtp));
+//     let smtp: SmtpConfig = store.section("smtp")?;
+//     store.update("smtp", |smtp: &mut SmtpConfig| smtp.port = 465)?;
+//
+// 配置段不存在时使用 T::default()。通过 set 和 update 写入的值会先经过该段所有的校验函数，
+// 写入成功后依次调用该段所有的监听函数。校验函数和监听函数必须使用与写入时相同的类型 T，类型不同的函数会被忽略。
+pub struct ConfigStore {
+    db: KeyValueDb,
+    validators: HashMap<String, Vec<Validator>>,
+    listeners: HashMap<String, Vec<Listener>>,
+}
+
+impl ConfigStore {
+    pub fn new(db: KeyValueDb) -> ConfigStore {
+        ConfigStore {
+            db,
+            validators: HashMap::new(),
+            listeners: HashMap::new(),
+        }
+    }
+
+    // 注册一个配置段并返回它当前的值。配置段不存在时写入 T::default()，之后可以在数据库文件中看到并修改它。
+    // 配置段的值无法反序列化为 T 时返回 Serialization 错误。
+    pub fn section<T>(&mut self, name: &str) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        if self.db.exists(name) {
+            return self.get(name);
+        }
+        let value = T::default();
+        self.db.set(name, &value)?;
+        Ok(value)
+    }
+
+    // 返回配置段当前的值，配置段不存在时返回 T::default()，值无法反序列化为 T 时返回 Serialization 错误
+    pub fn get<T>(&self, name: &str) -> Result<T>
+    where
+        T: DeserializeOwned + Default,
+    {
+        if !self.db.exists(name) {
+            return Ok(T::default());
+        }
+        match self.db.get(name) {
+            Some(value) => Ok(value),
+            None => Err(Error::new(ErrorCode::Serialization(format!(
+                "Cannot deserialize config section '{}'",
+                name
+            )))),
+        }
+    }
+
+    // 校验并写入配置段，任何一个校验函数返回错误时不会写入，返回 InvalidArgument 错误
+    pub fn set<T>(&mut self, name: &str, value: &T) -> Result<()>
+    where
+        T: Serialize + 'static,
+    {
+        if let Some(validators) = self.validators.get(name) {
+            for validator in validators {
+                if let Err(err) = validator(value) {
+                    return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                        "Invalid config section '{}': {}",
+                        name, err
+                    ))));
+                }
+            }
+        }
+
+        self.db.set(name, value)?;
+        if let Some(listeners) = self.listeners.get(name) {
+            for listener in listeners {
+                listener(value);
+            }
+        }
+        Ok(())
+    }
+
+    // 读取配置段的当前值，交给 f 修改后像 set 一样校验并写入，返回写入的值
+    pub fn update<T, F>(&mut self, name: &str, f: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default + 'static,
+        F: FnOnce(&mut T),
+    {
+        let mut value: T = self.get(name)?;
+        f(&mut value);
+        self.set(name, &value)?;
+        Ok(value)
+    }
+
+    // 为配置段注册一个校验函数，返回 Err 时拒绝写入，错误信息会包含在返回的错误中
+    pub fn validate<T, F>(&mut self, name: &str, f: F)
+    where
+        T: 'static,
+        F: Fn(&T) -> std::result::Result<(), String> + 'static,
+    {
+        let validator: Validator = Box::new(move |value| match value.downcast_ref::<T>() {
+            Some(value) => f(value),
+            None => Ok(()),
+        });
+        self.validators
+            .entry(String::from(name))
+            .or_default()
+            .push(validator);
+    }
+
+    // 为配置段注册一个监听函数，配置段通过 set 或者 update 写入成功后调用
+    pub fn on_change<T, F>(&mut self, name: &str, f: F)
+    where
+        T: 'static,
+        F: Fn(&T) + 'static,
+    {
+        let listener: Listener = Box::new(move |value| {
+            if let Some(value) = value.downcast_ref::<T>() {
+                f(value);
+            }
+        });
+        self.listeners
+            .entry(String::from(name))
+            .or_default()
+            .push(listener);
+    }
+
+    // 底层的数据库，例如用于 dump
+    pub fn db(&mut self) -> &mut KeyValueDb {
+        &mut self.db
+    }
+
+    pub fn into_inner(self) -> KeyValueDb {
+        self.db
+    }
+}
@@ -0,0 +1,537 @@
+// 基于锁的租约实现的多进程协调工具
+#[cfg(feature = "json")]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(feature = "json")]
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::background::{BackgroundTask, StopSignal};
+use crate::error::{Error, ErrorCode, Result};
+use crate::keyvaluedb::KeyValueDb;
+#[cfg(feature = "json")]
+use crate::protocol::{self, Command, Request, Response};
+
+// 保存租约的地方，对应 KeyValueDb 的 lock、renew_lock 和 unlock
+pub trait LeaseBackend: Send + 'static {
+    // 获取租约，返回隔离令牌，租约已经被其他人持有时返回 None
+    fn acquire(&mut self, name: &str, ttl: Duration) -> Result<Option<u64>>;
+
+    // 延长 token 对应的租约，租约已经失效时返回 false
+    fn renew(&mut self, name: &str, token: u64, ttl: Duration) -> Result<bool>;
+
+    // 释放 token 对应的租约，租约已经失效时返回 false
+    fn release(&mut self, name: &str, token: u64) -> Result<bool>;
+
+    // acquire_wait 的默认超时时间，默认为 30 秒
+    fn wait_timeout(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+
+    // 等待租约被释放或者过期后获取它，返回隔离令牌。等待期间以逐渐变长的间隔（最长 200 毫秒）重试 acquire，
+    // 超过 timeout 仍然没有获取到时返回 Timeout 错误
+    fn acquire_within(&mut self, name: &str, ttl: Duration, timeout: Duration) -> Result<u64> {
+        let deadline = Instant::now() + timeout;
+        let mut pause = Duration::from_millis(10);
+        loop {
+            if let Some(token) = self.acquire(name, ttl)? {
+                return Ok(token);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(ErrorCode::Timeout(format!(
+                    "Lock '{}' was not released within {:?}",
+                    name, timeout
+                )))
+                .with_context("acquire_within", name));
+            }
+            thread::sleep(pause.min(deadline - now));
+            pause = (pause * 2).min(Duration::from_millis(200));
+        }
+    }
+
+    // 与 acquire_within 相同，超时时间为 wait_timeout
+    fn acquire_wait(&mut self, name: &str, ttl: Duration) -> Result<u64> {
+        let timeout = self.wait_timeout();
+        self.acquire_within(name, ttl, timeout)
+    }
+}
+
+// 同一个进程中共享的数据库，例如多个线程竞争同一个角色
+impl LeaseBackend for Arc<Mutex<KeyValueDb>> {
+    fn acquire(&mut self, name: &str, ttl: Duration) -> Result<Option<u64>> {
+        self.lock().unwrap().lock(name, ttl)
+    }
+
+    fn renew(&mut self, name: &str, token: u64, ttl: Duration) -> Result<bool> {
+        self.lock().unwrap().renew_lock(name, token, ttl)
+    }
+
+    fn release(&mut self, name: &str, token: u64) -> Result<bool> {
+        self.lock().unwrap().unlock(name, token)
+    }
+
+    // 数据库的 with_lock_wait_timeout 设置的超时时间。等待期间不会一直持有数据库的锁
+    fn wait_timeout(&self) -> Duration {
+        self.lock().unwrap().lock_wait_timeout()
+    }
+}
+
+// 通过网络协议使用 kvstore 服务端上的锁，用于多个进程或者多台机器之间的协调。
+// 服务端同一时间只处理一个连接，因此每个请求都使用一个新的连接，发送完就关闭。
+// 每个请求（包括连接、发送和读取响应）最多等待 with_timeout 设置的时间，获取和延长租约时
+// 还不会超过租约长度的三分之一，使没有响应的服务端不会让 leader 在租约到期之后仍然阻塞在请求中。
+#[cfg(feature = "json")]
+pub struct ServerLeaseBackend {
+    addr: String,
+    timeout: Duration,
+}
+
+#[cfg(feature = "json")]
+impl ServerLeaseBackend {
+    // addr 是服务端的地址，例如 "127.0.0.1:4567"
+    pub fn new(addr: &str) -> ServerLeaseBackend {
+        ServerLeaseBackend {
+            addr: String::from(addr),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    // 每个请求最多等待的时间，默认为 1 秒
+    pub fn with_timeout(mut self, timeout: Duration) -> ServerLeaseBackend {
+        self.timeout = timeout;
+        self
+    }
+
+    // 获取和延长租约的请求最多等待的时间
+    fn timeout_for(&self, ttl: Duration) -> Duration {
+        self.timeout.min(ttl / 3)
+    }
+
+    // 在 timeout 之内完成整个请求，超时时返回 TimedOut 的 Io 错误
+    fn send(&self, command: Command, timeout: Duration) -> Result<Response> {
+        let io_error = |err: io::Error| Error::new(ErrorCode::Io(err));
+        let deadline = Instant::now() + timeout;
+        let remaining = || match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(remaining),
+            _ => Err(io_error(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("No response from {} within {:?}", self.addr, timeout),
+            ))),
+        };
+
+        let mut stream = self.connect(remaining()?).map_err(io_error)?;
+        let request = protocol::encode(&Request::new(command))?;
+        stream
+            .set_write_timeout(Some(remaining()?))
+            .map_err(io_error)?;
+        stream.write_all(request.as_bytes()).map_err(io_error)?;
+        stream
+            .set_read_timeout(Some(remaining()?))
+            .map_err(io_error)?;
+
+        let mut line = String::new();
+        if BufReader::new(stream)
+            .read_line(&mut line)
+            .map_err(io_error)?
+            == 0
+        {
+            return Err(io_error(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "Server closed the connection",
+            )));
+        }
+        match protocol::decode(&line)? {
+            Response::Error(err) => Err(io_error(io::Error::other(err))),
+            response => Ok(response),
+        }
+    }
+
+    // 依次尝试 addr 解析出的每一个地址
+    fn connect(&self, timeout: Duration) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Could not resolve {}", self.addr),
+            )
+        }))
+    }
+}
+
+#[cfg(feature = "json")]
+fn unexpected(response: Response) -> Error {
+    Error::new(ErrorCode::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected response {:?}", response),
+    )))
+}
+
+#[cfg(feature = "json")]
+impl LeaseBackend for ServerLeaseBackend {
+    fn acquire(&mut self, name: &str, ttl: Duration) -> Result<Option<u64>> {
+        let command = Command::Lock {
+            name: String::from(name),
+            ttl_ms: ttl.as_millis() as u64,
+        };
+        match self.send(command, self.timeout_for(ttl))? {
+            Response::Integer(token) => Ok(Some(token as u64)),
+            Response::Nil => Ok(None),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn renew(&mut self, name: &str, token: u64, ttl: Duration) -> Result<bool> {
+        let command = Command::Renew {
+            name: String::from(name),
+            token,
+            ttl_ms: ttl.as_millis() as u64,
+        };
+        match self.send(command, self.timeout_for(ttl))? {
+            Response::Integer(renewed) => Ok(renewed == 1),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn release(&mut self, name: &str, token: u64) -> Result<bool> {
+        let command = Command::Unlock {
+            name: String::from(name),
+            token,
+        };
+        match self.send(command, self.timeout)? {
+            Response::Integer(released) => Ok(released == 1),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+type GainCallback = Box<dyn FnMut(u64) + Send>;
+type LossCallback = Box<dyn FnMut() + Send>;
+
+// 在后台线程中竞选名为 name 的角色，同一时间最多只有一个参与者是 leader，例如：
+//
+//     let elector = LeaderElector::new(ServerLeaseBackend::new("127.0.0.1:4567"), "scheduler")
+//         .with_lease(Duration::from_secs(10))?
+//         .on_gain(|token| println!("Became the leader with token {}", token))
+//         .on_loss(|| println!("No longer the leader"))
+//         .start();
+//
+// 不是 leader 时每隔 lease / 3 尝试获取租约，成为 leader 之后以同样的间隔延长租约。
+// 延长失败（例如租约因为进程暂停而过期并被其他人获取）或者无法联系后端直到租约到期时，
+// 调用 on_loss 并重新开始竞选。on_gain 收到的隔离令牌可以用于拒绝旧的 leader 的写入。
+// 注意 on_loss 只能在发现失去租约之后调用，在此之前旧的 leader 仍然可能以为自己是 leader。
+pub struct LeaderElector<B: LeaseBackend> {
+    backend: B,
+    name: String,
+    lease: Duration,
+    on_gain: GainCallback,
+    on_loss: LossCallback,
+}
+
+impl<B: LeaseBackend> LeaderElector<B> {
+    pub fn new(backend: B, name: &str) -> LeaderElector<B> {
+        LeaderElector {
+            backend,
+            name: String::from(name),
+            lease: Duration::from_secs(10),
+            on_gain: Box::new(|_| {}),
+            on_loss: Box::new(|| {}),
+        }
+    }
+
+    // 租约的长度，默认为 10 秒。lease 为 0 时返回 InvalidArgument 错误
+    pub fn with_lease(mut self, lease: Duration) -> Result<LeaderElector<B>> {
+        if lease.is_zero() {
+            return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                "The lease of '{}' must be longer than 0",
+                self.name
+            )))
+            .with_op("with_lease"));
+        }
+        self.lease = lease;
+        Ok(self)
+    }
+
+    // 成为 leader 时在后台线程中调用，参数是隔离令牌
+    pub fn on_gain<F>(mut self, f: F) -> LeaderElector<B>
+    where
+        F: FnMut(u64) + Send + 'static,
+    {
+        self.on_gain = Box::new(f);
+        self
+    }
+
+    // 不再是 leader 时在后台线程中调用，包括 stop 时主动放弃
+    pub fn on_loss<F>(mut self, f: F) -> LeaderElector<B>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.on_loss = Box::new(f);
+        self
+    }
+
+    // 启动后台线程开始竞选
+    pub fn start(self) -> LeaderElectorHandle {
+        let token = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&token);
+        let task = BackgroundTask::spawn(move |stopped| self.run(shared, stopped));
+        LeaderElectorHandle { task, token }
+    }
+
+    fn run(mut self, shared: Arc<Mutex<Option<u64>>>, stopped: StopSignal) {
+        let interval = self.lease / 3;
+        // 当前持有的令牌和确定持有租约的最晚时间
+        let mut held: Option<(u64, Instant)> = None;
+        loop {
+            let started = Instant::now();
+            held = match held {
+                None => match self.backend.acquire(&self.name, self.lease) {
+                    Ok(Some(token)) => {
+                        *shared.lock().unwrap() = Some(token);
+                        (self.on_gain)(token);
+                        Some((token, started + self.lease))
+                    }
+                    _ => None,
+                },
+                Some((token, held_until)) => {
+                    match self.backend.renew(&self.name, token, self.lease) {
+                        Ok(true) => Some((token, started + self.lease)),
+                        // 无法联系后端时，在租约到期之前仍然是 leader
+                        Err(_) if Instant::now() < held_until => Some((token, held_until)),
+                        _ => {
+                            *shared.lock().unwrap() = None;
+                            (self.on_loss)();
+                            None
+                        }
+                    }
+                }
+            };
+
+            if stopped.wait(interval) {
+                break;
+            }
+        }
+
+        if let Some((token, _)) = held {
+            *shared.lock().unwrap() = None;
+            let _ = self.backend.release(&self.name, token);
+            (self.on_loss)();
+        }
+    }
+}
+
+// 正在运行的 LeaderElector，被 drop 时停止竞选，如果是 leader 则释放租约
+pub struct LeaderElectorHandle {
+    task: BackgroundTask,
+    token: Arc<Mutex<Option<u64>>>,
+}
+
+impl LeaderElectorHandle {
+    // 当前是否是 leader
+    pub fn is_leader(&self) -> bool {
+        self.token.lock().unwrap().is_some()
+    }
+
+    // 是 leader 时返回隔离令牌
+    pub fn token(&self) -> Option<u64> {
+        *self.token.lock().unwrap()
+    }
+
+    // 停止竞选，等待后台线程释放租约并调用 on_loss
+    pub fn stop(mut self) {
+        self.task.stop();
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+    use crate::SerializationMethod;
+    use std::mem;
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+
+    fn is_type<T>(result: Result<T>, expected: ErrorType) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(err) => mem::discriminant(&err.get_type()) == mem::discriminant(&expected),
+        }
+    }
+
+    #[test]
+    fn zero_lease_is_rejected() {
+        let db = KeyValueDb::in_memory(SerializationMethod::Json);
+        let elector = LeaderElector::new(Arc::new(Mutex::new(db)), "leader");
+        assert!(is_type(
+            elector.with_lease(Duration::ZERO),
+            ErrorType::InvalidArgument
+        ));
+    }
+
+    #[test]
+    fn unresponsive_server_times_out_within_the_lease() {
+        // 接受连接但从不响应的服务端
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut backend = ServerLeaseBackend::new(&addr).with_timeout(Duration::from_secs(30));
+
+        let started = Instant::now();
+        let result = backend.acquire("leader", Duration::from_millis(300));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(is_type(result, ErrorType::Io));
+    }
+
+    #[test]
+    fn acquire_within_waits_for_the_lock() {
+        let db = KeyValueDb::in_memory(SerializationMethod::Json)
+            .with_lock_wait_timeout(Duration::from_millis(20));
+        let mut backend = Arc::new(Mutex::new(db));
+        let token = backend
+            .acquire("leader", Duration::from_millis(100))
+            .unwrap()
+            .unwrap();
+
+        assert!(is_type(
+            backend.acquire_wait("leader", Duration::from_secs(1)),
+            ErrorType::Timeout
+        ));
+        // 租约过期之后可以获取到
+        let next = backend
+            .acquire_within("leader", Duration::from_secs(1), Duration::from_secs(5))
+            .unwrap();
+        assert!(next > token);
+    }
+
+    const LEASE: Duration = Duration::from_millis(150);
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Gain(u64),
+        Loss,
+    }
+
+    fn watch<B: LeaseBackend>(elector: LeaderElector<B>) -> (LeaderElectorHandle, Receiver<Event>) {
+        let (gained, events) = mpsc::channel();
+        let lost = gained.clone();
+        let handle = elector
+            .on_gain(move |token| gained.send(Event::Gain(token)).unwrap())
+            .on_loss(move || lost.send(Event::Loss).unwrap())
+            .start();
+        (handle, events)
+    }
+
+    // renew 的结果由测试决定的后端
+    #[derive(Clone, Default)]
+    struct ScriptedBackend(Arc<Mutex<Script>>);
+
+    #[derive(Default)]
+    struct Script {
+        next_token: u64,
+        // 为 None 时 renew 返回 Io 错误，模拟无法联系后端
+        renew: Option<bool>,
+        released: Vec<u64>,
+    }
+
+    impl LeaseBackend for ScriptedBackend {
+        fn acquire(&mut self, _name: &str, _ttl: Duration) -> Result<Option<u64>> {
+            let mut script = self.0.lock().unwrap();
+            script.next_token += 1;
+            script.renew = Some(true);
+            Ok(Some(script.next_token))
+        }
+
+        fn renew(&mut self, _name: &str, _token: u64, _ttl: Duration) -> Result<bool> {
+            match self.0.lock().unwrap().renew {
+                Some(renewed) => Ok(renewed),
+                None => Err(Error::new(ErrorCode::Io(io::Error::from(
+                    io::ErrorKind::ConnectionRefused,
+                )))),
+            }
+        }
+
+        fn release(&mut self, _name: &str, token: u64) -> Result<bool> {
+            self.0.lock().unwrap().released.push(token);
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn failed_renew_loses_leadership_and_campaigns_again() {
+        let backend = ScriptedBackend::default();
+        let elector = LeaderElector::new(backend.clone(), "leader")
+            .with_lease(LEASE)
+            .unwrap();
+        let (handle, events) = watch(elector);
+
+        assert_eq!(events.recv_timeout(WAIT).unwrap(), Event::Gain(1));
+        assert_eq!(handle.token(), Some(1));
+
+        backend.0.lock().unwrap().renew = Some(false);
+        assert_eq!(events.recv_timeout(WAIT).unwrap(), Event::Loss);
+        // 失去租约之后重新竞选，新的令牌比旧的大
+        assert_eq!(events.recv_timeout(WAIT).unwrap(), Event::Gain(2));
+        handle.stop();
+        assert_eq!(events.recv_timeout(WAIT).unwrap(), Event::Loss);
+        assert_eq!(backend.0.lock().unwrap().released, vec![2]);
+    }
+
+    #[test]
+    fn unreachable_backend_keeps_leadership_until_the_lease_ends() {
+        let backend = ScriptedBackend::default();
+        let elector = LeaderElector::new(backend.clone(), "leader")
+            .with_lease(LEASE)
+            .unwrap();
+        let (handle, events) = watch(elector);
+        assert_eq!(events.recv_timeout(WAIT).unwrap(), Event::Gain(1));
+
+        backend.0.lock().unwrap().renew = None;
+        let failing_since = Instant::now();
+        assert_eq!(events.recv_timeout(WAIT).unwrap(), Event::Loss);
+        // 最后一次成功的延长大约在 failing_since 之前 lease / 3 以内，之后还要等到这次租约到期
+        assert!(failing_since.elapsed() >= LEASE / 2);
+        assert!(!handle.is_leader());
+    }
+
+    #[test]
+    fn only_one_elector_leads_and_stop_hands_over() {
+        let db = Arc::new(Mutex::new(KeyValueDb::in_memory(SerializationMethod::Json)));
+        let first = LeaderElector::new(Arc::clone(&db), "leader")
+            .with_lease(LEASE)
+            .unwrap();
+        let (first, first_events) = watch(first);
+        let Event::Gain(first_token) = first_events.recv_timeout(WAIT).unwrap() else {
+            panic!("expected the first elector to lead");
+        };
+
+        let second = LeaderElector::new(Arc::clone(&db), "leader")
+            .with_lease(LEASE)
+            .unwrap();
+        let (second, second_events) = watch(second);
+        assert!(second_events.recv_timeout(LEASE * 2).is_err());
+        assert!(!second.is_leader());
+
+        first.stop();
+        assert_eq!(first_events.recv_timeout(WAIT).unwrap(), Event::Loss);
+        let Event::Gain(second_token) = second_events.recv_timeout(WAIT).unwrap() else {
+            panic!("expected the second elector to lead");
+        };
+        // 隔离令牌递增，旧的 leader 的令牌可以被拒绝
+        assert!(second_token > first_token);
+        assert!(!db
+            .lock()
+            .unwrap()
+            .renew_lock("leader", first_token, LEASE)
+            .unwrap());
+        drop(second);
+        assert_eq!(second_events.recv_timeout(WAIT).unwrap(), Event::Loss);
+    }
+}
@@ -0,0 +1,31 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::keyvaluedb::KeyValueDb;
+
+// KeyValueDb::database 返回的逻辑数据库，通过 Deref 可以使用 KeyValueDb 的所有方法，
+// 这些方法只会读写这个逻辑数据库中的键，dump 和 snapshot 则会保存所有的逻辑数据库。
+// 被 drop 时切换回调用 database 之前选择的数据库。
+pub struct KeyValueDbDatabase<'a> {
+    pub(crate) db: &'a mut KeyValueDb,
+    pub(crate) previous: usize,
+}
+
+impl<'a> Deref for KeyValueDbDatabase<'a> {
+    type Target = KeyValueDb;
+
+    fn deref(&self) -> &KeyValueDb {
+        self.db
+    }
+}
+
+impl<'a> DerefMut for KeyValueDbDatabase<'a> {
+    fn deref_mut(&mut self) -> &mut KeyValueDb {
+        self.db
+    }
+}
+
+impl<'a> Drop for KeyValueDbDatabase<'a> {
+    fn drop(&mut self) {
+        self.db.select(self.previous);
+    }
+}
@@ -0,0 +1,131 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::keyvaluedb::KeyValueDb;
+
+// 类似于 HashMap::entry，表示数据库中的一个键，它可能已经有值（Occupied），也可能没有（Vacant）。
+// 只有普通的键值对才会被视为 Occupied，列表不属于 entry 的范围，向同名的列表写入值会像 set 一样覆盖该列表。
+// 每次真正修改数据时只会触发一次 dump。
+pub enum KeyValueDbEntry<'a> {
+    Occupied(KeyValueDbOccupiedEntry<'a>),
+    Vacant(KeyValueDbVacantEntry<'a>),
+}
+
+pub struct KeyValueDbOccupiedEntry<'a> {
+    pub(crate) db: &'a mut KeyValueDb,
+    pub(crate) key: String,
+}
+
+pub struct KeyValueDbVacantEntry<'a> {
+    pub(crate) db: &'a mut KeyValueDb,
+    pub(crate) key: String,
+}
+
+impl<'a> KeyValueDbEntry<'a> {
+    // 返回该 entry 对应的键
+    pub fn key(&self) -> &str {
+        match self {
+            KeyValueDbEntry::Occupied(entry) => entry.key(),
+            KeyValueDbEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    // 如果键不存在，则写入 default，返回一个 Occupied entry 用于读取当前值
+    pub fn or_insert<V>(self, default: &V) -> Result<KeyValueDbOccupiedEntry<'a>>
+    where
+        V: Serialize,
+    {
+        match self {
+            KeyValueDbEntry::Occupied(entry) => Ok(entry),
+            KeyValueDbEntry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    // 如果键不存在，则写入 f 的返回值，f 只有在需要写入时才会被调用
+    pub fn or_insert_with<V, F>(self, f: F) -> Result<KeyValueDbOccupiedEntry<'a>>
+    where
+        V: Serialize,
+        F: FnOnce() -> V,
+    {
+        match self {
+            KeyValueDbEntry::Occupied(entry) => Ok(entry),
+            KeyValueDbEntry::Vacant(entry) => entry.insert(&f()),
+        }
+    }
+
+    // 如果键存在，则将当前值反序列化为 V，交给 f 修改后写回数据库；键不存在时什么都不做。
+    // 如果当前值无法反序列化为 V，返回一个 Serialization 错误。
+    pub fn and_modify<V, F>(self, f: F) -> Result<KeyValueDbEntry<'a>>
+    where
+        V: Serialize + DeserializeOwned,
+        F: FnOnce(&mut V),
+    {
+        match self {
+            KeyValueDbEntry::Occupied(mut entry) => {
+                let mut value = match entry.get::<V>() {
+                    Some(value) => value,
+                    None => {
+                        return Err(Error::new(ErrorCode::Serialization(format!(
+                            "Cannot deserialize the value of '{}'",
+                            entry.key
+                        ))))
+                    }
+                };
+                f(&mut value);
+                entry.insert(&value)?;
+                Ok(KeyValueDbEntry::Occupied(entry))
+            }
+            KeyValueDbEntry::Vacant(entry) => Ok(KeyValueDbEntry::Vacant(entry)),
+        }
+    }
+}
+
+impl<'a> KeyValueDbOccupiedEntry<'a> {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    // 将当前值反序列化为 V
+    pub fn get<V>(&self) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        self.db.get(&self.key)
+    }
+
+    // 返回当前值序列化后的原始字节数据
+    pub fn get_raw(&self) -> &[u8] {
+        self.db.get_raw(&self.key).unwrap()
+    }
+
+    // 用新的值覆盖当前值
+    pub fn insert<V>(&mut self, value: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.db.set(&self.key, value)
+    }
+
+    // 从数据库中删除该键
+    pub fn remove(self) -> Result<()> {
+        self.db.rem(&self.key).map(|_| ())
+    }
+}
+
+impl<'a> KeyValueDbVacantEntry<'a> {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    // 写入一个值，返回对应的 Occupied entry
+    pub fn insert<V>(self, value: &V) -> Result<KeyValueDbOccupiedEntry<'a>>
+    where
+        V: Serialize,
+    {
+        self.db.set(&self.key, value)?;
+        Ok(KeyValueDbOccupiedEntry {
+            db: self.db,
+            key: self.key,
+        })
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::result;
 
 // ErrorType 是一个枚举类型，它表示了可能出现的错误类型，包括 Io 和 Serialization。其中，Io 表示 I/O 错误，例如文件读写错误等，Serialization 表示序列化错误，例如在使用 JSON 或者 bincode 时遇到的错误。
@@ -8,13 +9,39 @@ use std::result;
 pub enum ErrorType {
     Io,
     Serialization,
+    // 键或者值超过了 with_max_key_len 或 with_max_value_bytes 设置的大小限制
+    LimitExceeded,
+    // 键存在，但它的值无法反序列化为请求的类型，例如数据损坏或者类型不一致
+    Deserialization,
+    // 读取时请求的类型与写入时记录的类型不一致，见 with_type_tags
+    TypeMismatch,
+    // 列表不存在或者已经过期
+    ListNotFound,
+    // 键不存在或者已经过期
+    KeyNotFound,
+    // 数据库是用 load_read_only 打开的，不允许修改
+    ReadOnly,
+    // 写入会使数据库超过 with_max_memory 设置的内存上限，并且无法通过淘汰其他的键腾出空间
+    OutOfMemoryBudget,
+    // 值被 validate_with 注册的检查函数拒绝
+    ValidationFailed,
+    // 参数不合法，例如锁的租期为 0 或者 scan 的游标无法解析
+    InvalidArgument,
+    // 在超时之前没有完成等待，例如 acquire_within 在超时之前没有获取到锁
+    Timeout,
+    // 键已经存在，例如 merge_from 使用 MergeStrategy::ErrorOnConflict 时两个数据库中都有同一个键
+    KeyExists,
 }
 
 // Error 结构体，其中包含一个 err_code 字段，类型为 ErrorCode 枚举类型。 
 // ErrorCode 枚举类型包含了不同的错误码，例如 Io 或 Serialization。
 // 通过这种方式可以在代码中捕获和处理不同类型的错误。
+// key、op 和 path 记录出错的键、操作（例如 "set"、"lcreate"）和文件路径，没有记录时为 None。
 pub struct Error {
     err_code: ErrorCode,
+    key: Option<String>,
+    op: Option<&'static str>,
+    path: Option<PathBuf>,
 }
 
 // Result 类型的别名
@@ -23,7 +50,53 @@ pub type Result<T> = result::Result<T, Error>;
 impl Error {
     // 创建一个新的Error实例
     pub(crate) fn new(err_code: ErrorCode) -> Error {
-        Error { err_code }
+        Error {
+            err_code,
+            key: None,
+            op: None,
+            path: None,
+        }
+    }
+
+    // 以下三个方法记录出错的操作、键和文件路径。已经记录过的不会被覆盖，
+    // 这样错误在向外传递时保留的是最先记录的、最具体的上下文
+    pub(crate) fn with_op(mut self, op: &'static str) -> Error {
+        self.op.get_or_insert(op);
+        self
+    }
+
+    pub(crate) fn with_key(mut self, key: &str) -> Error {
+        if self.key.is_none() {
+            self.key = Some(String::from(key));
+        }
+        self
+    }
+
+    pub(crate) fn with_path(mut self, path: &Path) -> Error {
+        if self.path.is_none() {
+            self.path = Some(path.to_path_buf());
+        }
+        self
+    }
+
+    // 同时记录操作和键
+    pub(crate) fn with_context(self, op: &'static str, key: &str) -> Error {
+        self.with_op(op).with_key(key)
+    }
+
+    // 出错的键，例如不存在的键或者超过长度限制的键
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    // 出错的操作，与方法名相同，例如 "set"、"rename"
+    pub fn op(&self) -> Option<&str> {
+        self.op
+    }
+
+    // 出错时读写的文件，例如 load 或者 dump 的数据库文件
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
     }
 
     // 通过匹配err_code字段的值来确定错误类型，
@@ -33,37 +106,128 @@ impl Error {
         match self.err_code {
             ErrorCode::Io(_) => ErrorType::Io,
             ErrorCode::Serialization(_) => ErrorType::Serialization,
+            ErrorCode::LimitExceeded(_) => ErrorType::LimitExceeded,
+            ErrorCode::Deserialization(_) => ErrorType::Deserialization,
+            ErrorCode::TypeMismatch(_) => ErrorType::TypeMismatch,
+            ErrorCode::ListNotFound(_) => ErrorType::ListNotFound,
+            ErrorCode::KeyNotFound(_) => ErrorType::KeyNotFound,
+            ErrorCode::ReadOnly(_) => ErrorType::ReadOnly,
+            ErrorCode::OutOfMemoryBudget(_) => ErrorType::OutOfMemoryBudget,
+            ErrorCode::ValidationFailed(_) => ErrorType::ValidationFailed,
+            ErrorCode::InvalidArgument(_) => ErrorType::InvalidArgument,
+            ErrorCode::Timeout(_) => ErrorType::Timeout,
+            ErrorCode::KeyExists(_) => ErrorType::KeyExists,
         }
     }
 }
 
-// 通过匹配错误代码（err_code）来确定错误类型
+// 通过匹配错误代码（err_code）来确定错误类型，记录了文件路径时在错误信息前加上路径
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref path) = self.path {
+            write!(f, "{}: ", path.display())?;
+        }
         match self.err_code {
             ErrorCode::Io(ref err) => fmt::Display::fmt(err, f),
             ErrorCode::Serialization(ref err_str) => f.write_str(err_str),
+            ErrorCode::LimitExceeded(ref err_str) => f.write_str(err_str),
+            ErrorCode::Deserialization(ref err_str) => f.write_str(err_str),
+            ErrorCode::TypeMismatch(ref err_str) => f.write_str(err_str),
+            ErrorCode::ListNotFound(ref err_str) => f.write_str(err_str),
+            ErrorCode::KeyNotFound(ref err_str) => f.write_str(err_str),
+            ErrorCode::ReadOnly(ref err_str) => f.write_str(err_str),
+            ErrorCode::OutOfMemoryBudget(ref err_str) => f.write_str(err_str),
+            ErrorCode::ValidationFailed(ref err_str) => f.write_str(err_str),
+            ErrorCode::InvalidArgument(ref err_str) => f.write_str(err_str),
+            ErrorCode::Timeout(ref err_str) => f.write_str(err_str),
+            ErrorCode::KeyExists(ref err_str) => f.write_str(err_str),
         }
     }
 }
 
 // 当需要在调试信息中打印 Error 类型时，该 trait 方法会被调用。
-// 它会将错误类型转换为字符串并格式化为类似于 Error { msg: ... } 的字符串，其中 ... 为错误消息。
+// 它会将错误类型转换为字符串并格式化为类似于 Error { msg: ... } 的字符串，其中 ... 为错误消息，
+// 记录了操作、键或者文件路径时依次追加 op、key 和 path。
 impl fmt::Debug for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut context = String::new();
+        if let Some(op) = self.op {
+            context.push_str(&format!(", op: {}", op));
+        }
+        if let Some(ref key) = self.key {
+            context.push_str(&format!(", key: {:?}", key));
+        }
+        if let Some(ref path) = self.path {
+            context.push_str(&format!(", path: {}", path.display()));
+        }
         fmt.write_str(&format!(
-            "Error {{ msg: {} }}",
+            "Error {{ msg: {}{} }}",
             match self.err_code {
                 ErrorCode::Io(ref err) => err.to_string(),
                 ErrorCode::Serialization(ref err_str) => err_str.to_string(),
-            }
+                ErrorCode::LimitExceeded(ref err_str) => err_str.to_string(),
+                ErrorCode::Deserialization(ref err_str) => err_str.to_string(),
+                ErrorCode::TypeMismatch(ref err_str) => err_str.to_string(),
+                ErrorCode::ListNotFound(ref err_str) => err_str.to_string(),
+                ErrorCode::KeyNotFound(ref err_str) => err_str.to_string(),
+                ErrorCode::ReadOnly(ref err_str) => err_str.to_string(),
+                ErrorCode::OutOfMemoryBudget(ref err_str) => err_str.to_string(),
+                ErrorCode::ValidationFailed(ref err_str) => err_str.to_string(),
+                ErrorCode::InvalidArgument(ref err_str) => err_str.to_string(),
+                ErrorCode::Timeout(ref err_str) => err_str.to_string(),
+                ErrorCode::KeyExists(ref err_str) => err_str.to_string(),
+            },
+            context
         ))
     }
 }
 
 // 实现该 trait，可以为 Error 结构体提供更多的错误处理特性，
 // 比如使用 ? 运算符捕获错误、自定义错误类型等。
-impl std::error::Error for Error {}
+// source 返回底层的 io::Error，其他类型的错误没有底层错误。
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.err_code {
+            ErrorCode::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// 以下 From 的实现使应用代码在同一个函数中混用 KeyValueDb 和文件读写、序列化库时可以直接使用 ? 运算符
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::new(ErrorCode::Io(err))
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::new(ErrorCode::Serialization(err.to_string()))
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Error {
+        Error::new(ErrorCode::Serialization(err.to_string()))
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Error {
+        Error::new(ErrorCode::Serialization(err.to_string()))
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl From<serde_cbor::Error> for Error {
+    fn from(err: serde_cbor::Error) -> Error {
+        Error::new(ErrorCode::Serialization(err.to_string()))
+    }
+}
 
 // ErrorCode 和 ErrorType 都是用来表示错误类型的枚举类型，但是它们在设计上有不同的目的和用途。
 
@@ -76,4 +240,15 @@ impl std::error::Error for Error {}
 pub(crate) enum ErrorCode {
     Io(io::Error),
     Serialization(String),
+    LimitExceeded(String),
+    Deserialization(String),
+    TypeMismatch(String),
+    ListNotFound(String),
+    KeyNotFound(String),
+    ReadOnly(String),
+    OutOfMemoryBudget(String),
+    ValidationFailed(String),
+    InvalidArgument(String),
+    Timeout(String),
+    KeyExists(String),
 }
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::keyvaluedb::KeyValueDbEvictionPolicy;
+
+// 一个键最近一次被访问的时间（逻辑时钟）和被访问的次数
+#[derive(Debug, Clone, Copy, Default)]
+struct Access {
+    last: u64,
+    count: u64,
+}
+
+// with_max_memory 的设置和每个键的访问记录。访问记录只保存在内存中，
+// 使用 Mutex 而不是 RefCell，使只读的方法也可以记录访问，同时 KeyValueDb 仍然可以在线程之间共享。
+// 键的访问记录以 (逻辑数据库的编号, 键) 为索引，不同逻辑数据库中的同名键互不影响。
+pub(crate) struct Eviction {
+    pub(crate) max_bytes: usize,
+    pub(crate) policy: KeyValueDbEvictionPolicy,
+    clock: AtomicU64,
+    access: Mutex<HashMap<(usize, String), Access>>,
+}
+
+impl Eviction {
+    pub(crate) fn new(max_bytes: usize, policy: KeyValueDbEvictionPolicy) -> Eviction {
+        Eviction {
+            max_bytes,
+            policy,
+            clock: AtomicU64::new(0),
+            access: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 记录一次对键的读取或者写入
+    pub(crate) fn record(&self, db: usize, key: &str) {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut access = self.access.lock().unwrap();
+        let entry = access.entry((db, String::from(key))).or_default();
+        entry.last = now;
+        entry.count += 1;
+    }
+
+    // 按照淘汰的先后顺序排列 keys，没有访问记录的键最先被淘汰。
+    // 同时删除该逻辑数据库中不在 keys 里的键（已经被删除的键）的访问记录。
+    pub(crate) fn order(&self, db: usize, keys: Vec<String>) -> Vec<String> {
+        let mut access = self.access.lock().unwrap();
+        let live: HashSet<&str> = keys.iter().map(String::as_str).collect();
+        access.retain(|(index, key), _| *index != db || live.contains(key.as_str()));
+
+        let mut ranked: Vec<((u64, u64), &String)> = keys
+            .iter()
+            .map(|key| {
+                let record = access.get(&(db, key.clone())).copied().unwrap_or_default();
+                let rank = match self.policy {
+                    KeyValueDbEvictionPolicy::Lfu => (record.count, record.last),
+                    _ => (record.last, record.count),
+                };
+                (rank, key)
+            })
+            .collect();
+        ranked.sort();
+        ranked.into_iter().map(|(_, key)| key.clone()).collect()
+    }
+
+    // 删除被淘汰的键的访问记录
+    pub(crate) fn forget(&self, db: usize, key: &str) {
+        self.access.lock().unwrap().remove(&(db, String::from(key)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| String::from(*name)).collect()
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used_key_first() {
+        let eviction = Eviction::new(0, KeyValueDbEvictionPolicy::Lru);
+        eviction.record(0, "a");
+        eviction.record(0, "a");
+        eviction.record(0, "b");
+        eviction.record(0, "c");
+        eviction.record(0, "a");
+        assert_eq!(
+            eviction.order(0, keys(&["a", "b", "c", "new"])),
+            keys(&["new", "b", "c", "a"])
+        );
+    }
+
+    #[test]
+    fn lfu_evicts_the_least_frequently_used_key_first() {
+        let eviction = Eviction::new(0, KeyValueDbEvictionPolicy::Lfu);
+        for key in ["a", "a", "a", "b", "c", "c", "b"] {
+            eviction.record(0, key);
+        }
+        // 访问次数相同时先淘汰更早访问的键
+        eviction.record(0, "d");
+        eviction.record(0, "d");
+        assert_eq!(
+            eviction.order(0, keys(&["a", "b", "c", "d"])),
+            keys(&["c", "b", "d", "a"])
+        );
+    }
+
+    #[test]
+    fn access_is_tracked_per_database_and_forgotten() {
+        let eviction = Eviction::new(0, KeyValueDbEvictionPolicy::Lru);
+        eviction.record(0, "a");
+        eviction.record(1, "b");
+        eviction.record(0, "b");
+        eviction.record(1, "a");
+        assert_eq!(eviction.order(0, keys(&["a", "b"])), keys(&["a", "b"]));
+        assert_eq!(eviction.order(1, keys(&["a", "b"])), keys(&["b", "a"]));
+
+        eviction.forget(0, "a");
+        eviction.record(0, "c");
+        assert_eq!(
+            eviction.order(0, keys(&["a", "b", "c"])),
+            keys(&["a", "b", "c"])
+        );
+        // 不在 keys 中的键的记录被删除，重新出现时视为没有被访问过
+        eviction.order(1, keys(&["a"]));
+        assert_eq!(eviction.order(1, keys(&["a", "b"])), keys(&["b", "a"]));
+    }
+}
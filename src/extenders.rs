@@ -1,3 +1,4 @@
+use crate::error::Result;
 use crate::keyvaluedb::KeyValueDb;
 use serde::Serialize;
 
@@ -8,21 +9,21 @@ pub struct KeyValueDbListExtender<'a> {
 }
 
 impl<'a> KeyValueDbListExtender<'a> {
-    // 向列表末尾添加一个新元素。
-    pub fn ladd<V>(&mut self, value: &V) -> KeyValueDbListExtender
+    // 向列表末尾添加一个新元素，错误与 KeyValueDb::ladd 相同
+    pub fn ladd<V>(&mut self, value: &V) -> Result<KeyValueDbListExtender<'_>>
     where
         V: Serialize,
     {
-        self.db.ladd(&self.list_name, value).unwrap()
+        self.db.ladd(&self.list_name, value)
     }
 
-    // 向列表末尾批量添加新元素。
-    pub fn lextend<'i, V, I>(&mut self, seq: I) -> KeyValueDbListExtender
+    // 向列表末尾批量添加新元素，错误与 KeyValueDb::lextend 相同
+    pub fn lextend<'i, V, I>(&mut self, seq: I) -> Result<KeyValueDbListExtender<'_>>
     where
         V: 'i + Serialize,
         I: IntoIterator<Item = &'i V>,
     {
-        self.db.lextend(&self.list_name, seq).unwrap()
+        self.db.lextend(&self.list_name, seq)
     }
 }
 
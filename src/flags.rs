@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::keyvaluedb::KeyValueDb;
+use crate::protocol::checksum;
+
+// 开关保存在以此为前缀的键中
+const FLAG_PREFIX: &str = "flag:";
+
+type Listener = Box<dyn Fn(&str, Option<&FeatureFlag>)>;
+
+// 功能开关的默认规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rollout {
+    // 对所有人打开或者关闭
+    Enabled(bool),
+    // 对一部分租户打开，取值为 0..=100。同一个租户对同一个开关的结果总是相同的，
+    // 百分比增大时已经打开的租户仍然保持打开
+    Percentage(u8),
+}
+
+// 一个功能开关的定义：默认规则和按租户覆盖的结果，覆盖的优先级高于默认规则
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureFlag {
+    pub rollout: Rollout,
+    pub overrides: BTreeMap<String, bool>,
+}
+
+impl FeatureFlag {
+    pub fn new(rollout: Rollout) -> FeatureFlag {
+        FeatureFlag {
+            rollout,
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    // 计算开关对租户 tenant 是否打开，name 是开关的名字，用于让不同开关的百分比选中不同的租户
+    pub fn is_enabled(&self, name: &str, tenant: &str) -> bool {
+        if let Some(enabled) = self.overrides.get(tenant) {
+            return *enabled;
+        }
+        match self.rollout {
+            Rollout::Enabled(enabled) => enabled,
+            Rollout::Percentage(percentage) => bucket_of(name, tenant) < u32::from(percentage),
+        }
+    }
+}
+
+// 租户在开关 name 上所在的桶，取值为 0..100
+fn bucket_of(name: &str, tenant: &str) -> u32 {
+    checksum(format!("{}\0{}", name, tenant).as_bytes()) % 100
+}
+
+// 在 KeyValueDb 上保存功能开关，每个开关保存在键 "flag:<name>" 中，例如：
+//
+//     let mut flags = FeatureFlags::new(db);
+//     flags.define("new_checkout", Rollout::Percentage(10))?;
+//     flags.set_override("new_checkout", "acme", true)?;
+//     flags.on_change(|name, flag| println!("{} changed to {:?}", name, flag));
+//     if flags.is_enabled("new_checkout", tenant) { ... }
+//
+// 不存在的开关对所有租户都是关闭的。每次修改之后依次调用所有的监听函数，开关被删除时参数为 None。
+pub struct FeatureFlags {
+    db: KeyValueDb,
+    listeners: Vec<Listener>,
+}
+
+impl FeatureFlags {
+    pub fn new(db: KeyValueDb) -> FeatureFlags {
+        FeatureFlags {
+            db,
+            listeners: Vec::new(),
+        }
+    }
+
+    // 返回开关的定义，开关不存在时返回 None，无法反序列化时返回 Serialization 错误
+    pub fn flag(&self, name: &str) -> Result<Option<FeatureFlag>> {
+        let key = flag_key(name);
+        if !self.db.exists(&key) {
+            return Ok(None);
+        }
+        match self.db.get(&key) {
+            Some(flag) => Ok(Some(flag)),
+            None => Err(Error::new(ErrorCode::Serialization(format!(
+                "'{}' is not a feature flag",
+                name
+            )))),
+        }
+    }
+
+    // 返回所有开关的名字
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .db
+            .keys_with_prefix(FLAG_PREFIX)
+            .map(|key| String::from(&key[FLAG_PREFIX.len()..]))
+            .collect();
+        names.sort();
+        names
+    }
+
+    // 开关对租户 tenant 是否打开，开关不存在或者无法读取时返回 false
+    pub fn is_enabled(&self, name: &str, tenant: &str) -> bool {
+        match self.flag(name) {
+            Ok(Some(flag)) => flag.is_enabled(name, tenant),
+            _ => false,
+        }
+    }
+
+    // 定义一个开关或者修改开关的默认规则，已经存在的覆盖保持不变
+    pub fn define(&mut self, name: &str, rollout: Rollout) -> Result<()> {
+        self.modify(name, |flag| flag.rollout = rollout)
+    }
+
+    // 覆盖开关对租户 tenant 的结果
+    pub fn set_override(&mut self, name: &str, tenant: &str, enabled: bool) -> Result<()> {
+        self.modify(name, |flag| {
+            flag.overrides.insert(String::from(tenant), enabled);
+        })
+    }
+
+    // 删除对租户 tenant 的覆盖，之后使用默认规则
+    pub fn clear_override(&mut self, name: &str, tenant: &str) -> Result<()> {
+        self.modify(name, |flag| {
+            flag.overrides.remove(tenant);
+        })
+    }
+
+    // 写入完整的开关定义
+    pub fn set(&mut self, name: &str, flag: &FeatureFlag) -> Result<()> {
+        if let Rollout::Percentage(percentage) = flag.rollout {
+            if percentage > 100 {
+                return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                    "Invalid percentage {} for flag '{}'",
+                    percentage, name
+                ))));
+            }
+        }
+        self.db.set(&flag_key(name), flag)?;
+        for listener in &self.listeners {
+            listener(name, Some(flag));
+        }
+        Ok(())
+    }
+
+    // 删除开关，开关不存在时返回 false
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        let removed = self.db.rem(&flag_key(name))?;
+        if removed {
+            for listener in &self.listeners {
+                listener(name, None);
+            }
+        }
+        Ok(removed)
+    }
+
+    // 注册一个监听函数，任何开关被修改或者删除之后调用
+    pub fn on_change<F>(&mut self, f: F)
+    where
+        F: Fn(&str, Option<&FeatureFlag>) + 'static,
+    {
+        self.listeners.push(Box::new(f));
+    }
+
+    // 底层的数据库，例如用于 dump
+    pub fn db(&mut self) -> &mut KeyValueDb {
+        &mut self.db
+    }
+
+    pub fn into_inner(self) -> KeyValueDb {
+        self.db
+    }
+
+    // 读取开关（不存在时为关闭的开关），交给 f 修改后写入
+    fn modify<F>(&mut self, name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut FeatureFlag),
+    {
+        let mut flag = match self.flag(name)? {
+            Some(flag) => flag,
+            None => FeatureFlag::new(Rollout::Enabled(false)),
+        };
+        f(&mut flag);
+        self.set(name, &flag)
+    }
+}
+
+fn flag_key(name: &str) -> String {
+    format!("{}{}", FLAG_PREFIX, name)
+}
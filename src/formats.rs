@@ -0,0 +1,71 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::serialization::{SerializationMethod, Serializer};
+
+// 编译时启用的所有序列化方法
+const METHODS: &[SerializationMethod] = &[
+    #[cfg(feature = "json")]
+    SerializationMethod::Json,
+    #[cfg(feature = "bincode")]
+    SerializationMethod::Bin,
+    #[cfg(feature = "yaml")]
+    SerializationMethod::Yaml,
+    #[cfg(feature = "cbor")]
+    SerializationMethod::Cbor,
+];
+
+// analyze_formats 对一种序列化方法的测量结果。bytes 是所有样本值序列化之后的大小之和，
+// 不包括数据库文件本身的开销（例如键和元数据，以及 Json 和 Yaml 把值当作字符串保存时的转义）。
+// errors 是这种方法无法序列化或者无法读回的样本数，不计入 bytes 和耗时。
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatReport {
+    pub method: SerializationMethod,
+    pub values: usize,
+    pub bytes: usize,
+    pub serialize_time: Duration,
+    pub deserialize_time: Duration,
+    pub errors: usize,
+}
+
+// 用每一种序列化方法序列化 samples 并读回，统计大小和耗时
+pub(crate) fn analyze<V>(samples: &[V]) -> Vec<FormatReport>
+where
+    V: Serialize + DeserializeOwned,
+{
+    METHODS
+        .iter()
+        .map(|&method| {
+            let serializer = Serializer::new(method);
+            let mut report = FormatReport {
+                method,
+                values: samples.len(),
+                bytes: 0,
+                serialize_time: Duration::ZERO,
+                deserialize_time: Duration::ZERO,
+                errors: 0,
+            };
+            for sample in samples {
+                let started = Instant::now();
+                let ser_data = match serializer.serialize_data(sample) {
+                    Ok(ser_data) => ser_data,
+                    Err(_) => {
+                        report.errors += 1;
+                        continue;
+                    }
+                };
+                let serialized = Instant::now();
+                let decoded = serializer.deserialize_data::<V>(&ser_data);
+                let deserialized = Instant::now();
+                if decoded.is_none() {
+                    report.errors += 1;
+                    continue;
+                }
+                report.bytes += ser_data.len();
+                report.serialize_time += serialized - started;
+                report.deserialize_time += deserialized - serialized;
+            }
+            report
+        })
+        .collect()
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// 记录一组非负数值（例如请求延迟）的分布，可以作为普通的值保存在数据库中，在进程重启之后继续累积。
+// 与 DDSketch 相同，数值按照对数划分到桶中，quantile 的相对误差不超过 relative_accuracy，
+// 占用的空间只与数值的范围有关，与记录的次数无关。例如：
+//
+//     let mut latency: Histogram = db.get("latency").unwrap_or_default();
+//     latency.record(elapsed.as_secs_f64() * 1000.0);
+//     db.set("latency", &latency)?;
+//     let p99 = latency.quantile(0.99);
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    relative_accuracy: f64,
+    // 桶的编号到桶中数值的个数，编号为 i 的桶包含 (gamma^(i-1), gamma^i] 中的数值
+    buckets: BTreeMap<i32, u64>,
+    // 小于等于 0 的数值单独计数，quantile 返回 0
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    // 没有记录过任何数值时 min 和 max 为 0，不使用无穷大，因为 JSON 无法表示无穷大
+    min: f64,
+    max: f64,
+}
+
+// 默认的相对误差为 1%
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new(0.01)
+    }
+}
+
+impl Histogram {
+    // relative_accuracy 必须在 (0, 1) 之间，超出范围时使用默认的 1%
+    pub fn new(relative_accuracy: f64) -> Histogram {
+        let relative_accuracy = if relative_accuracy > 0.0 && relative_accuracy < 1.0 {
+            relative_accuracy
+        } else {
+            0.01
+        };
+        Histogram {
+            relative_accuracy,
+            buckets: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    fn gamma(&self) -> f64 {
+        (1.0 + self.relative_accuracy) / (1.0 - self.relative_accuracy)
+    }
+
+    // 记录一个数值，负数视为 0，NaN 被忽略
+    pub fn record(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        let value = value.max(0.0);
+        if value == 0.0 {
+            self.zero_count += 1;
+        } else {
+            let index = value.ln() / self.gamma().ln();
+            *self.buckets.entry(index.ceil() as i32).or_insert(0) += 1;
+        }
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    // 返回 q 分位数（q 在 0 到 1 之间，超出范围时截断），没有记录过任何数值时返回 None
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let q = q.clamp(0.0, 1.0);
+        if q == 0.0 {
+            return Some(self.min);
+        }
+        if q == 1.0 {
+            return Some(self.max);
+        }
+
+        let rank = (q * (self.count - 1) as f64) as u64;
+        if rank < self.zero_count {
+            return Some(0.0);
+        }
+        let gamma = self.gamma();
+        let mut seen = self.zero_count;
+        for (index, count) in &self.buckets {
+            seen += count;
+            if seen > rank {
+                // 桶的中点，使相对误差不超过 relative_accuracy
+                let value = 2.0 * gamma.powi(*index) / (1.0 + gamma);
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    // 合并另一个直方图中的所有数值，两者的 relative_accuracy 必须相同，否则返回 false 并且不做任何修改
+    pub fn merge(&mut self, other: &Histogram) -> bool {
+        if self.relative_accuracy != other.relative_accuracy {
+            return false;
+        }
+        if other.count == 0 {
+            return true;
+        }
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_insert(0) += count;
+        }
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.sum += other.sum;
+        true
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    // 没有记录过任何数值时返回 None
+    pub fn mean(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count => Some(self.sum / count as f64),
+        }
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            _ => Some(self.min),
+        }
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            _ => Some(self.max),
+        }
+    }
+
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+}
@@ -1,17 +1,18 @@
 use serde::de::DeserializeOwned;
-use std::collections::hash_map;
-use std::slice;
+use std::collections::vec_deque;
 
-use crate::serialization::Serializer;
+use crate::serialization::{DbList, Serializer};
 
 // 一个迭代器结构体，用于遍历一个 HashMap 中的键值对，
 // 'a 是生命周期参数，用于指定该迭代器的生命周期与其所遍历的 HashMap 的生命周期相同。
 pub struct KeyValueDbIterator<'a> {
-    // map_iter 是一个 hash_map::Iter 类型的迭代器，它用于遍历一个 HashMap 中的键值对，
+    // map_iter 是一个遍历 HashMap 中键值对的迭代器，可能只包含部分键（例如 iter_prefix），
     // 其中的键是一个 String 类型，值是一个 Vec<u8> 类型。
     // serializer 是一个对序列化器（Serializer）的引用，它用于反序列化 Vec<u8> 类型的值。
-    pub(crate) map_iter: hash_map::Iter<'a, String, Vec<u8>>,
+    pub(crate) map_iter: Box<dyn Iterator<Item = (&'a String, &'a Vec<u8>)> + 'a>,
     pub(crate) serializer: &'a Serializer,
+    // 返回的键去掉前面的 strip_len 个字节，用于 KeyValueDbBucket 隐藏键的前缀
+    pub(crate) strip_len: usize,
 }
 
 // 使其可以通过 for-in 循环进行迭代。每次迭代会返回一个 KeyValueIteratorItem 实例的引用，
@@ -22,7 +23,7 @@ impl<'a> Iterator for KeyValueDbIterator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         match self.map_iter.next() {
             Some((key, value)) => Some(KeyValueDbIteratorItem {
-                key,
+                key: &key[self.strip_len..],
                 value,
                 serializer: self.serializer,
             }),
@@ -49,10 +50,99 @@ impl<'a> KeyValueDbIteratorItem<'a> {
     {
         self.serializer.deserialize_data::<V>(self.value)
     }
+
+    // 与 get_value 相同，但在无法反序列化时返回 V::default()
+    pub fn get_value_or_default<V>(&self) -> V
+    where
+        V: DeserializeOwned + Default,
+    {
+        self.get_value().unwrap_or_default()
+    }
+}
+
+// KeyValueDb::iter_raw 返回的迭代器，依次返回所有的普通值和列表，不进行反序列化。
+// size_hint 的上限是数据库中键（包括列表）的个数，可以用来预先分配空间。
+pub struct KeyValueDbRawIterator<'a> {
+    pub(crate) map_iter: Box<dyn Iterator<Item = (&'a String, &'a Vec<u8>)> + 'a>,
+    pub(crate) list_iter: Box<dyn Iterator<Item = (&'a String, &'a DbList)> + 'a>,
+}
+
+impl<'a> Iterator for KeyValueDbRawIterator<'a> {
+    type Item = KeyValueDbRawItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((key, value)) = self.map_iter.next() {
+            return Some(KeyValueDbRawItem {
+                key,
+                data: RawData::Value(value),
+            });
+        }
+        self.list_iter.next().map(|(name, list)| KeyValueDbRawItem {
+            key: name,
+            data: RawData::List(list),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (map_min, map_max) = self.map_iter.size_hint();
+        let (list_min, list_max) = self.list_iter.size_hint();
+        let max = match (map_max, list_max) {
+            (Some(map_max), Some(list_max)) => map_max.checked_add(list_max),
+            _ => None,
+        };
+        (map_min.saturating_add(list_min), max)
+    }
+}
+
+// iter_raw 返回的一个键：普通值或者列表，以及它们序列化后的数据
+pub struct KeyValueDbRawItem<'a> {
+    key: &'a str,
+    data: RawData<'a>,
+}
+
+enum RawData<'a> {
+    Value(&'a [u8]),
+    List(&'a DbList),
+}
+
+impl<'a> KeyValueDbRawItem<'a> {
+    // 键名，对列表来说是列表名
+    pub fn get_key(&self) -> &'a str {
+        self.key
+    }
+
+    pub fn is_list(&self) -> bool {
+        matches!(self.data, RawData::List(_))
+    }
+
+    // 普通值序列化后的数据，列表返回 None
+    pub fn get_value(&self) -> Option<&'a [u8]> {
+        match self.data {
+            RawData::Value(value) => Some(value),
+            RawData::List(_) => None,
+        }
+    }
+
+    // 列表中每个元素序列化后的数据，从列表的开头开始；普通值返回 None
+    pub fn get_items(&self) -> Option<impl DoubleEndedIterator<Item = &'a [u8]> + 'a> {
+        match self.data {
+            RawData::Value(_) => None,
+            RawData::List(list) => Some(list.iter().map(Vec::as_slice)),
+        }
+    }
+
+    // 这个键占用的字节数，与 KeyValueDb::size_of 相同：键名的长度加上值（或者列表所有元素）的长度
+    pub fn size(&self) -> usize {
+        let data_len = match self.data {
+            RawData::Value(value) => value.len(),
+            RawData::List(list) => list.iter().map(Vec::len).sum(),
+        };
+        self.key.len() + data_len
+    }
 }
 
 pub struct KeyValueDbListIterator<'a> {
-    pub(crate) list_iter: slice::Iter<'a, Vec<u8>>,
+    pub(crate) list_iter: vec_deque::Iter<'a, Vec<u8>>,
     pub(crate) serializer: &'a Serializer,
 }
 
@@ -70,6 +160,17 @@ impl<'a> Iterator for KeyValueDbListIterator<'a> {
     }
 }
 
+// 从列表的末尾开始遍历，例如 db.liter(name).rev() 先返回最后添加的元素
+impl<'a> DoubleEndedIterator for KeyValueDbListIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.list_iter
+            .next_back()
+            .map(|value| KeyValueDbListIteratorItem {
+                value,
+                serializer: self.serializer,
+            })
+    }
+}
 
 pub struct KeyValueDbListIteratorItem<'a> {
     value: &'a Vec<u8>,
@@ -0,0 +1,381 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime};
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::keyvaluedb::KeyValueDb;
+
+// 默认的最大尝试次数，超过之后任务被移入死信队列
+pub(crate) const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+// visibility_timeout 和 backoff 的上限，大约 100 年，超过时按照这个值计算，任务实际上不会再被领取。
+// 不直接使用 now + Duration::MAX 这样的时间，它会溢出 SystemTime，也无法写入数据库文件
+const MAX_DELAY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+fn after(now: SystemTime, delay: Duration) -> SystemTime {
+    now + delay.min(MAX_DELAY)
+}
+
+// reserve 返回的任务。attempts 是包括这一次在内被领取的次数。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job<V> {
+    pub id: u64,
+    pub payload: V,
+    pub attempts: u32,
+}
+
+// 保存在数据库中的任务
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JobRecord<V> {
+    id: u64,
+    payload: V,
+    attempts: u32,
+    // 在这个时间之前任务不会被 reserve 返回：任务被领取后到期之前，或者 nack 之后等待重试期间
+    visible_at: SystemTime,
+    // 最近一次领取该任务的 worker，任务等待被领取时为 None
+    worker: Option<String>,
+}
+
+impl<V> JobRecord<V> {
+    fn is_reserved_by(&self, worker_id: &str, now: SystemTime) -> bool {
+        self.worker.as_deref() == Some(worker_id) && self.visible_at > now
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QueueState<V> {
+    next_id: u64,
+    jobs: Vec<JobRecord<V>>,
+    dead: Vec<JobRecord<V>>,
+}
+
+impl<V> QueueState<V> {
+    fn new() -> QueueState<V> {
+        QueueState {
+            next_id: 0,
+            jobs: Vec::new(),
+            dead: Vec::new(),
+        }
+    }
+}
+
+// 数据库中的一个持久化的任务队列，由 KeyValueDb::job_queue 返回，整个队列作为一个值保存在以队列名为键的位置。
+// 同一个队列中的所有任务必须使用相同的 payload 类型，否则读取队列时返回 Serialization 错误。
+// worker 用 reserve 领取一个任务，任务在 visibility_timeout 之内对其他 worker 不可见；
+// 处理成功后调用 ack 删除任务，失败时调用 nack_with_backoff 在一段时间后重试。
+// worker 在 visibility_timeout 之内既没有 ack 也没有 nack（例如进程崩溃）时，任务会重新被其他 worker 领取。
+// 被领取超过 max_attempts 次仍然没有成功的任务会被移入死信队列，不再被领取。
+// 每个修改队列的操作只会触发一次 dump，队列在进程重启之后仍然有效。
+pub struct KeyValueDbJobQueue<'a, V> {
+    pub(crate) db: &'a mut KeyValueDb,
+    pub(crate) name: String,
+    pub(crate) max_attempts: u32,
+    pub(crate) payload: PhantomData<V>,
+}
+
+impl<'a, V> KeyValueDbJobQueue<'a, V>
+where
+    V: Serialize + DeserializeOwned + Clone,
+{
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> KeyValueDbJobQueue<'a, V> {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    // 添加一个任务，返回任务的 id
+    pub fn enqueue(&mut self, payload: V) -> Result<u64> {
+        let mut state = self.load()?;
+        let id = state.next_id;
+        state.next_id += 1;
+        state.jobs.push(JobRecord {
+            id,
+            payload,
+            attempts: 0,
+            visible_at: SystemTime::now(),
+            worker: None,
+        });
+        self.save(&state)?;
+        Ok(id)
+    }
+
+    // 领取最早添加的一个可以领取的任务，没有这样的任务时返回 None
+    pub fn reserve(
+        &mut self,
+        worker_id: &str,
+        visibility_timeout: Duration,
+    ) -> Result<Option<Job<V>>> {
+        let now = SystemTime::now();
+        let mut state = self.load()?;
+
+        // 领取之后到期仍然没有 ack 的任务已经用完了尝试次数
+        let (exhausted, jobs): (Vec<JobRecord<V>>, Vec<JobRecord<V>>) = state
+            .jobs
+            .into_iter()
+            .partition(|job| job.visible_at <= now && job.attempts >= self.max_attempts);
+        state.jobs = jobs;
+        let mut changed = !exhausted.is_empty();
+        state.dead.extend(exhausted);
+
+        let mut reserved = None;
+        if let Some(job) = state.jobs.iter_mut().find(|job| job.visible_at <= now) {
+            job.attempts += 1;
+            job.visible_at = after(now, visibility_timeout);
+            job.worker = Some(String::from(worker_id));
+            reserved = Some(Job {
+                id: job.id,
+                payload: job.payload.clone(),
+                attempts: job.attempts,
+            });
+            changed = true;
+        }
+
+        if changed {
+            self.save(&state)?;
+        }
+        Ok(reserved)
+    }
+
+    // 确认 worker_id 领取的任务已经完成并删除它。任务不存在、领取已经到期或者不是由 worker_id 领取时返回 false
+    pub fn ack(&mut self, worker_id: &str, id: u64) -> Result<bool> {
+        let now = SystemTime::now();
+        let mut state = self.load()?;
+        match state
+            .jobs
+            .iter()
+            .position(|job| job.id == id && job.is_reserved_by(worker_id, now))
+        {
+            Some(pos) => {
+                state.jobs.remove(pos);
+                self.save(&state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // 放弃 worker_id 领取的任务，任务在 backoff 之后可以重新被领取；已经用完尝试次数的任务被移入死信队列。
+    // 返回值与 ack 相同
+    pub fn nack_with_backoff(
+        &mut self,
+        worker_id: &str,
+        id: u64,
+        backoff: Duration,
+    ) -> Result<bool> {
+        let now = SystemTime::now();
+        let mut state = self.load()?;
+        let pos = match state
+            .jobs
+            .iter()
+            .position(|job| job.id == id && job.is_reserved_by(worker_id, now))
+        {
+            Some(pos) => pos,
+            None => return Ok(false),
+        };
+
+        if state.jobs[pos].attempts >= self.max_attempts {
+            let job = state.jobs.remove(pos);
+            state.dead.push(job);
+        } else {
+            let job = &mut state.jobs[pos];
+            job.visible_at = after(now, backoff);
+            job.worker = None;
+        }
+        self.save(&state)?;
+        Ok(true)
+    }
+
+    // 队列中还没有完成的任务的数量，包括已经被领取的任务，不包括死信队列
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.load()?.jobs.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.load()?.jobs.is_empty())
+    }
+
+    // 返回死信队列中的所有任务
+    pub fn dead_letters(&self) -> Result<Vec<Job<V>>> {
+        Ok(self
+            .load()?
+            .dead
+            .into_iter()
+            .map(|job| Job {
+                id: job.id,
+                payload: job.payload,
+                attempts: job.attempts,
+            })
+            .collect())
+    }
+
+    // 将死信队列中的所有任务放回队列并重置尝试次数，返回移动的任务的数量
+    pub fn retry_dead_letters(&mut self) -> Result<usize> {
+        let mut state = self.load()?;
+        let count = state.dead.len();
+        if count == 0 {
+            return Ok(0);
+        }
+        let now = SystemTime::now();
+        for mut job in state.dead.drain(..) {
+            job.attempts = 0;
+            job.visible_at = now;
+            job.worker = None;
+            state.jobs.push(job);
+        }
+        self.save(&state)?;
+        Ok(count)
+    }
+
+    fn load(&self) -> Result<QueueState<V>> {
+        if !self.db.exists(&self.name) {
+            return Ok(QueueState::new());
+        }
+        match self.db.get::<QueueState<V>>(&self.name) {
+            Some(state) => Ok(state),
+            None => Err(Error::new(ErrorCode::Serialization(format!(
+                "'{}' is not a job queue of this payload type",
+                self.name
+            )))),
+        }
+    }
+
+    fn save(&mut self, state: &QueueState<V>) -> Result<()> {
+        self.db.set(&self.name, state)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::keyvaluedb::KeyValueDbDumpPolicy;
+    use crate::serialization::SerializationMethod;
+    use std::thread;
+
+    const LONG: Duration = Duration::from_secs(60);
+
+    fn memory_db() -> KeyValueDb {
+        KeyValueDb::in_memory(SerializationMethod::Json)
+    }
+
+    #[test]
+    fn reserved_job_is_hidden_until_the_visibility_timeout() {
+        let mut db = memory_db();
+        let mut queue = db.job_queue::<String>("jobs");
+        let id = queue.enqueue(String::from("a")).unwrap();
+
+        let job = queue
+            .reserve("w1", Duration::from_millis(20))
+            .unwrap()
+            .unwrap();
+        assert_eq!((job.id, job.payload.as_str(), job.attempts), (id, "a", 1));
+        assert_eq!(queue.reserve("w2", LONG).unwrap(), None);
+
+        thread::sleep(Duration::from_millis(40));
+        let job = queue.reserve("w2", LONG).unwrap().unwrap();
+        assert_eq!((job.id, job.attempts), (id, 2));
+        // w1 的领取已经到期
+        assert!(!queue.ack("w1", id).unwrap());
+        assert!(queue.ack("w2", id).unwrap());
+        assert!(queue.is_empty().unwrap());
+    }
+
+    #[test]
+    fn only_the_reserving_worker_can_ack_or_nack() {
+        let mut db = memory_db();
+        let mut queue = db.job_queue::<u32>("jobs");
+        let id = queue.enqueue(7).unwrap();
+        queue.reserve("w1", LONG).unwrap().unwrap();
+
+        assert!(!queue.ack("w2", id).unwrap());
+        assert!(!queue.nack_with_backoff("w2", id, Duration::ZERO).unwrap());
+        assert!(!queue.ack("w1", id + 1).unwrap());
+        assert_eq!(queue.reserve("w2", LONG).unwrap(), None);
+
+        assert!(queue.nack_with_backoff("w1", id, Duration::ZERO).unwrap());
+        let job = queue.reserve("w2", LONG).unwrap().unwrap();
+        assert_eq!((job.id, job.attempts), (id, 2));
+        assert!(!queue.ack("w1", id).unwrap());
+        assert!(queue.ack("w2", id).unwrap());
+    }
+
+    #[test]
+    fn exhausted_jobs_move_to_the_dead_letter_queue() {
+        let mut db = memory_db();
+        let mut queue = db.job_queue::<u32>("jobs").with_max_attempts(2);
+        let nacked = queue.enqueue(1).unwrap();
+        let expired = queue.enqueue(2).unwrap();
+
+        for _ in 0..2 {
+            let job = queue.reserve("w", LONG).unwrap().unwrap();
+            assert_eq!(job.id, nacked);
+            assert!(queue
+                .nack_with_backoff("w", nacked, Duration::ZERO)
+                .unwrap());
+        }
+        for _ in 0..2 {
+            let job = queue
+                .reserve("w", Duration::from_millis(10))
+                .unwrap()
+                .unwrap();
+            assert_eq!(job.id, expired);
+            thread::sleep(Duration::from_millis(20));
+        }
+        // 第二次领取到期之后，下一次 reserve 把任务移入死信队列
+        assert_eq!(queue.reserve("w", LONG).unwrap(), None);
+        assert_eq!(queue.len().unwrap(), 0);
+        let dead: Vec<(u64, u32)> = queue
+            .dead_letters()
+            .unwrap()
+            .iter()
+            .map(|job| (job.id, job.attempts))
+            .collect();
+        assert_eq!(dead, vec![(nacked, 2), (expired, 2)]);
+
+        assert_eq!(queue.retry_dead_letters().unwrap(), 2);
+        assert_eq!(queue.retry_dead_letters().unwrap(), 0);
+        assert!(queue.dead_letters().unwrap().is_empty());
+        let job = queue.reserve("w", LONG).unwrap().unwrap();
+        assert_eq!((job.id, job.attempts), (nacked, 1));
+    }
+
+    #[test]
+    fn huge_timeouts_keep_the_job_hidden() {
+        let mut db = memory_db();
+        let mut queue = db.job_queue::<u32>("jobs");
+        let id = queue.enqueue(1).unwrap();
+
+        queue.reserve("w1", Duration::MAX).unwrap().unwrap();
+        assert_eq!(queue.reserve("w2", LONG).unwrap(), None);
+        assert!(queue.nack_with_backoff("w1", id, Duration::MAX).unwrap());
+        assert_eq!(queue.reserve("w2", LONG).unwrap(), None);
+        assert_eq!(queue.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn queue_survives_a_reload() {
+        let mut db =
+            KeyValueDb::temporary(KeyValueDbDumpPolicy::AutoDump, SerializationMethod::Json)
+                .unwrap();
+        let first;
+        {
+            let mut queue = db.job_queue::<String>("jobs").with_max_attempts(1);
+            first = queue.enqueue(String::from("a")).unwrap();
+            queue.enqueue(String::from("b")).unwrap();
+            queue.reserve("w", LONG).unwrap().unwrap();
+            assert!(queue.nack_with_backoff("w", first, Duration::ZERO).unwrap());
+        }
+
+        let path = db.path().unwrap().to_path_buf();
+        let mut loaded = KeyValueDb::load(
+            path,
+            KeyValueDbDumpPolicy::NeverDump,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        let mut queue = loaded.job_queue::<String>("jobs");
+        assert_eq!(queue.len().unwrap(), 1);
+        assert_eq!(queue.dead_letters().unwrap()[0].id, first);
+        let job = queue.reserve("w", LONG).unwrap().unwrap();
+        assert_eq!((job.payload.as_str(), job.attempts), ("b", 1));
+        assert_eq!(queue.enqueue(String::from("c")).unwrap(), first + 2);
+    }
+}
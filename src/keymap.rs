@@ -0,0 +1,231 @@
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::ops::Bound;
+
+// 数据库内部保存键的容器。默认使用 HashMap；
+// 也可以使用按键的字典序排列的 BTreeMap，以支持范围查询和有序遍历。
+// 两种容器序列化后的格式完全相同，因此同一个数据库文件可以用任意一种方式加载。
+pub(crate) enum KeyMap<V> {
+    Hashed(HashMap<String, V>),
+    Ordered(BTreeMap<String, V>),
+}
+
+pub(crate) type KeyMapIter<'a, V> = Box<dyn Iterator<Item = (&'a String, &'a V)> + 'a>;
+
+impl<V> Default for KeyMap<V> {
+    fn default() -> Self {
+        KeyMap::Hashed(HashMap::new())
+    }
+}
+
+impl<V> KeyMap<V> {
+    // 返回一个与当前容器类型相同的空容器
+    pub(crate) fn new_like(&self) -> KeyMap<V> {
+        match self {
+            KeyMap::Hashed(_) => KeyMap::Hashed(HashMap::new()),
+            KeyMap::Ordered(_) => KeyMap::Ordered(BTreeMap::new()),
+        }
+    }
+
+    // 取出所有数据，留下一个类型相同的空容器
+    pub(crate) fn take(&mut self) -> KeyMap<V> {
+        let empty = self.new_like();
+        std::mem::replace(self, empty)
+    }
+
+    // 转换为按键排序的 BTreeMap
+    pub(crate) fn into_ordered(self) -> KeyMap<V> {
+        match self {
+            KeyMap::Hashed(map) => KeyMap::Ordered(map.into_iter().collect()),
+            ordered => ordered,
+        }
+    }
+
+    // 预留至少 additional 个键的空间，BTreeMap 不需要预留空间
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        if let KeyMap::Hashed(map) = self {
+            map.reserve(additional);
+        }
+    }
+
+    // 释放多余的空间，例如在删除大量的键之后
+    pub(crate) fn shrink_to_fit(&mut self) {
+        if let KeyMap::Hashed(map) = self {
+            map.shrink_to_fit();
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&V> {
+        match self {
+            KeyMap::Hashed(map) => map.get(key),
+            KeyMap::Ordered(map) => map.get(key),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        match self {
+            KeyMap::Hashed(map) => map.get_mut(key),
+            KeyMap::Ordered(map) => map.get_mut(key),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        match self {
+            KeyMap::Hashed(map) => map.contains_key(key),
+            KeyMap::Ordered(map) => map.contains_key(key),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: String, value: V) -> Option<V> {
+        match self {
+            KeyMap::Hashed(map) => map.insert(key, value),
+            KeyMap::Ordered(map) => map.insert(key, value),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        match self {
+            KeyMap::Hashed(map) => map.remove(key),
+            KeyMap::Ordered(map) => map.remove(key),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            KeyMap::Hashed(map) => map.len(),
+            KeyMap::Ordered(map) => map.len(),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> KeyMapIter<'_, V> {
+        match self {
+            KeyMap::Hashed(map) => Box::new(map.iter()),
+            KeyMap::Ordered(map) => Box::new(map.iter()),
+        }
+    }
+
+    pub(crate) fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut V> + '_> {
+        match self {
+            KeyMap::Hashed(map) => Box::new(map.values_mut()),
+            KeyMap::Ordered(map) => Box::new(map.values_mut()),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        Box::new(self.iter().map(|(key, _)| key))
+    }
+
+    // 按字典序返回位于 start 和 end 之间的键值对，范围为空（例如 start 大于 end）时不返回任何结果。
+    // BTreeMap 可以直接定位到范围的起点；HashMap 需要遍历所有的键，再对结果排序。
+    pub(crate) fn range(&self, start: Bound<&str>, end: Bound<&str>) -> KeyMapIter<'_, V> {
+        match self {
+            KeyMap::Ordered(map) => {
+                // BTreeMap::range 遇到空的范围会 panic
+                let empty = match (start, end) {
+                    (Bound::Included(s), Bound::Included(e)) => s > e,
+                    (Bound::Included(s), Bound::Excluded(e))
+                    | (Bound::Excluded(s), Bound::Included(e))
+                    | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+                    _ => false,
+                };
+                if empty {
+                    Box::new(std::iter::empty())
+                } else {
+                    Box::new(map.range::<str, _>((start, end)))
+                }
+            }
+            KeyMap::Hashed(map) => {
+                let mut items: Vec<(&String, &V)> = map
+                    .iter()
+                    .filter(|(key, _)| in_bounds(key, start, end))
+                    .collect();
+                items.sort_by(|a, b| a.0.cmp(b.0));
+                Box::new(items.into_iter())
+            }
+        }
+    }
+
+    // 按字典序返回 start 之后的最多 count 个键。
+    // BTreeMap 只需要读取这 count 个键；HashMap 需要检查所有的键，但只保留最小的 count 个，不会复制所有的键。
+    pub(crate) fn keys_after(&self, start: Bound<&str>, count: usize) -> Vec<&String> {
+        match self {
+            KeyMap::Ordered(map) => map
+                .range::<str, _>((start, Bound::Unbounded))
+                .take(count)
+                .map(|(key, _)| key)
+                .collect(),
+            KeyMap::Hashed(map) => {
+                let mut smallest: BinaryHeap<&String> = BinaryHeap::with_capacity(count + 1);
+                for key in map.keys() {
+                    if !in_bounds(key, start, Bound::Unbounded) {
+                        continue;
+                    }
+                    if smallest.len() < count {
+                        smallest.push(key);
+                    } else if smallest.peek().is_some_and(|largest| key < *largest) {
+                        smallest.pop();
+                        smallest.push(key);
+                    }
+                }
+                smallest.into_sorted_vec()
+            }
+        }
+    }
+}
+
+fn in_bounds(key: &str, start: Bound<&str>, end: Bound<&str>) -> bool {
+    let after_start = match start {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
+    };
+    let before_end = match end {
+        Bound::Included(end) => key <= end,
+        Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+impl<V> Extend<(String, V)> for KeyMap<V> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, iter: I) {
+        match self {
+            KeyMap::Hashed(map) => map.extend(iter),
+            KeyMap::Ordered(map) => map.extend(iter),
+        }
+    }
+}
+
+impl<V> IntoIterator for KeyMap<V>
+where
+    V: 'static,
+{
+    type Item = (String, V);
+    type IntoIter = Box<dyn Iterator<Item = (String, V)>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            KeyMap::Hashed(map) => Box::new(map.into_iter()),
+            KeyMap::Ordered(map) => Box::new(map.into_iter()),
+        }
+    }
+}
+
+impl<V> FromIterator<(String, V)> for KeyMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        KeyMap::Hashed(iter.into_iter().collect())
+    }
+}
+
+impl<V: Serialize> Serialize for KeyMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for KeyMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(KeyMap::Hashed)
+    }
+}
@@ -0,0 +1,106 @@
+// 把任意的字符串（例如用户输入）安全地嵌入键中的工具函数。
+// 转义使用百分号编码，例如 ':' 编码为 "%3A"。复合键、命名空间和服务端的文本协议都使用这一种编码，
+// 所以 unescape 可以解码其中任何一层转义的结果。
+use std::fmt::Write;
+
+use crate::protocol::checksum;
+
+// 复合键中各个部分之间的分隔符，也是命名空间与键之间的分隔符
+pub const SEPARATOR: char = ':';
+
+// shorten 在截断的键末尾追加的 "~" 和 8 位十六进制校验和的长度
+pub const SHORTEN_SUFFIX_LEN: usize = 9;
+
+// 复合键的一个部分中需要转义的字符：'%'、分隔符、空白和控制字符，以及 keys_matching 的通配符 '*' 和 '?'
+fn is_reserved(c: char) -> bool {
+    c == '%' || c == SEPARATOR || c == '*' || c == '?' || c.is_whitespace() || c.is_control()
+}
+
+// 完整的键在文本协议中需要转义的字符：'%'、空白和控制字符
+fn is_reserved_in_text(c: char) -> bool {
+    c == '%' || c.is_whitespace() || c.is_control()
+}
+
+fn escape_with(text: &str, reserved: fn(char) -> bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if reserved(c) {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                let _ = write!(escaped, "%{:02X}", byte);
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+// 转义复合键的一个部分（或者命名空间的名字），转义之后不包含分隔符、通配符和空白，
+// 例如 escape("a:b c") 返回 "a%3Ab%20c"
+pub fn escape(part: &str) -> String {
+    escape_with(part, is_reserved)
+}
+
+// 转义一个完整的键，只转义 '%'、空白和控制字符，分隔符保持不变。
+// 服务端的文本协议用它表示包含空格的键，例如 escape_key("user:a b") 返回 "user:a%20b"
+pub fn escape_key(key: &str) -> String {
+    escape_with(key, is_reserved_in_text)
+}
+
+// 解码 escape 或者 escape_key 的结果。'%' 后面不是两位十六进制数字，或者解码之后不是合法的 UTF-8 时返回 None
+pub fn unescape(escaped: &str) -> Option<String> {
+    let bytes = escaped.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let digits = escaped.get(i + 1..i + 3)?;
+            if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(digits, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// part 不包含任何需要转义的字符，即 escape(part) == part
+pub fn is_safe(part: &str) -> bool {
+    !part.chars().any(is_reserved)
+}
+
+// 转义每一个部分之后用分隔符连接，例如 join(&["user", "a:b"]) 返回 "user:a%3Ab"
+pub fn join<S: AsRef<str>>(parts: &[S]) -> String {
+    let escaped: Vec<String> = parts.iter().map(|part| escape(part.as_ref())).collect();
+    escaped.join(&SEPARATOR.to_string())
+}
+
+// join 的逆操作，按照分隔符拆分之后解码每一个部分，某一个部分无法解码时返回 None
+pub fn split(key: &str) -> Option<Vec<String>> {
+    key.split(SEPARATOR).map(unescape).collect()
+}
+
+// 键的长度（字节数）超过 max_len 时截断，并在末尾追加 "~" 和完整的键的校验和，
+// 使前缀相同的长键截断之后仍然（大概率）不同，适合与 with_max_key_len 一起使用。
+// 截断不会拆开一个字符或者一个百分号转义；截断是单向的，无法从结果还原出原来的键。
+// max_len 小于 SHORTEN_SUFFIX_LEN 时结果只有校验和，长度仍然是 SHORTEN_SUFFIX_LEN。
+pub fn shorten(key: &str, max_len: usize) -> String {
+    if key.len() <= max_len {
+        return String::from(key);
+    }
+    let mut keep = max_len.saturating_sub(SHORTEN_SUFFIX_LEN);
+    while !key.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    // 不保留被截断的百分号转义，例如 "%3" 或者 "%"
+    let tail = keep.saturating_sub(2);
+    if let Some(percent) = key.as_bytes()[tail..keep].iter().position(|b| *b == b'%') {
+        keep = tail + percent;
+    }
+    format!("{}~{:08x}", &key[..keep], checksum(key.as_bytes()))
+}
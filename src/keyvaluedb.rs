@@ -1,14 +1,40 @@
 use serde::{de::DeserializeOwned, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
 use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::bucket::KeyValueDbBucket;
+use crate::buffer::WriteBuffer;
+use crate::database::KeyValueDbDatabase;
+use crate::entry::{KeyValueDbEntry, KeyValueDbOccupiedEntry, KeyValueDbVacantEntry};
 use crate::error::{Error, ErrorCode, Result};
+use crate::eviction::Eviction;
 use crate::extenders::KeyValueDbListExtender;
-use crate::iterators::{KeyValueDbIterator, KeyValueDbListIterator};
+use crate::formats::{self, FormatReport};
+use crate::glob::glob_match;
+use crate::iterators::{KeyValueDbIterator, KeyValueDbListIterator, KeyValueDbRawIterator};
+use crate::jobqueue::{KeyValueDbJobQueue, DEFAULT_MAX_ATTEMPTS};
+use crate::keymap::KeyMap;
+use crate::keys;
+use crate::listindex::ListIndex;
+use crate::lock::LockState;
+use crate::metadata::KeyMetadata;
+use crate::pqueue::PriorityQueue;
+use crate::ratelimit::{RateLimitDecision, TokenBucket};
+use crate::readhandle::{KeyValueDbReadHandle, Published, ReadSnapshot};
+use crate::schedule::{schedule_key, ScheduledEntry, SCHEDULE_PREFIX};
 use crate::serialization::SerializationMethod;
 use crate::serialization::Serializer;
+use crate::serialization::{DbList, Keyspace, KeyspaceRef};
 
 // 将键值对数据库中的更改自动存储到磁盘的四种策略
 pub enum KeyValueDbDumpPolicy {
@@ -25,16 +51,73 @@ pub enum KeyValueDbDumpPolicy {
     PeriodicDump(Duration),
 }
 
+// with_max_memory 设置的内存上限被超过时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyValueDbEvictionPolicy {
+    // 淘汰最久没有被访问的键
+    Lru,
+    // 淘汰被访问次数最少的键，次数相同时淘汰最久没有被访问的键
+    Lfu,
+    // 不淘汰任何键，拒绝会超过上限的写入并返回 OutOfMemoryBudget 错误
+    RejectWrites,
+}
+
+// merge_from 遇到两个数据库中都存在的键时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeStrategy {
+    // 保留当前数据库中的值
+    KeepExisting,
+    // 使用另一个数据库中的值
+    Overwrite,
+    // 返回 KeyExists 错误，不合并任何键
+    ErrorOnConflict,
+}
+
+// take_due 修改一个键之前保存的普通值、列表和元数据，dump 失败时用于恢复
+type OriginalKey = (Option<Vec<u8>>, Option<DbList>, Option<KeyMetadata>);
+
 // 表示一个键值对数据库对象
 pub struct KeyValueDb {
-    map: HashMap<String, Vec<u8>>,
-    list_map: HashMap<String, Vec<Vec<u8>>>,
+    map: KeyMap<Vec<u8>>,
+    list_map: KeyMap<DbList>,
+    meta: KeyMap<KeyMetadata>,
+    // 当前选择的逻辑数据库的编号，map、list_map 和 meta 中保存的是这个数据库的数据
+    selected: usize,
+    // 其他不为空的逻辑数据库
+    databases: BTreeMap<usize, Keyspace>,
     serializer: Serializer,
-    db_file_path: PathBuf,
+    // 纯内存数据库（in_memory）没有文件路径
+    db_file_path: Option<PathBuf>,
     dump_policy: KeyValueDbDumpPolicy,
     last_dump: Instant,
+    // 自上次 dump 以来是否有未写入文件的修改
+    dirty: bool,
+    // temporary 创建的临时目录，Drop 时连同其中的数据库文件一起删除
+    temp_dir: Option<PathBuf>,
+    // 键的最大长度（字节）和序列化之后的值的最大大小，None 表示不限制
+    max_key_len: Option<usize>,
+    max_value_bytes: Option<usize>,
+    // with_max_memory 设置的内存上限和访问记录
+    eviction: Option<Eviction>,
+    // buffer_writes 注册的键，写入时推迟 dump
+    write_buffers: Vec<WriteBuffer>,
+    // 是否在元数据中记录值的类型名，见 with_type_tags
+    type_tags: bool,
+    // load_read_only 打开的数据库不允许任何修改
+    read_only: bool,
+    // dump 时的临时文件名为数据库文件名加上这个后缀和时间戳，见 with_temp_suffix
+    temp_suffix: String,
+    // 等待锁的默认超时时间，见 with_lock_wait_timeout
+    lock_wait_timeout: Duration,
+    // read_handle 创建的读句柄共享的快照，没有读句柄时为 None，dump 时不需要复制数据
+    read_handles: Option<Arc<Published>>,
+    // validate_with 注册的键前缀和检查函数
+    validators: Vec<(String, Validator)>,
 }
 
+// validate_with 的检查函数，参数是序列化后的值，返回 Err 时拒绝写入
+type Validator = Box<dyn Fn(&[u8]) -> std::result::Result<(), String> + Send + Sync>;
+
 impl KeyValueDb {
     // 创建 KeyValueDb 实例的方法，参数为：
     // db_path：类型是 P，需要可以转换为 Path 类型，它指定了数据库存储的路径。
@@ -51,14 +134,115 @@ impl KeyValueDb {
         let mut db_path_buf = PathBuf::new();
         db_path_buf.push(db_path);
 
-        KeyValueDb {
-            map: HashMap::new(),
-            list_map: HashMap::new(),
+        let db = KeyValueDb {
+            map: KeyMap::default(),
+            list_map: KeyMap::default(),
+            meta: KeyMap::default(),
+            selected: 0,
+            databases: BTreeMap::new(),
             serializer: Serializer::new(serialization_method),
-            db_file_path: db_path_buf,
+            db_file_path: Some(db_path_buf),
             dump_policy,
             last_dump: Instant::now(),
+            dirty: false,
+            temp_dir: None,
+            max_key_len: None,
+            max_value_bytes: None,
+            eviction: None,
+            write_buffers: Vec::new(),
+            type_tags: false,
+            read_only: false,
+            temp_suffix: String::from(DEFAULT_TEMP_SUFFIX),
+            lock_wait_timeout: DEFAULT_LOCK_WAIT_TIMEOUT,
+            read_handles: None,
+            validators: Vec::new(),
+        };
+        // 清理之前的进程留下的临时文件，失败（例如目录还不存在）时不影响创建数据库
+        let _ = db.cleanup_temp_files();
+        db
+    }
+
+    // 在系统的临时目录中新建一个单独的目录，并在其中创建数据库文件，例如用于集成测试。
+    // 数据库被 drop 时删除整个目录，包括数据库文件和 dump 留下的 .temp 文件，drop 时不会再 dump；
+    // 即使之后用 set_path 修改了路径，删除的仍然是这个临时目录。
+    pub fn temporary(
+        dump_policy: KeyValueDbDumpPolicy,
+        serialization_method: SerializationMethod,
+    ) -> Result<KeyValueDb> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = env::temp_dir().join(format!(
+            "kvstore-{}-{}-{}",
+            process::id(),
+            nanos,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(err) = fs::create_dir(&dir) {
+            return Err(Error::new(ErrorCode::Io(err))
+                .with_op("temporary")
+                .with_path(&dir));
+        }
+
+        let mut db = KeyValueDb::new(dir.join("kvstore.db"), dump_policy, serialization_method);
+        db.temp_dir = Some(dir);
+        Ok(db)
+    }
+
+    // 创建一个不对应任何文件的纯内存数据库，例如用于测试或者临时的缓存。
+    // dump 不做任何事情并返回成功，close 和 Drop 也不会写入任何文件；
+    // 之后可以用 set_path 指定文件路径，此后的 dump 会写入该文件。
+    pub fn in_memory(serialization_method: SerializationMethod) -> KeyValueDb {
+        KeyValueDb {
+            map: KeyMap::default(),
+            list_map: KeyMap::default(),
+            meta: KeyMap::default(),
+            selected: 0,
+            databases: BTreeMap::new(),
+            serializer: Serializer::new(serialization_method),
+            db_file_path: None,
+            dump_policy: KeyValueDbDumpPolicy::DumpUponRequest,
+            last_dump: Instant::now(),
+            dirty: false,
+            temp_dir: None,
+            max_key_len: None,
+            max_value_bytes: None,
+            eviction: None,
+            write_buffers: Vec::new(),
+            type_tags: false,
+            read_only: false,
+            temp_suffix: String::from(DEFAULT_TEMP_SUFFIX),
+            lock_wait_timeout: DEFAULT_LOCK_WAIT_TIMEOUT,
+            read_handles: None,
+            validators: Vec::new(),
+        }
+    }
+
+    // 创建一个包含 map 中所有键值对的纯内存数据库（见 in_memory），值使用 serialization_method 序列化，
+    // 之后可以用 set_path 指定文件路径。任何一个值无法序列化时返回 Serialization 错误。
+    pub fn from_hashmap<V>(
+        map: HashMap<String, V>,
+        serialization_method: SerializationMethod,
+    ) -> Result<KeyValueDb>
+    where
+        V: Serialize,
+    {
+        let mut db = KeyValueDb::in_memory(serialization_method);
+        db.map.reserve(map.len());
+        for (key, value) in map {
+            let ser_data = match db.serializer.serialize_data(&value) {
+                Ok(ser_data) => ser_data,
+                Err(err_str) => {
+                    return Err(Error::new(ErrorCode::Serialization(err_str))
+                        .with_context("from_hashmap", &key))
+                }
+            };
+            db.touch(&key);
+            db.map.insert(key, ser_data);
         }
+        Ok(db)
     }
 
     // 使用 SerializationMethod::Json 作为序列化方法，其他的实现和 new 方法相同。
@@ -90,21 +274,41 @@ impl KeyValueDb {
     
     // 最后，创建一个新的 KeyValueDb 实例，包括读取的哈希表和列表映射、序列化器、DB 文件路径、存储策略和上一次写入文件的时间。
     // 如果所有操作都成功，则返回 Ok 包装的 KeyValueDb 实例。
+    // 与 new 一样会清理之前的进程留下的临时文件，清理失败时不影响加载。
     pub fn load<P: AsRef<Path>>(
         db_path: P,
         dump_policy: KeyValueDbDumpPolicy,
         serialization_method: SerializationMethod,
+    ) -> Result<KeyValueDb> {
+        let db = KeyValueDb::load_file(db_path, dump_policy, serialization_method)?;
+        let _ = db.cleanup_temp_files();
+        Ok(db)
+    }
+
+    // load 和 load_read_only 共用，不清理临时文件
+    fn load_file<P: AsRef<Path>>(
+        db_path: P,
+        dump_policy: KeyValueDbDumpPolicy,
+        serialization_method: SerializationMethod,
     ) -> Result<KeyValueDb> {
         let content = match fs::read(db_path.as_ref()) {
             Ok(file_content) => file_content,
-            Err(err) => return Err(Error::new(ErrorCode::Io(err))),
+            Err(err) => {
+                return Err(Error::new(ErrorCode::Io(err))
+                    .with_op("load")
+                    .with_path(db_path.as_ref()))
+            }
         };
 
         let serializer = Serializer::new(serialization_method);
 
-        let maps_from_file: (_, _) = match serializer.deserialize_db(&content) {
+        let maps_from_file: (_, _, _, _) = match serializer.deserialize_db(&content) {
             Ok(maps) => maps,
-            Err(err_str) => return Err(Error::new(ErrorCode::Serialization(err_str))),
+            Err(err_str) => {
+                return Err(Error::new(ErrorCode::Serialization(err_str))
+                    .with_op("load")
+                    .with_path(db_path.as_ref()))
+            }
         };
 
         let mut db_path_buf = PathBuf::new();
@@ -113,10 +317,25 @@ impl KeyValueDb {
         Ok(KeyValueDb {
             map: maps_from_file.0,
             list_map: maps_from_file.1,
+            meta: maps_from_file.2,
+            selected: 0,
+            databases: maps_from_file.3,
             serializer,
-            db_file_path: db_path_buf,
+            db_file_path: Some(db_path_buf),
             dump_policy,
             last_dump: Instant::now(),
+            dirty: false,
+            temp_dir: None,
+            max_key_len: None,
+            max_value_bytes: None,
+            eviction: None,
+            write_buffers: Vec::new(),
+            type_tags: false,
+            read_only: false,
+            temp_suffix: String::from(DEFAULT_TEMP_SUFFIX),
+            lock_wait_timeout: DEFAULT_LOCK_WAIT_TIMEOUT,
+            read_handles: None,
+            validators: Vec::new(),
         })
     }
 
@@ -161,94 +380,641 @@ impl KeyValueDb {
     }
 
     // 加载指定路径的 KeyValueDb 文件，但将其配置为只读模式，不会将任何更改写入文件。
+    // 任何修改数据库的方法（set、rem、lcreate 等）都返回 ReadOnly 错误，数据保持不变。
+    // 也不会清理目录中的临时文件。
     pub fn load_read_only<P: AsRef<Path>>(
         db_path: P,
         serialization_method: SerializationMethod,
     ) -> Result<KeyValueDb> {
-        KeyValueDb::load(db_path, KeyValueDbDumpPolicy::NeverDump, serialization_method)
+        let mut db = KeyValueDb::load_file(
+            db_path,
+            KeyValueDbDumpPolicy::NeverDump,
+            serialization_method,
+        )?;
+        db.read_only = true;
+        Ok(db)
+    }
+
+    // 修改数据库之前调用，只读的数据库返回 ReadOnly 错误，错误中记录操作 op 和键 key
+    fn check_writable(&self, op: &'static str, key: Option<&str>) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        let err = Error::new(ErrorCode::ReadOnly(format!(
+            "Cannot {}: the database is read-only",
+            op
+        )))
+        .with_op(op);
+        match key {
+            Some(key) => Err(err.with_key(key)),
+            None => Err(err),
+        }
+    }
+
+    // 改为使用按字典序排列的 BTreeMap 保存所有的键，之后 iter、get_all 等方法都会按键的顺序返回结果，
+    // 并且可以使用 range 高效地查询一个范围内的键。可以在 new 或者 load 之后调用，例如：
+    // KeyValueDb::load_json("db.json", KeyValueDbDumpPolicy::AutoDump)?.with_ordered_keys()
+    // 数据库文件的格式不受影响。
+    pub fn with_ordered_keys(mut self) -> KeyValueDb {
+        self.map = self.map.take().into_ordered();
+        self.list_map = self.list_map.take().into_ordered();
+        self.meta = self.meta.take().into_ordered();
+        for (map, list_map, meta) in self.databases.values_mut() {
+            *map = map.take().into_ordered();
+            *list_map = list_map.take().into_ordered();
+            *meta = meta.take().into_ordered();
+        }
+        self
+    }
+
+    // 限制键的长度（字节），set、lcreate、rename、copy、import_keys 和 merge_from 写入更长的键时
+    // 返回 LimitExceeded 错误，已经存在的键不受影响
+    pub fn with_max_key_len(mut self, max_key_len: usize) -> KeyValueDb {
+        self.max_key_len = Some(max_key_len);
+        self
+    }
+
+    // 限制单个值序列化之后的大小（字节），对列表来说是每个元素的大小。
+    // set、ladd、lextend、import_keys 和 merge_from 写入更大的值时返回 LimitExceeded 错误，列表保持不变
+    pub fn with_max_value_bytes(mut self, max_value_bytes: usize) -> KeyValueDb {
+        self.max_value_bytes = Some(max_value_bytes);
+        self
+    }
+
+    // 限制当前逻辑数据库中所有键的 size_of 之和（即 total_bytes），每个逻辑数据库分别计算。
+    // 写入会超过上限时，按照 policy 在写入之前淘汰其他的键，或者拒绝写入；
+    // 单个值本身就超过上限时总是返回 OutOfMemoryBudget 错误。被淘汰的键在写入的 dump 失败时不会恢复。
+    // 访问记录只保存在内存中，load 之后所有的键都视为没有被访问过。
+    // 每次写入都需要计算 total_bytes，耗时与键的数量成正比。
+    pub fn with_max_memory(
+        mut self,
+        max_bytes: usize,
+        policy: KeyValueDbEvictionPolicy,
+    ) -> KeyValueDb {
+        self.eviction = Some(Eviction::new(max_bytes, policy));
+        self
+    }
+
+    // 为即将写入的 key 腾出空间：写入之后 key 的大小为 added，写入之前为 replaced
+    fn reserve_memory(
+        &mut self,
+        op: &'static str,
+        key: &str,
+        added: usize,
+        replaced: usize,
+    ) -> Result<()> {
+        self.reserve_memory_for(op, &[key], added, replaced)
+    }
+
+    // 与 reserve_memory 相同，但一次写入多个键：added 和 replaced 是所有键写入之后和写入之前的大小之和。
+    // keys 中的键都不会被淘汰，错误中记录的是第一个键
+    fn reserve_memory_for(
+        &mut self,
+        op: &'static str,
+        keys: &[&str],
+        added: usize,
+        replaced: usize,
+    ) -> Result<()> {
+        let key = match keys.first() {
+            Some(key) => *key,
+            None => return Ok(()),
+        };
+        let (max_bytes, policy) = match &self.eviction {
+            Some(eviction) => (eviction.max_bytes, eviction.policy),
+            None => return Ok(()),
+        };
+        let mut projected = self.total_bytes() - replaced + added;
+        if projected <= max_bytes {
+            return Ok(());
+        }
+        if added > max_bytes || policy == KeyValueDbEvictionPolicy::RejectWrites {
+            let target = match keys.len() {
+                1 => format!("'{}'", key),
+                len => format!("{} keys", len),
+            };
+            return Err(Error::new(ErrorCode::OutOfMemoryBudget(format!(
+                "Writing {} would use {} bytes, the limit is {}",
+                target, projected, max_bytes
+            )))
+            .with_context(op, key));
+        }
+
+        let candidates: Vec<String> = self
+            .map
+            .keys()
+            .chain(self.list_map.keys())
+            .cloned()
+            .collect();
+        let eviction = self.eviction.as_ref().unwrap();
+        for victim in eviction.order(self.selected, candidates) {
+            if projected <= max_bytes {
+                break;
+            }
+            if keys.contains(&victim.as_str()) {
+                continue;
+            }
+            projected -= self.size_of(&victim).unwrap_or(0);
+            self.map.remove(&victim);
+            self.list_map.remove(&victim);
+            self.meta.remove(&victim);
+            eviction.forget(self.selected, &victim);
+        }
+        Ok(())
+    }
+
+    fn record_access(&self, key: &str) {
+        if let Some(eviction) = &self.eviction {
+            eviction.record(self.selected, key);
+        }
+    }
+
+    // 修改 dump 时临时文件名中的后缀，临时文件名为数据库文件名加上 suffix 和当前时间戳，默认的 suffix 为 ".temp."。
+    // 例如使用 with_temp_suffix("~") 时 db.json 的临时文件为 db.json~1700000000。
+    // suffix 不能包含路径分隔符。new 和 load 清理临时文件时使用的是默认的后缀，
+    // 因此这里会按新的后缀再清理一次，忽略其中的错误。
+    pub fn with_temp_suffix(mut self, suffix: &str) -> KeyValueDb {
+        self.temp_suffix = String::from(suffix);
+        let _ = self.cleanup_temp_files();
+        self
+    }
+
+    // 在多个线程之间共享这个数据库时，LeaseBackend::acquire_wait 等待锁的默认超时时间，默认为 30 秒。
+    // lock 本身从不等待，需要限制等待时间的调用方也可以用 acquire_within 为每次等待单独指定超时时间
+    pub fn with_lock_wait_timeout(mut self, timeout: Duration) -> KeyValueDb {
+        self.lock_wait_timeout = timeout;
+        self
+    }
+
+    // with_lock_wait_timeout 设置的超时时间
+    pub fn lock_wait_timeout(&self) -> Duration {
+        self.lock_wait_timeout
+    }
+
+    // 写入普通值时在元数据（key_info 的 type_tag）中记录值的类型名，get_checked 据此检查读取的类型。
+    // 类型名来自 std::any::type_name，set(key, &"text") 和 set(key, &String::from("text"))
+    // 记录的类型名相同；set_raw 写入的值和使用 with_type_tags 之前写入的值没有类型名。
+    pub fn with_type_tags(mut self) -> KeyValueDb {
+        self.type_tags = true;
+        self
+    }
+
+    fn check_key(&self, op: &'static str, key: &str) -> Result<()> {
+        match self.max_key_len {
+            Some(max) if key.len() > max => Err(Error::new(ErrorCode::LimitExceeded(format!(
+                "Key is {} bytes long, the limit is {}",
+                key.len(),
+                max
+            )))
+            .with_context(op, key)),
+            _ => Ok(()),
+        }
+    }
+
+    // 写入以 prefix 开头的键（列表为列表名）之前，用 validator 检查序列化后的值，
+    // validator 返回 Err(原因) 时写入被拒绝并返回 ValidationFailed 错误，数据库保持不变。例如：
+    //
+    //     db.validate_with("user:", |raw| match serde_json::from_slice::<User>(raw) {
+    //         Ok(user) if !user.email.is_empty() => Ok(()),
+    //         Ok(_) => Err(String::from("email is empty")),
+    //         Err(err) => Err(err.to_string()),
+    //     });
+    //
+    // 同一个键匹配多个前缀时所有的 validator 都需要通过。set、ladd、lset 等写入值的方法，
+    // 以及 rename、copy、import_keys、restore 和 merge_from 都会按照写入的键检查；已经存在的值不会检查。
+    pub fn validate_with<F>(&mut self, prefix: &str, validator: F)
+    where
+        F: Fn(&[u8]) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.validators
+            .push((String::from(prefix), Box::new(validator)));
+    }
+
+    fn check_value(&self, op: &'static str, key: &str, ser_data: &[u8]) -> Result<()> {
+        for (prefix, validator) in &self.validators {
+            if !key.starts_with(prefix.as_str()) {
+                continue;
+            }
+            if let Err(reason) = validator(ser_data) {
+                return Err(Error::new(ErrorCode::ValidationFailed(format!(
+                    "Value of '{}' was rejected: {}",
+                    key, reason
+                )))
+                .with_context(op, key));
+            }
+        }
+        match self.max_value_bytes {
+            Some(max) if ser_data.len() > max => {
+                Err(Error::new(ErrorCode::LimitExceeded(format!(
+                    "Value of '{}' is {} bytes, the limit is {}",
+                    key,
+                    ser_data.len(),
+                    max
+                )))
+                .with_context(op, key))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 预先为 keys 个普通键分配空间，避免批量写入时反复扩容，例如：
+    // KeyValueDb::new_json("db.json", KeyValueDbDumpPolicy::DumpUponRequest).with_capacity(1_000_000)
+    // 在 with_ordered_keys 之后调用没有效果。
+    pub fn with_capacity(mut self, keys: usize) -> KeyValueDb {
+        self.map.reserve(keys);
+        self.meta.reserve(keys);
+        self
+    }
+
+    // 释放所有逻辑数据库中多余的空间，例如在 purge_expired 或者 rem_prefix 删除了大量的键之后。
+    // 删除键时容器不会自动缩小，因此进程占用的内存只会在调用这个方法之后下降。
+    pub fn shrink_to_fit(&mut self) {
+        let current = (&mut self.map, &mut self.list_map, &mut self.meta);
+        let others = self
+            .databases
+            .values_mut()
+            .map(|(map, list_map, meta)| (map, list_map, meta));
+        for (map, list_map, meta) in std::iter::once(current).chain(others) {
+            map.shrink_to_fit();
+            meta.shrink_to_fit();
+            list_map.shrink_to_fit();
+            for list in list_map.values_mut() {
+                list.shrink_to_fit();
+            }
+        }
+    }
+
+    // 返回数据库使用的序列化方法
+    pub fn serialization_method(&self) -> SerializationMethod {
+        self.serializer.method()
+    }
+
+    // 将整个数据库序列化为与数据库文件相同格式的数据，不写入任何文件，
+    // 可以用来在后台线程中保存备份，而不必在写文件期间占用数据库。
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        match self.serialize() {
+            Ok(ser_db) => Ok(ser_db),
+            Err(err_str) => Err(Error::new(ErrorCode::Serialization(err_str)).with_op("snapshot")),
+        }
+    }
+
+    // 序列化所有的逻辑数据库，空的数据库不会被写入
+    fn serialize(&self) -> std::result::Result<Vec<u8>, String> {
+        let mut keyspaces: BTreeMap<usize, KeyspaceRef> = self
+            .databases
+            .iter()
+            .map(|(index, (map, list_map, meta))| (*index, (map, list_map, meta)))
+            .collect();
+        if self.map.len() + self.list_map.len() > 0 {
+            keyspaces.insert(self.selected, (&self.map, &self.list_map, &self.meta));
+        }
+
+        let empty = Keyspace::default();
+        let (map, list_map, meta) = keyspaces
+            .remove(&0)
+            .unwrap_or((&empty.0, &empty.1, &empty.2));
+        self.serializer
+            .serialize_db(map, list_map, meta, &keyspaces)
+    }
+
+    // 自上次 dump 以来是否有修改还没有写入文件
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
     }
 
     // dump 方法用于将当前的键值存储到文件中。具体实现如下：
     // 首先，如果当前设置的存储策略是 NeverDump，则直接返回成功。
     // 接着，使用 Serializer 结构体的 serialize_db 方法将当前的键值对转化为二进制格式。
-    // 如果转化成功，则将转化后的数据写入到临时文件中，临时文件名为当前数据库文件名加上 .temp 后缀加上当前时间戳的字符串表示。
+    // 如果转化成功，则将转化后的数据写入到临时文件中，临时文件名为当前数据库文件名加上 .temp. 后缀（见 with_temp_suffix）加上当前时间戳的字符串表示。
     // 如果写入成功，则使用 fs::rename 方法将临时文件重命名为数据库文件，以保证写入的数据完整性。
     // 如果重命名成功，则如果当前存储策略为 PeriodicDump，则更新上一次存储的时间为当前时间。
     // 如果出现任何错误，则返回一个包含错误信息的 Result 类型。
     pub fn dump(&mut self) -> Result<()> {
+        self.write_dump().map_err(|err| err.with_op("dump"))
+    }
+
+    // dump 和 dumpdb 共用，不记录操作，使 dumpdb 返回的错误记录的是触发 dump 的操作。
+    // 成功后向读句柄发布新的快照
+    fn write_dump(&mut self) -> Result<()> {
+        self.write_file()?;
+        self.publish_snapshot();
+        Ok(())
+    }
+
+    fn write_file(&mut self) -> Result<()> {
         if let KeyValueDbDumpPolicy::NeverDump = self.dump_policy {
             return Ok(());
         }
+        let path = match &self.db_file_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
 
-        match self.serializer.serialize_db(&self.map, &self.list_map) {
+        match self.serialize() {
             Ok(ser_db) => {
-                let temp_file_path = format!(
-                    "{}.temp.{}",
-                    self.db_file_path.to_str().unwrap(),
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs()
-                );
-
-                match fs::write(&temp_file_path, ser_db) {
-                    Ok(_) => (),
-                    Err(err) => return Err(Error::new(ErrorCode::Io(err))),
-                }
-
-                match fs::rename(temp_file_path, &self.db_file_path) {
-                    Ok(_) => (),
-                    Err(err) => return Err(Error::new(ErrorCode::Io(err))),
-                }
+                write_atomically(path, &self.temp_suffix, ser_db)?;
 
                 if let KeyValueDbDumpPolicy::PeriodicDump(_dur) = self.dump_policy {
                     self.last_dump = Instant::now();
                 }
+                self.dirty = false;
+                for buffer in &mut self.write_buffers {
+                    buffer.flushed();
+                }
                 Ok(())
             }
+            Err(err_str) => Err(Error::new(ErrorCode::Serialization(err_str)).with_path(path)),
+        }
+    }
+
+    // 将整个数据库写入另一个文件，例如用于备份，不改变数据库文件的路径，也不受存储策略的影响。
+    // 与 dump 一样先写入临时文件再重命名，不会留下写了一半的文件；与直接复制数据库文件不同，
+    // 不会读到正在被 dump 替换的文件。
+    pub fn dump_to_path<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let result = match self.serialize() {
+            Ok(ser_db) => write_atomically(path.as_ref(), &self.temp_suffix, ser_db),
             Err(err_str) => Err(Error::new(ErrorCode::Serialization(err_str))),
+        };
+        result.map_err(|err| err.with_op("dump_to_path").with_path(path.as_ref()))
+    }
+
+    // dump 被中断（例如进程崩溃，或者写入、重命名失败）时，数据库文件所在的目录中会留下
+    // "<数据库文件名><with_temp_suffix 设置的后缀><时间戳>" 文件。删除其中超过 STALE_TEMP_AGE 没有被修改过的文件，返回删除的数量；
+    // 最近修改过的文件可能正在被另一个进程的 dump 写入，因此保留。纯内存数据库返回 0。
+    pub fn cleanup_temp_files(&self) -> Result<usize> {
+        let path = match &self.db_file_path {
+            Some(path) => path,
+            None => return Ok(0),
+        };
+        // 按字节比较文件名，使不是合法 UTF-8 的文件名也可以被清理
+        let prefix: Vec<u8> = match path.file_name() {
+            Some(name) => [name.as_encoded_bytes(), self.temp_suffix.as_bytes()].concat(),
+            None => return Ok(0),
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let io_error = |err: io::Error, path: &Path| {
+            Error::new(ErrorCode::Io(err))
+                .with_op("cleanup_temp_files")
+                .with_path(path)
+        };
+
+        let mut removed = 0;
+        for entry in fs::read_dir(dir).map_err(|err| io_error(err, dir))? {
+            let entry = entry.map_err(|err| io_error(err, dir))?;
+            let file_name = entry.file_name();
+            let is_temp = file_name
+                .as_encoded_bytes()
+                .strip_prefix(prefix.as_slice())
+                .is_some_and(|ts| !ts.is_empty() && ts.iter().all(u8::is_ascii_digit));
+            let is_stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= STALE_TEMP_AGE);
+            if !is_temp || !is_stale {
+                continue;
+            }
+            match fs::remove_file(entry.path()) {
+                Ok(_) => removed += 1,
+                // 同时被其他进程删除
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(io_error(err, &entry.path())),
+            }
+        }
+        Ok(removed)
+    }
+
+    // 返回一个只读句柄，它读取的是 0 号逻辑数据库在最近一次 dump 时的内容，
+    // 不需要获取 KeyValueDb 的锁，见 KeyValueDbReadHandle。只有 0 号逻辑数据库会被发布，
+    // 用 select 选择其他逻辑数据库时写入的键对读句柄不可见。NeverDump、纯内存数据库和 DumpUponRequest
+    // 在调用 dump 时更新快照。创建第一个读句柄之后，每次 dump 都会复制整个 0 号逻辑数据库；
+    // 使用 AutoDump 时每次写入都会 dump，即每次写入都会复制一次，写入频繁时应当使用 PeriodicDump。
+    pub fn read_handle(&mut self) -> KeyValueDbReadHandle {
+        if self.read_handles.is_none() {
+            self.read_handles = Some(Arc::new(Published::new(self.read_snapshot())));
+        }
+        let shared = Arc::clone(self.read_handles.as_ref().unwrap());
+        KeyValueDbReadHandle::new(shared, self.serializer.method())
+    }
+
+    fn publish_snapshot(&self) {
+        if let Some(shared) = &self.read_handles {
+            shared.publish(self.read_snapshot());
+        }
+    }
+
+    fn read_snapshot(&self) -> ReadSnapshot {
+        let (map, list_map, meta) = if self.selected == 0 {
+            (&self.map, &self.list_map, &self.meta)
+        } else {
+            match self.databases.get(&0) {
+                Some((map, list_map, meta)) => (map, list_map, meta),
+                None => return ReadSnapshot::default(),
+            }
+        };
+        ReadSnapshot {
+            map: map.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            list_map: list_map
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            meta: meta.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
         }
     }
 
+    // 返回数据库文件的路径，纯内存数据库返回 None
+    pub fn path(&self) -> Option<&Path> {
+        self.db_file_path.as_deref()
+    }
+
+    // 修改数据库文件的路径，之后的 dump 都会写入新的路径，原来的文件保持不变。
+    // 这里不会写入新的文件，数据库被视为有未写入的修改，需要时可以随后调用 dump。
+    pub fn set_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.db_file_path = Some(path.as_ref().to_path_buf());
+        self.dirty = true;
+    }
+
+    // 关闭数据库：除 NeverDump 以外的任何策略（包括 DumpUponRequest）都会执行最后一次 dump，
+    // 并返回 dump 的结果。与依赖 Drop 不同，dump 失败时调用者可以知道数据没有被保存。
+    // 之后 Drop 不会再次 dump。
+    pub fn close(mut self) -> Result<()> {
+        let result = self.dump();
+        self.dump_policy = KeyValueDbDumpPolicy::NeverDump;
+        result
+    }
+
     // 根据当前备份策略进行判断，
     // 如果是 AutoDump 策略，则直接调用 dump 函数进行备份；
     // 如果是 PeriodicDump 策略，则判断距离上次备份的时间是否超过指定的时间间隔，如果超过则进行备份，否则不进行备份。最后返回执行结果。
-    fn dumpdb(&mut self) -> Result<()> {
-        match self.dump_policy {
-            KeyValueDbDumpPolicy::AutoDump => self.dump(),
+    // 返回的错误中记录触发 dump 的操作 op 和键 key。
+    fn dumpdb(&mut self, op: &'static str, key: Option<&str>) -> Result<()> {
+        self.dirty = true;
+        let result = match self.dump_policy {
+            KeyValueDbDumpPolicy::AutoDump => self.write_dump(),
             KeyValueDbDumpPolicy::PeriodicDump(duration) => {
                 let now = Instant::now();
                 if now.duration_since(self.last_dump) > duration {
                     self.last_dump = Instant::now();
-                    self.dump()?;
+                    self.write_dump()
+                } else {
+                    Ok(())
                 }
-                Ok(())
             }
 
             _ => Ok(()),
+        };
+        result.map_err(|err| match key {
+            Some(key) => err.with_context(op, key),
+            None => err.with_op(op),
+        })
+    }
+
+    // 缓冲对匹配 pattern（与 keys_matching 相同的 glob 模式）的键的写入，例如高频更新的传感器数据：
+    // 这些写入仍然立即生效，读取总是返回最新的值，但最多每隔 flush_every 才按照存储策略 dump 一次，
+    // 而不是每次写入都 dump。只影响 set、set_with_ttl、set_raw 和 incr 这类写入普通值的方法。
+    // 没有新的写入时不会自动 dump，需要时调用 flush_writes；AutoDump 和 PeriodicDump 在 Drop 时仍然会 dump。
+    // 对同一个 pattern 再次调用时修改它的间隔。
+    pub fn buffer_writes(&mut self, pattern: &str, flush_every: Duration) {
+        match self
+            .write_buffers
+            .iter_mut()
+            .find(|buffer| buffer.pattern == pattern)
+        {
+            Some(buffer) => buffer.flush_every = flush_every,
+            None => self
+                .write_buffers
+                .push(WriteBuffer::new(pattern, flush_every)),
+        }
+    }
+
+    // 取消 buffer_writes，之后对这些键的写入恢复为按照存储策略立即 dump，还没有写入文件的修改随即 dump
+    pub fn unbuffer_writes(&mut self, pattern: &str) -> Result<()> {
+        let pending = self
+            .write_buffers
+            .iter()
+            .any(|buffer| buffer.pattern == pattern && buffer.pending);
+        self.write_buffers
+            .retain(|buffer| buffer.pattern != pattern);
+        if pending {
+            return self.dumpdb("unbuffer_writes", None);
+        }
+        Ok(())
+    }
+
+    // 按照存储策略立即 dump 所有被缓冲的写入，没有被缓冲的写入时不做任何事情
+    pub fn flush_writes(&mut self) -> Result<()> {
+        if self.write_buffers.iter().any(|buffer| buffer.pending) {
+            return self.dumpdb("flush_writes", None);
+        }
+        Ok(())
+    }
+
+    // 写入 key 之后调用：key 被 buffer_writes 缓冲时只在间隔已到时 dump，否则与 dumpdb 相同
+    fn dumpdb_buffered(&mut self, op: &'static str, key: &str) -> Result<()> {
+        let buffer = match self
+            .write_buffers
+            .iter_mut()
+            .find(|buffer| buffer.matches(key))
+        {
+            Some(buffer) => buffer,
+            None => return self.dumpdb(op, Some(key)),
+        };
+        if !buffer.is_due() {
+            buffer.pending = true;
+            self.dirty = true;
+            return Ok(());
         }
+        buffer.pending = true;
+        self.dumpdb(op, Some(key))
     }
 
     // set 方法将一个序列化后的值与一个键关联起来，并将它们存储在 KeyValueDb 实例的内部哈希表中。键的类型是字符串，而值必须实现 Serialize trait。如果指定的键已经存在于 list_map 中，则先从其中删除。然后，该方法将指定的值序列化为字节数组，并插入到内部哈希表中。如果插入成功，则将其结果包装在 Ok 中返回。
     // 否则，该方法将尝试恢复先前哈希表中该键的原始值。
     // 如果无法恢复，它将返回一个错误。
     // 此外，如果存储策略允许，该方法还将调用 dumpdb 方法，将哈希表中的更改写入磁盘。
+    // 键原来的过期时间会被清除。
     pub fn set<V>(&mut self, key: &str, value: &V) -> Result<()>
     where
         V: Serialize,
     {
-        if self.list_map.contains_key(key) {
-            self.list_map.remove(key);
-        }
+        self.set_expiring("set", key, value, None)
+    }
+
+    // 与 set 相同，但键在 ttl 之后过期。过期的键对 get、exists、iter、get_all 等方法不可见，
+    // 但在被覆盖或者删除之前仍然保存在数据库文件中。过期时间随数据库文件一起保存，load 之后仍然有效。
+    pub fn set_with_ttl<V>(&mut self, key: &str, value: &V, ttl: Duration) -> Result<()>
+    where
+        V: Serialize,
+    {
+        // ttl 大到无法表示时视为永不过期
+        let expires_at = SystemTime::now().checked_add(ttl);
+        self.set_expiring("set_with_ttl", key, value, expires_at)
+    }
+
+    fn set_expiring<V>(
+        &mut self,
+        op: &'static str,
+        key: &str,
+        value: &V,
+        expires_at: Option<SystemTime>,
+    ) -> Result<()>
+    where
+        V: Serialize,
+    {
         let ser_data = match self.serializer.serialize_data(value) {
             Ok(data) => data,
-            Err(err_str) => return Err(Error::new(ErrorCode::Serialization(err_str))),
+            Err(err_str) => {
+                return Err(Error::new(ErrorCode::Serialization(err_str)).with_context(op, key))
+            }
         };
 
+        let type_tag = type_tag::<V>();
+        self.set_serialized(op, key, ser_data, expires_at, Some(type_tag))
+    }
+
+    // 直接存储已经序列化好的数据，例如从网络上收到的数据，避免一次多余的反序列化和序列化。
+    // 调用者需要保证数据的格式与当前使用的 SerializationMethod 一致，
+    // 对于 Json 和 Yaml 这类文本格式，数据必须是合法的 UTF-8，否则返回错误。
+    pub fn set_raw(&mut self, key: &str, bytes: &[u8]) -> Result<()> {
+        if let Err(err_str) = self.serializer.check_raw(bytes) {
+            return Err(Error::new(ErrorCode::Serialization(err_str)).with_context("set_raw", key));
+        }
+
+        self.set_serialized("set_raw", key, bytes.to_vec(), None, None)
+    }
+
+    // set 和 set_raw 共用的存储逻辑，dump 失败时恢复原来的值。
+    // type_tag 只在使用 with_type_tags 时记录，否则清除键原来的类型名。
+    fn set_serialized(
+        &mut self,
+        op: &'static str,
+        key: &str,
+        ser_data: Vec<u8>,
+        expires_at: Option<SystemTime>,
+        type_tag: Option<&str>,
+    ) -> Result<()> {
+        self.check_writable(op, Some(key))?;
+        self.check_key(op, key)?;
+        self.check_value(op, key, &ser_data)?;
+        let replaced = self.size_of(key).unwrap_or(0);
+        self.reserve_memory(op, key, key.len() + ser_data.len(), replaced)?;
+        self.record_access(key);
+        let original_list = self.list_map.remove(key);
+
         let original_value = self.map.insert(String::from(key), ser_data);
-        match self.dumpdb() {
+        let original_meta = self.touch(key);
+        let type_tag = type_tag.filter(|_| self.type_tags).map(String::from);
+        if let Some(meta) = self.meta.get_mut(key) {
+            meta.expires_at = expires_at;
+            meta.type_tag = type_tag;
+        }
+        match self.dumpdb_buffered(op, key) {
             Ok(_) => Ok(()),
             Err(err) => {
+                self.restore_meta(key, original_meta);
                 match original_value {
                     None => {
                         self.map.remove(key);
@@ -257,168 +1023,1997 @@ impl KeyValueDb {
                         self.map.insert(String::from(key), orig_value.to_vec());
                     }
                 }
+                if let Some(orig_list) = original_list {
+                    self.list_map.insert(String::from(key), orig_list);
+                }
 
                 Err(err)
             }
         }
     }
 
-    pub fn get<V>(&self, key: &str) -> Option<V>
-    where
-        V: DeserializeOwned,
-    {
-        match self.map.get(key) {
-            Some(val) => self.serializer.deserialize_data::<V>(val),
-            None => None,
-        }
+    // 记录键被写入的时间，返回原来的元数据，用于 dump 失败时恢复。
+    // 已经过期的键视为新创建的键。
+    fn touch(&mut self, key: &str) -> Option<KeyMetadata> {
+        let meta = match self.meta.get(key) {
+            Some(meta) if !meta.is_expired() => meta.touched(),
+            _ => KeyMetadata::now(),
+        };
+        self.meta.insert(String::from(key), meta)
     }
 
-    pub fn exists(&self, key: &str) -> bool {
-        self.map.get(key).is_some() || self.list_map.get(key).is_some()
+    fn is_expired(&self, key: &str) -> bool {
+        self.meta.get(key).is_some_and(KeyMetadata::is_expired)
     }
 
-    pub fn get_all(&self) -> Vec<String> {
-        [
-            self.map.keys().cloned().collect::<Vec<String>>(),
-            self.list_map.keys().cloned().collect::<Vec<String>>(),
-        ]
-        .concat()
+    // 键当前的过期时间，键不存在或者已经过期时返回 None
+    fn deadline(&self, key: &str) -> Option<SystemTime> {
+        match self.meta.get(key) {
+            Some(meta) if !meta.is_expired() => meta.expires_at,
+            _ => None,
+        }
     }
 
-    pub fn total_keys(&self) -> usize {
-        self.map.iter().len() + self.list_map.iter().len()
+    // 键的序列化后的值，过期的键视为不存在
+    fn value(&self, key: &str) -> Option<&Vec<u8>> {
+        if self.is_expired(key) {
+            return None;
+        }
+        let value = self.map.get(key);
+        if value.is_some() {
+            self.record_access(key);
+        }
+        value
     }
 
-    pub fn rem(&mut self, key: &str) -> Result<bool> {
-        let remove_map = match self.map.remove(key) {
-            None => None,
-            Some(val) => match self.dumpdb() {
-                Ok(_) => Some(val),
-                Err(err) => {
-                    self.map.insert(String::from(key), val);
-                    return Err(err);
-                }
-            },
-        };
-
-        let remove_list = match self.list_map.remove(key) {
-            None => None,
-            Some(list) => match self.dumpdb() {
-                Ok(_) => Some(list),
-                Err(err) => {
-                    self.list_map.insert(String::from(key), list);
-                    return Err(err);
-                }
-            },
-        };
-
-        Ok(remove_map.is_some() || remove_list.is_some())
+    // 与 value 相同，但用于列表
+    fn list(&self, name: &str) -> Option<&DbList> {
+        if self.is_expired(name) {
+            return None;
+        }
+        let list = self.list_map.get(name);
+        if list.is_some() {
+            self.record_access(name);
+        }
+        list
     }
 
-
-    pub fn lcreate(&mut self, name: &str) -> Result<KeyValueDbListExtender> {
-        let new_list: Vec<Vec<u8>> = Vec::new();
-        if self.map.contains_key(name) {
-            self.map.remove(name);
+    // 返回键（普通值或者列表）剩余的存活时间，键不存在或者没有过期时间时返回 None
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        if !self.exists(key) {
+            return None;
         }
-        self.list_map.insert(String::from(name), new_list);
-        self.dumpdb()?;
-        Ok(KeyValueDbListExtender {
-            db: self,
-            list_name: String::from(name),
-        })
+        let expires_at = self.meta.get(key)?.expires_at?;
+        Some(
+            expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default(),
+        )
     }
 
-    pub fn lexists(&self, name: &str) -> bool {
-        self.list_map.get(name).is_some()
+    // 为已经存在的键（普通值或者列表）设置过期时间，之前的过期时间会被覆盖，键不存在时返回 false
+    pub fn expire(&mut self, key: &str, ttl: Duration) -> Result<bool> {
+        if !self.exists(key) {
+            return Ok(false);
+        }
+        // ttl 大到无法表示时视为永不过期
+        self.set_deadline("expire", key, SystemTime::now().checked_add(ttl))?;
+        Ok(true)
     }
 
-    pub fn ladd<V>(&mut self, name: &str, value: &V) -> Option<KeyValueDbListExtender>
-    where
-        V: Serialize,
-    {
-        self.lextend(name, &[value])
+    // 清除键的过期时间，键不存在或者没有过期时间时返回 false
+    pub fn persist(&mut self, key: &str) -> Result<bool> {
+        if self.ttl(key).is_none() {
+            return Ok(false);
+        }
+        self.set_deadline("persist", key, None)?;
+        Ok(true)
     }
 
-    pub fn lextend<'a, V, I>(&mut self, name: &str, seq: I) -> Option<KeyValueDbListExtender>
-    where
-        V: 'a + Serialize,
-        I: IntoIterator<Item = &'a V>,
-    {
-        let serializer = &self.serializer;
-        match self.list_map.get_mut(name) {
-            Some(list) => {
-                let original_len = list.len();
-                let serialized: Vec<Vec<u8>> = seq
-                    .into_iter()
-                    .map(|x| serializer.serialize_data(x).unwrap())
-                    .collect();
-                list.extend(serialized);
-                match self.dumpdb() {
-                    Ok(_) => (),
-                    Err(_) => {
-                        let same_list = self.list_map.get_mut(name).unwrap();
-                        same_list.truncate(original_len);
-                        return None;
-                    }
-                }
-                Some(KeyValueDbListExtender {
-                    db: self,
-                    list_name: String::from(name),
-                })
+    // expire 和 persist 共用，只修改过期时间，不改变 modified。dump 失败时恢复原来的过期时间
+    fn set_deadline(
+        &mut self,
+        op: &'static str,
+        key: &str,
+        expires_at: Option<SystemTime>,
+    ) -> Result<()> {
+        self.check_writable(op, Some(key))?;
+        let original_meta = self.meta.get(key).cloned();
+        // 旧版本的数据库文件中的键没有元数据
+        let mut meta = original_meta.clone().unwrap_or_else(KeyMetadata::now);
+        meta.expires_at = expires_at;
+        self.meta.insert(String::from(key), meta);
+        match self.dumpdb(op, Some(key)) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                self.restore_meta(key, original_meta);
+                Err(err)
             }
+        }
+    }
 
-            None => None,
+    fn restore_meta(&mut self, key: &str, meta: Option<KeyMetadata>) {
+        match meta {
+            Some(meta) => {
+                self.meta.insert(String::from(key), meta);
+            }
+            None => {
+                self.meta.remove(key);
+            }
         }
     }
 
-    pub fn lget<V>(&self, name: &str, pos: usize) -> Option<V>
+    pub fn get<V>(&self, key: &str) -> Option<V>
     where
         V: DeserializeOwned,
     {
-        match self.list_map.get(name) {
-            Some(list) => match list.get(pos) {
-                Some(val) => self.serializer.deserialize_data::<V>(val),
+        match self.value(key) {
+            Some(val) => self.serializer.deserialize_data::<V>(val),
+            None => None,
+        }
+    }
+
+    // 与 get 相同，但区分键不存在和值无法反序列化为 V：键不存在（或者已经过期）时返回 Ok(None)，
+    // 无法反序列化时返回 Deserialization 错误，错误信息中包含键名。键是一个列表时也返回 Deserialization 错误。
+    pub fn try_get<V>(&self, key: &str) -> Result<Option<V>>
+    where
+        V: DeserializeOwned,
+    {
+        if self.list_map.contains_key(key) && !self.is_expired(key) {
+            return Err(Error::new(ErrorCode::Deserialization(format!(
+                "'{}' is a list, not a value",
+                key
+            )))
+            .with_context("try_get", key));
+        }
+        match self.value(key) {
+            Some(val) => match self.serializer.deserialize_data::<V>(val) {
+                Some(value) => Ok(Some(value)),
+                None => Err(Error::new(ErrorCode::Deserialization(format!(
+                    "Cannot deserialize the value of '{}' as {}",
+                    key,
+                    std::any::type_name::<V>()
+                )))
+                .with_context("try_get", key)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // 与 try_get 相同，但键不存在或者已经过期时返回 KeyNotFound 错误，用于必须存在的键
+    pub fn require<V>(&self, key: &str) -> Result<V>
+    where
+        V: DeserializeOwned,
+    {
+        match self.try_get(key)? {
+            Some(value) => Ok(value),
+            None => Err(Error::new(ErrorCode::KeyNotFound(format!(
+                "Key '{}' doesn't exist",
+                key
+            )))
+            .with_context("require", key)),
+        }
+    }
+
+    // 与 try_get 相同，但键记录了类型名（见 with_type_tags）并且与 V 不一致时返回 TypeMismatch 错误，
+    // 即使值碰巧可以反序列化为 V，例如把写入时的 u64 读取为 i32
+    pub fn get_checked<V>(&self, key: &str) -> Result<Option<V>>
+    where
+        V: DeserializeOwned,
+    {
+        let stored = match self.meta.get(key) {
+            Some(meta) if !meta.is_expired() => meta.type_tag.as_deref(),
+            _ => None,
+        };
+        if let Some(stored) = stored {
+            let requested = type_tag::<V>();
+            if stored != requested {
+                return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                    "'{}' was stored as {} but read as {}",
+                    key, stored, requested
+                )))
+                .with_context("get_checked", key));
+            }
+        }
+        self.try_get(key)
+    }
+
+    // 将键的值加上 delta 并返回新的值，键不存在时视为 0。整个操作只会触发一次 dump。
+    // 值可以是整数，也可以是内容为整数的字符串（例如通过服务端的 SET 写入的值），写回时保持原来的类型。
+    // 值不是整数或者键是一个列表时返回错误，结果溢出时返回 LimitExceeded 错误，值都保持不变。
+    pub fn incr(&mut self, key: &str, delta: i64) -> Result<i64> {
+        if self.list_map.contains_key(key) {
+            return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                "'{}' is a list, not an integer",
+                key
+            )))
+            .with_context("incr", key));
+        }
+
+        let (current, as_string) = match self.value(key) {
+            None => (0, false),
+            Some(val) => match self.serializer.deserialize_data::<i64>(val) {
+                Some(num) => (num, false),
+                None => match self
+                    .serializer
+                    .deserialize_data::<String>(val)
+                    .and_then(|text| text.parse::<i64>().ok())
+                {
+                    Some(num) => (num, true),
+                    None => {
+                        return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                            "The value of '{}' is not an integer",
+                            key
+                        )))
+                        .with_context("incr", key))
+                    }
+                },
+            },
+        };
+
+        let new_value = match current.checked_add(delta) {
+            Some(num) => num,
+            None => {
+                return Err(Error::new(ErrorCode::LimitExceeded(format!(
+                    "Incrementing '{}' would overflow",
+                    key
+                )))
+                .with_context("incr", key))
+            }
+        };
+        // 与 set 不同，incr 保留键原来的过期时间
+        let expires_at = self.deadline(key);
+        if as_string {
+            self.set_expiring("incr", key, &new_value.to_string(), expires_at)?;
+        } else {
+            self.set_expiring("incr", key, &new_value, expires_at)?;
+        }
+        Ok(new_value)
+    }
+
+    // 与 incr 相同，但减去 delta
+    pub fn decr(&mut self, key: &str, delta: i64) -> Result<i64> {
+        match delta.checked_neg() {
+            Some(delta) => self.incr(key, delta),
+            None => Err(Error::new(ErrorCode::LimitExceeded(format!(
+                "Decrementing '{}' would overflow",
+                key
+            )))
+            .with_context("decr", key)),
+        }
+    }
+
+    // 在字符串值的末尾追加 suffix，返回追加后的长度（字节数），键不存在时视为空字符串。
+    // 整个操作只会触发一次 dump，保留键原来的过期时间。键是一个列表或者值不是字符串时返回错误，值保持不变。
+    pub fn append(&mut self, key: &str, suffix: &str) -> Result<usize> {
+        let mut value = self.get_string("append", key)?.unwrap_or_default();
+        value.push_str(suffix);
+        let expires_at = self.deadline(key);
+        self.set_expiring("append", key, &value, expires_at)?;
+        Ok(value.len())
+    }
+
+    // 返回字符串值的长度（字节数），键不存在时返回 0。键是一个列表或者值不是字符串时返回错误。
+    pub fn strlen(&self, key: &str) -> Result<usize> {
+        Ok(self
+            .get_string("strlen", key)?
+            .map_or(0, |value| value.len()))
+    }
+
+    // 基于令牌桶的限流：在任意长度为 per 的时间内最多允许 max 个请求，也允许一次突发 max 个请求，
+    // 例如 db.rate_limit("ip:1.2.3.4", 100, Duration::from_secs(60))。
+    // 令牌桶作为 key 的值保存在数据库中，随数据库文件一起保存，重启后继续生效。
+    // 只有请求被允许时才会写入并触发一次 dump；桶重新装满之后键会自动过期。
+    // max 或者 per 为 0 时返回 InvalidArgument 错误，key 是一个列表或者它的值不是令牌桶时返回 TypeMismatch 错误。
+    pub fn rate_limit(&mut self, key: &str, max: u64, per: Duration) -> Result<RateLimitDecision> {
+        if max == 0 || per.is_zero() {
+            return Err(Error::new(ErrorCode::InvalidArgument(String::from(
+                "max and per must be greater than zero",
+            )))
+            .with_context("rate_limit", key));
+        }
+        if self.list_map.contains_key(key) {
+            return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                "'{}' is a list, not a rate limiter",
+                key
+            )))
+            .with_context("rate_limit", key));
+        }
+
+        let now = SystemTime::now();
+        let mut bucket = match self.value(key) {
+            Some(val) => match self.serializer.deserialize_data::<TokenBucket>(val) {
+                Some(bucket) => bucket,
+                None => {
+                    return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                        "The value of '{}' is not a rate limiter",
+                        key
+                    )))
+                    .with_context("rate_limit", key))
+                }
+            },
+            None => TokenBucket::full(max, now),
+        };
+
+        let decision = bucket.take(max, per, now);
+        if decision.allowed {
+            let expires_at = now.checked_add(bucket.refill_time(max, per));
+            self.set_expiring("rate_limit", key, &bucket, expires_at)?;
+        }
+        Ok(decision)
+    }
+
+    // 尝试获取名为 name 的锁，租约在 ttl 之后到期。成功时返回这次加锁的隔离令牌，锁已经被持有时返回 None。
+    // 同一把锁的令牌总是递增的，释放锁时需要提供它。锁的状态作为 name 的值保存在数据库中，
+    // 例如多台主机上的定时任务可以通过同一个服务端协调，保证同一时间只有一个任务在运行。
+    // ttl 大到无法表示时返回 InvalidArgument 错误，name 是一个列表或者它的值不是锁时返回 TypeMismatch 错误，
+    // 令牌已经达到 u64::MAX 时返回 LimitExceeded 错误，锁保持不变。
+    pub fn lock(&mut self, name: &str, ttl: Duration) -> Result<Option<u64>> {
+        let now = SystemTime::now();
+        let token = match self.lock_state("lock", name)? {
+            Some(state) if state.is_held(now) => return Ok(None),
+            Some(state) => match state.token.checked_add(1) {
+                Some(token) => token,
+                None => {
+                    return Err(Error::new(ErrorCode::LimitExceeded(format!(
+                        "The fencing token of lock '{}' would overflow",
+                        name
+                    )))
+                    .with_context("lock", name))
+                }
+            },
+            None => 1,
+        };
+        let held_until = match now.checked_add(ttl) {
+            Some(held_until) => held_until,
+            None => {
+                return Err(Error::new(ErrorCode::InvalidArgument(String::from(
+                    "ttl is too large",
+                )))
+                .with_context("lock", name))
+            }
+        };
+        let state = LockState {
+            token,
+            held_until: Some(held_until),
+        };
+        self.set_expiring("lock", name, &state, None)?;
+        Ok(Some(token))
+    }
+
+    // 释放 lock 返回 token 时获取的锁。锁已经过期、已经被释放或者已经被其他人重新获取时返回 false。
+    pub fn unlock(&mut self, name: &str, token: u64) -> Result<bool> {
+        match self.lock_state("unlock", name)? {
+            Some(state) if state.token == token && state.is_held(SystemTime::now()) => {
+                let released = LockState {
+                    token,
+                    held_until: None,
+                };
+                self.set_expiring("unlock", name, &released, None)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // 延长 lock 返回 token 时获取的锁的租约，使锁在 ttl 之后过期，令牌保持不变。
+    // 锁已经过期、已经被释放或者已经被其他人重新获取时不做任何修改并返回 false。
+    pub fn renew_lock(&mut self, name: &str, token: u64, ttl: Duration) -> Result<bool> {
+        let now = SystemTime::now();
+        match self.lock_state("renew_lock", name)? {
+            Some(state) if state.token == token && state.is_held(now) => {
+                let held_until = match now.checked_add(ttl) {
+                    Some(held_until) => held_until,
+                    None => {
+                        return Err(Error::new(ErrorCode::InvalidArgument(String::from(
+                            "ttl is too large",
+                        )))
+                        .with_context("renew_lock", name))
+                    }
+                };
+                let renewed = LockState {
+                    token,
+                    held_until: Some(held_until),
+                };
+                self.set_expiring("renew_lock", name, &renewed, None)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // lock、renew_lock 和 unlock 共用：读取锁的状态，键不存在时返回 None
+    fn lock_state(&self, op: &'static str, name: &str) -> Result<Option<LockState>> {
+        if self.list_map.contains_key(name) {
+            return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                "'{}' is a list, not a lock",
+                name
+            )))
+            .with_context(op, name));
+        }
+        match self.value(name) {
+            Some(val) => match self.serializer.deserialize_data::<LockState>(val) {
+                Some(state) => Ok(Some(state)),
+                None => Err(Error::new(ErrorCode::TypeMismatch(format!(
+                    "The value of '{}' is not a lock",
+                    name
+                )))
+                .with_context(op, name)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // 将 value 以优先级 priority 加入名为 name 的优先队列，队列不存在时创建。
+    // 队列作为一个普通的值保存，整个队列在每次修改时重新序列化，适合元素数量不多的队列。
+    // 键原来的过期时间保持不变。
+    pub fn pqpush<V>(&mut self, name: &str, priority: i64, value: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        let ser_data = match self.serializer.serialize_data(value) {
+            Ok(data) => data,
+            Err(err_str) => {
+                return Err(
+                    Error::new(ErrorCode::Serialization(err_str)).with_context("pqpush", name)
+                )
+            }
+        };
+        let mut queue = self.pq_state("pqpush", name)?.unwrap_or_default();
+        queue.push(priority, ser_data);
+        self.set_expiring("pqpush", name, &queue, self.deadline(name))
+    }
+
+    // 取出优先级最高的元素，优先级相同时先加入的元素先出队；队列不存在或者为空时返回 None。
+    // 元素无法反序列化为 V 时返回 Deserialization 错误，队列保持不变。
+    pub fn pqpop_max<V>(&mut self, name: &str) -> Result<Option<V>>
+    where
+        V: DeserializeOwned,
+    {
+        let mut queue = match self.pq_state("pqpop_max", name)? {
+            Some(queue) => queue,
+            None => return Ok(None),
+        };
+        let entry = match queue.heap.pop() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let value = self.pq_value("pqpop_max", name, &entry.value)?;
+        self.set_expiring("pqpop_max", name, &queue, self.deadline(name))?;
+        Ok(Some(value))
+    }
+
+    // 返回优先级最高的元素但不取出
+    pub fn pqpeek_max<V>(&self, name: &str) -> Result<Option<V>>
+    where
+        V: DeserializeOwned,
+    {
+        match self.pq_state("pqpeek_max", name)? {
+            Some(queue) => match queue.heap.peek() {
+                Some(entry) => self.pq_value("pqpeek_max", name, &entry.value).map(Some),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // 优先队列中元素的数量，队列不存在时返回 0
+    pub fn pqlen(&self, name: &str) -> Result<usize> {
+        Ok(self
+            .pq_state("pqlen", name)?
+            .map_or(0, |queue| queue.heap.len()))
+    }
+
+    // 读取优先队列，键不存在时返回 None
+    fn pq_state(&self, op: &'static str, name: &str) -> Result<Option<PriorityQueue>> {
+        if self.list_map.contains_key(name) {
+            return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                "'{}' is a list, not a priority queue",
+                name
+            )))
+            .with_context(op, name));
+        }
+        match self.value(name) {
+            Some(val) => match self.serializer.deserialize_data::<PriorityQueue>(val) {
+                Some(queue) => Ok(Some(queue)),
+                None => Err(Error::new(ErrorCode::TypeMismatch(format!(
+                    "The value of '{}' is not a priority queue",
+                    name
+                )))
+                .with_context(op, name)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn pq_value<V>(&self, op: &'static str, name: &str, ser_data: &[u8]) -> Result<V>
+    where
+        V: DeserializeOwned,
+    {
+        match self.serializer.deserialize_data::<V>(ser_data) {
+            Some(value) => Ok(value),
+            None => Err(Error::new(ErrorCode::Deserialization(format!(
+                "Cannot deserialize an element of priority queue '{}' as {}",
+                name,
+                std::any::type_name::<V>()
+            )))
+            .with_context(op, name)),
+        }
+    }
+
+    // 创建一个在 at 到期的定时器，到期后由 take_due（例如 KeyValueDbScheduler）取出 payload 交给调用者处理。
+    // 定时器保存在键 "schedule:<key>" 中，随数据库文件一起保存，进程重启之后仍然有效；
+    // 已经存在的同名定时器被替换。at 已经过去时在下一次 take_due 时触发。
+    pub fn schedule<V>(&mut self, key: &str, at: SystemTime, payload: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.schedule_entry("schedule", key, at, None, payload)
+    }
+
+    // 与 schedule 相同，但到期后把 payload 追加到列表 list 的末尾，列表不存在时先创建，
+    // 例如用于延迟任务：db.schedule_to_list("retry:42", at, "jobs", &job)
+    pub fn schedule_to_list<V>(
+        &mut self,
+        key: &str,
+        at: SystemTime,
+        list: &str,
+        payload: &V,
+    ) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.check_key("schedule_to_list", list)?;
+        self.schedule_entry("schedule_to_list", key, at, Some(list), payload)
+    }
+
+    fn schedule_entry<V>(
+        &mut self,
+        op: &'static str,
+        key: &str,
+        at: SystemTime,
+        target: Option<&str>,
+        payload: &V,
+    ) -> Result<()>
+    where
+        V: Serialize,
+    {
+        let payload = match self.serializer.serialize_data(payload) {
+            Ok(data) => data,
+            Err(err_str) => {
+                return Err(Error::new(ErrorCode::Serialization(err_str)).with_context(op, key))
+            }
+        };
+        self.check_value(op, key, &payload)?;
+        let entry = ScheduledEntry {
+            due: at,
+            target: target.map(String::from),
+            payload,
+        };
+        self.set_expiring(op, &schedule_key(key), &entry, None)
+    }
+
+    // 删除还没有到期的定时器，定时器不存在时返回 false
+    pub fn unschedule(&mut self, key: &str) -> Result<bool> {
+        self.rem(&schedule_key(key))
+    }
+
+    // 定时器的到期时间，定时器不存在时返回 None
+    pub fn scheduled_at(&self, key: &str) -> Option<SystemTime> {
+        self.get::<ScheduledEntry>(&schedule_key(key))
+            .map(|entry| entry.due)
+    }
+
+    // 触发所有已经到期的定时器，按照到期时间的先后顺序：schedule_to_list 的 payload 被追加到目标列表，
+    // schedule 的 payload 反序列化为 V 之后与定时器的键一起返回。被触发的定时器从数据库中删除，
+    // 整个操作只会触发一次 dump，dump 失败时恢复所有的定时器和列表。
+    // 无法反序列化为 V 的定时器和目标是一个普通值的定时器保持不变，可以使用 unschedule 删除。
+    pub fn take_due<V>(&mut self) -> Result<Vec<(String, V)>>
+    where
+        V: DeserializeOwned,
+    {
+        self.check_writable("take_due", None)?;
+        let now = SystemTime::now();
+        let mut due: Vec<(String, ScheduledEntry)> = self
+            .keys_with_prefix(SCHEDULE_PREFIX)
+            .filter_map(|full_key| {
+                let val = self.map.get(full_key)?;
+                let entry = self.serializer.deserialize_data::<ScheduledEntry>(val)?;
+                Some((full_key.clone(), entry))
+            })
+            .filter(|(_, entry)| entry.due <= now)
+            .collect();
+        due.sort_by_key(|(_, entry)| entry.due);
+
+        let mut fired = Vec::new();
+        let mut moved = Vec::new();
+        for (full_key, entry) in due {
+            match entry.target {
+                Some(target) => {
+                    if self.value(&target).is_none() {
+                        moved.push((full_key, target, entry.payload));
+                    }
+                }
+                None => {
+                    if let Some(value) = self.serializer.deserialize_data::<V>(&entry.payload) {
+                        fired.push((full_key, value));
+                    }
+                }
+            }
+        }
+        if fired.is_empty() && moved.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // 目标列表被修改之前的状态，用于 dump 失败时恢复
+        let mut original_targets: HashMap<String, OriginalKey> = HashMap::new();
+        let mut removed = Vec::new();
+        for (full_key, target, payload) in moved {
+            if !original_targets.contains_key(&target) {
+                let mut original: OriginalKey = (
+                    None,
+                    self.list_map.get(&target).cloned(),
+                    self.meta.get(&target).cloned(),
+                );
+                // 目标不存在、已经过期或者是一个已经过期的普通值时创建新的列表
+                if self.list(&target).is_none() {
+                    original.0 = self.map.remove(&target);
+                    self.list_map.insert(target.clone(), DbList::new());
+                    self.meta.insert(target.clone(), KeyMetadata::now());
+                }
+                original_targets.insert(target.clone(), original);
+            }
+            if let Some(list) = self.list_map.get_mut(&target) {
+                list.push_back(payload);
+            }
+            self.touch(&target);
+            removed.push(full_key);
+        }
+        removed.extend(fired.iter().map(|(full_key, _)| full_key.clone()));
+        let removed: Vec<(String, OriginalKey)> = removed
+            .into_iter()
+            .map(|key| {
+                let original = (self.map.remove(&key), None, self.meta.remove(&key));
+                (key, original)
+            })
+            .collect();
+
+        match self.dumpdb("take_due", None) {
+            Ok(_) => Ok(fired
+                .into_iter()
+                .map(|(full_key, value)| (String::from(&full_key[SCHEDULE_PREFIX.len()..]), value))
+                .collect()),
+            Err(err) => {
+                for (target, (value, list, meta)) in original_targets {
+                    self.list_map.remove(&target);
+                    if let Some(value) = value {
+                        self.map.insert(target.clone(), value);
+                    }
+                    if let Some(list) = list {
+                        self.list_map.insert(target.clone(), list);
+                    }
+                    self.restore_meta(&target, meta);
+                }
+                for (key, (value, _, meta)) in removed {
+                    if let Some(value) = value {
+                        self.map.insert(key.clone(), value);
+                    }
+                    self.restore_meta(&key, meta);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // append 和 strlen 共用：读取字符串值，键不存在时返回 None
+    fn get_string(&self, op: &'static str, key: &str) -> Result<Option<String>> {
+        if self.list_map.contains_key(key) {
+            return Err(Error::new(ErrorCode::TypeMismatch(format!(
+                "'{}' is a list, not a string",
+                key
+            )))
+            .with_context(op, key));
+        }
+        match self.value(key) {
+            Some(val) => match self.serializer.deserialize_data::<String>(val) {
+                Some(value) => Ok(Some(value)),
+                None => Err(Error::new(ErrorCode::TypeMismatch(format!(
+                    "The value of '{}' is not a string",
+                    key
+                )))
+                .with_context(op, key)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // 读取键的当前值（不存在时为 None），交给 f 处理后写回：f 返回 Some 时存储新值，返回 None 时删除该键。
+    // 如果键存在但无法反序列化为 V，则不会调用 f，直接返回一个 Serialization 错误。
+    // 与 set 一样，dump 失败时会恢复原来的值。写回时保留键原来的过期时间。
+    pub fn update<V, F>(&mut self, key: &str, f: F) -> Result<()>
+    where
+        V: Serialize + DeserializeOwned,
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        self.check_writable("update", Some(key))?;
+        let current = match self.value(key) {
+            Some(val) => match self.serializer.deserialize_data::<V>(val) {
+                Some(value) => Some(value),
+                None => {
+                    return Err(Error::new(ErrorCode::Serialization(format!(
+                        "Cannot deserialize the value of '{}'",
+                        key
+                    )))
+                    .with_context("update", key))
+                }
+            },
+            None => None,
+        };
+
+        match f(current) {
+            Some(value) => {
+                let expires_at = self.deadline(key);
+                self.set_expiring("update", key, &value, expires_at)
+            }
+            None => match self.map.remove(key) {
+                Some(val) => {
+                    let meta = self.meta.remove(key);
+                    match self.dumpdb("update", Some(key)) {
+                        Ok(_) => Ok(()),
+                        Err(err) => {
+                            self.map.insert(String::from(key), val);
+                            self.restore_meta(key, meta);
+                            Err(err)
+                        }
+                    }
+                }
+                None => Ok(()),
+            },
+        }
+    }
+
+    // 类似于 HashMap::entry，返回键对应的 entry，用于方便地实现"读取-修改-写回"的操作
+    pub fn entry(&mut self, key: &str) -> KeyValueDbEntry<'_> {
+        if self.value(key).is_some() {
+            KeyValueDbEntry::Occupied(KeyValueDbOccupiedEntry {
+                db: self,
+                key: String::from(key),
+            })
+        } else {
+            KeyValueDbEntry::Vacant(KeyValueDbVacantEntry {
+                db: self,
+                key: String::from(key),
+            })
+        }
+    }
+
+    // 类似于 Redis 的 SELECT，返回编号为 index 的逻辑数据库，可以像 KeyValueDb 一样使用。
+    // 每个逻辑数据库都有独立的键，所有的逻辑数据库一起保存在同一个数据库文件中，
+    // 未选择过的数据库 0 就是 KeyValueDb 本身。返回值被 drop 后恢复为原来的数据库。
+    pub fn database(&mut self, index: usize) -> KeyValueDbDatabase<'_> {
+        let previous = self.selected;
+        self.select(index);
+        KeyValueDbDatabase { db: self, previous }
+    }
+
+    // 返回所有不为空的逻辑数据库的编号，按从小到大的顺序
+    pub fn databases(&self) -> Vec<usize> {
+        let mut indexes: Vec<usize> = self.databases.keys().copied().collect();
+        if self.map.len() + self.list_map.len() > 0 {
+            indexes.push(self.selected);
+            indexes.sort();
+        }
+        indexes
+    }
+
+    // 切换到编号为 index 的逻辑数据库，当前数据库的数据移到 databases 中，为空时直接丢弃
+    pub(crate) fn select(&mut self, index: usize) {
+        if index == self.selected {
+            return;
+        }
+        let (map, list_map, meta) = self.databases.remove(&index).unwrap_or_else(|| {
+            (
+                self.map.new_like(),
+                self.list_map.new_like(),
+                self.meta.new_like(),
+            )
+        });
+        let current = (
+            mem::replace(&mut self.map, map),
+            mem::replace(&mut self.list_map, list_map),
+            mem::replace(&mut self.meta, meta),
+        );
+        if current.0.len() + current.1.len() > 0 {
+            self.databases.insert(self.selected, current);
+        }
+        self.selected = index;
+    }
+
+    // 返回名为 name 的命名空间，通过它读写的键会自动加上 "name:" 前缀，
+    // 使不相关的模块可以共用一个数据库文件而不必担心键名冲突。
+    // name 按原样写入前缀，因此名为 "a:b" 的命名空间与名为 "a" 的命名空间有重叠，见 bucket_escaped。
+    pub fn bucket(&mut self, name: &str) -> KeyValueDbBucket<'_> {
+        KeyValueDbBucket {
+            db: self,
+            name: String::from(name),
+            prefix: format!("{}{}", name, keys::SEPARATOR),
+        }
+    }
+
+    // 与 bucket 相同，但前缀中的 name 用 keys::escape 转义，例如名为 "a:b" 的命名空间的前缀是 "a%3Ab:"，
+    // 不会与其他命名空间重叠。name 满足 keys::is_safe 时两者的前缀相同；否则 bucket 写入的已有数据
+    // 不会出现在 bucket_escaped 返回的命名空间中，需要先用 rename 移动到新的前缀下。
+    pub fn bucket_escaped(&mut self, name: &str) -> KeyValueDbBucket<'_> {
+        KeyValueDbBucket {
+            db: self,
+            name: String::from(name),
+            prefix: format!("{}{}", keys::escape(name), keys::SEPARATOR),
+        }
+    }
+
+    // 返回以 name 为键的持久化任务队列，队列中的任务的 payload 类型为 V，
+    // 默认被领取 5 次仍然没有完成的任务会被移入死信队列，可以用 with_max_attempts 修改
+    pub fn job_queue<V>(&mut self, name: &str) -> KeyValueDbJobQueue<'_, V>
+    where
+        V: Serialize + DeserializeOwned + Clone,
+    {
+        KeyValueDbJobQueue {
+            db: self,
+            name: String::from(name),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            payload: PhantomData,
+        }
+    }
+
+    // 与 get 相同，但在键不存在或者无法反序列化时返回 V::default()
+    pub fn get_or_default<V>(&self, key: &str) -> V
+    where
+        V: DeserializeOwned + Default,
+    {
+        self.get(key).unwrap_or_default()
+    }
+
+    // 与 get 相同，但在键不存在或者无法反序列化时返回 fallback
+    pub fn get_or<V>(&self, key: &str, fallback: V) -> V
+    where
+        V: DeserializeOwned,
+    {
+        self.get(key).unwrap_or(fallback)
+    }
+
+    // 返回键对应的序列化后的原始字节数据，不进行反序列化，
+    // 数据的格式取决于当前使用的 SerializationMethod。
+    pub fn get_raw(&self, key: &str) -> Option<&[u8]> {
+        self.value(key).map(|val| val.as_slice())
+    }
+
+    // 一次性获取多个键的值，返回以键为索引的哈希表，
+    // 不存在或者无法反序列化的键对应的值为 None。
+    pub fn get_multiple<V>(&self, keys: &[&str]) -> HashMap<String, Option<V>>
+    where
+        V: DeserializeOwned,
+    {
+        keys.iter()
+            .map(|key| (String::from(*key), self.get::<V>(key)))
+            .collect()
+    }
+
+    // 把所有没有过期的普通键值对反序列化为 V 之后放入 HashMap 返回，便于与使用标准库容器的代码交换数据。
+    // 列表不包括在内。任何一个值无法反序列化为 V 时返回 Deserialization 错误，错误信息中包含键名。
+    pub fn to_hashmap<V>(&self) -> Result<HashMap<String, V>>
+    where
+        V: DeserializeOwned,
+    {
+        let mut result = HashMap::with_capacity(self.map.len());
+        for (key, val) in self.map.iter() {
+            if self.is_expired(key) {
+                continue;
+            }
+            match self.serializer.deserialize_data::<V>(val) {
+                Some(value) => {
+                    result.insert(key.clone(), value);
+                }
+                None => {
+                    return Err(Error::new(ErrorCode::Deserialization(format!(
+                        "Cannot deserialize the value of '{}' as {}",
+                        key,
+                        std::any::type_name::<V>()
+                    )))
+                    .with_context("to_hashmap", key))
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // 返回键（普通值或者列表）的创建时间和最近一次写入的时间，键不存在时返回 None。
+    // 旧版本的数据库文件没有保存元数据，其中的键在下一次被写入之前也返回 None。
+    pub fn key_info(&self, key: &str) -> Option<KeyMetadata> {
+        if !self.exists(key) {
+            return None;
+        }
+        self.meta.get(key).cloned()
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        (self.map.contains_key(key) || self.list_map.contains_key(key)) && !self.is_expired(key)
+    }
+
+    pub fn get_all(&self) -> Vec<String> {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .filter(|key| !self.is_expired(key))
+            .cloned()
+            .collect()
+    }
+
+    // 返回所有匹配通配符 pattern 的键（包括普通值和列表），'*' 匹配任意多个字符，'?' 匹配一个字符，
+    // 例如 "session:*"
+    pub fn keys_matching(&self, pattern: &str) -> Vec<String> {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .filter(|key| glob_match(pattern, key) && !self.is_expired(key))
+            .cloned()
+            .collect()
+    }
+
+    // 类似于 Redis 的 SCAN，分批遍历所有的键（包括普通值和列表），避免像 get_all 那样一次性复制所有的键。
+    // 第一次调用时 cursor 为 "0"，之后每次传入上一次返回的 cursor，直到返回的 cursor 为 "0"。
+    // 每次最多返回 count 个键，键按字典序返回。遍历期间一直存在的键一定会被返回且只返回一次，
+    // 遍历期间新增或删除的键可能返回也可能不返回。
+    // 使用 with_ordered_keys 时每次调用只需要读取 count 个键；否则每次调用都需要检查所有的键。
+    // cursor 不是由 scan 返回的字符串时返回 InvalidArgument 错误。
+    pub fn scan(&self, cursor: &str, count: usize) -> Result<(String, Vec<String>)> {
+        let after = match decode_cursor(cursor) {
+            Some(after) => after,
+            None => {
+                return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                    "Invalid cursor '{}'",
+                    cursor
+                )))
+                .with_op("scan"))
+            }
+        };
+        let start = match &after {
+            Some(key) => Bound::Excluded(key.as_str()),
+            None => Bound::Unbounded,
+        };
+
+        let count = count.max(1);
+        let mut keys = self.map.keys_after(start, count);
+        keys.extend(self.list_map.keys_after(start, count));
+        keys.sort();
+        keys.truncate(count);
+
+        let next_cursor = match keys.last() {
+            Some(last) if keys.len() == count => encode_cursor(last),
+            _ => String::from("0"),
+        };
+        // 过期的键不返回，但仍然用于计算 cursor，因此一次可能返回少于 count 个键
+        let keys = keys
+            .into_iter()
+            .filter(|key| !self.is_expired(key))
+            .cloned()
+            .collect();
+        Ok((next_cursor, keys))
+    }
+
+    // 与 exists 一样，已经过期但还没有被清理的键不计入
+    pub fn total_keys(&self) -> usize {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .filter(|key| !self.is_expired(key))
+            .count()
+    }
+
+    // 所有以 prefix 开头的键（包括普通值和列表）
+    pub(crate) fn keys_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = &'a String> + 'a {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .filter(move |key| key.starts_with(prefix) && !self.is_expired(key))
+    }
+
+    // 返回键占用的字节数：键名的长度加上序列化后的值的长度，列表为所有元素的长度之和，键不存在时返回 None。
+    // 这是数据在内存中的大小，不包括 HashMap 自身的开销，也不等于它在数据库文件中占用的大小。
+    pub fn size_of(&self, key: &str) -> Option<usize> {
+        match self.map.get(key) {
+            Some(value) => Some(key.len() + value.len()),
+            None => self
+                .list_map
+                .get(key)
+                .map(|list| key.len() + list.iter().map(Vec::len).sum::<usize>()),
+        }
+    }
+
+    // 所有键的 size_of 之和
+    pub fn total_bytes(&self) -> usize {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .filter_map(|key| self.size_of(key))
+            .sum()
+    }
+
+    // 返回数据库文件在磁盘上的大小，包括元数据和序列化格式本身的开销；文件还没有写入过时返回 Io 错误，纯内存数据库返回 0
+    pub fn disk_size(&self) -> Result<u64> {
+        let path = match &self.db_file_path {
+            Some(path) => path,
+            None => return Ok(0),
+        };
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) => Err(Error::new(ErrorCode::Io(err))
+                .with_op("disk_size")
+                .with_path(path)),
+        }
+    }
+
+    // 从当前逻辑数据库中取最多 sample 个能反序列化为 V 的值（普通值和列表的元素），
+    // 用编译时启用的每一种序列化方法分别序列化并读回，报告大小和耗时，用于在迁移之前比较各种格式，例如：
+    // for report in db.analyze_formats::<User>(1000) { println!("{}: {} bytes", report.method, report.bytes); }
+    // 数据库本身不会被修改。
+    pub fn analyze_formats<V>(&self, sample: usize) -> Vec<FormatReport>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        let values = self
+            .map
+            .iter()
+            .filter(|(key, _)| !self.is_expired(key))
+            .map(|(_, value)| value)
+            .chain(
+                self.list_map
+                    .iter()
+                    .filter(|(key, _)| !self.is_expired(key))
+                    .flat_map(|(_, list)| list.iter()),
+            );
+        let samples: Vec<V> = values
+            .filter_map(|value| self.serializer.deserialize_data(value))
+            .take(sample)
+            .collect();
+        formats::analyze(&samples)
+    }
+
+    // 删除键（普通值或者列表），键不存在或者已经过期时返回 false
+    pub fn rem(&mut self, key: &str) -> Result<bool> {
+        self.check_writable("rem", Some(key))?;
+        if self.is_expired(key) {
+            return Ok(false);
+        }
+        let remove_map = match self.map.remove(key) {
+            None => None,
+            Some(val) => {
+                let meta = self.meta.remove(key);
+                match self.dumpdb("rem", Some(key)) {
+                    Ok(_) => Some(val),
+                    Err(err) => {
+                        self.map.insert(String::from(key), val);
+                        self.restore_meta(key, meta);
+                        return Err(err);
+                    }
+                }
+            }
+        };
+
+        let remove_list = match self.list_map.remove(key) {
+            None => None,
+            Some(list) => {
+                let meta = self.meta.remove(key);
+                match self.dumpdb("rem", Some(key)) {
+                    Ok(_) => Some(list),
+                    Err(err) => {
+                        self.list_map.insert(String::from(key), list);
+                        self.restore_meta(key, meta);
+                        return Err(err);
+                    }
+                }
+            }
+        };
+
+        Ok(remove_map.is_some() || remove_list.is_some())
+    }
+
+    // 删除所有以 prefix 开头的键（包括普通值和列表），返回删除的键的数量。
+    // 整个操作只会触发一次 dump，dump 失败时恢复所有被删除的键。
+    pub fn rem_prefix(&mut self, prefix: &str) -> Result<usize> {
+        self.rem_where("rem_prefix", |key| key.starts_with(prefix))
+    }
+
+    // 与 rem_prefix 相同，但删除所有匹配通配符 pattern 的键，通配符的规则与 keys_matching 相同
+    pub fn rem_matching(&mut self, pattern: &str) -> Result<usize> {
+        self.rem_where("rem_matching", |key| glob_match(pattern, key))
+    }
+
+    pub(crate) fn rem_where<F>(&mut self, op: &'static str, matches: F) -> Result<usize>
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.check_writable(op, None)?;
+        let keys: Vec<String> = self
+            .map
+            .keys()
+            .filter(|key| matches(key))
+            .cloned()
+            .collect();
+        let list_keys: Vec<String> = self
+            .list_map
+            .keys()
+            .filter(|key| matches(key))
+            .cloned()
+            .collect();
+        if keys.is_empty() && list_keys.is_empty() {
+            return Ok(0);
+        }
+
+        let removed: Vec<(String, Vec<u8>)> = keys
+            .into_iter()
+            .filter_map(|key| self.map.remove(&key).map(|value| (key, value)))
+            .collect();
+        let removed_lists: Vec<(String, DbList)> = list_keys
+            .into_iter()
+            .filter_map(|key| self.list_map.remove(&key).map(|list| (key, list)))
+            .collect();
+        let removed_meta: Vec<(String, KeyMetadata)> = removed
+            .iter()
+            .map(|(key, _)| key)
+            .chain(removed_lists.iter().map(|(key, _)| key))
+            .filter_map(|key| self.meta.remove(key).map(|meta| (key.clone(), meta)))
+            .collect();
+
+        match self.dumpdb(op, None) {
+            Ok(_) => Ok(removed.len() + removed_lists.len()),
+            Err(err) => {
+                self.map.extend(removed);
+                self.list_map.extend(removed_lists);
+                self.meta.extend(removed_meta);
+                Err(err)
+            }
+        }
+    }
+
+    // 删除所有逻辑数据库中已经过期的键，返回删除的键的数量。
+    // 过期的键只是对读取不可见，在被删除之前仍然占用内存和数据库文件的空间，长时间运行的进程应该定期调用它，
+    // 例如使用 KeyValueDbSweeper。每个有过期键的逻辑数据库触发一次 dump，dump 失败时恢复该数据库中被删除的键。
+    pub fn purge_expired(&mut self) -> Result<usize> {
+        let selected = self.selected;
+        let mut purged = 0;
+        for index in self.databases() {
+            self.select(index);
+            let expired: HashSet<String> = self
+                .meta
+                .iter()
+                .filter(|(_, meta)| meta.is_expired())
+                .map(|(key, _)| key.clone())
+                .collect();
+            match self.rem_where("purge_expired", |key| expired.contains(key)) {
+                Ok(count) => purged += count,
+                Err(err) => {
+                    self.select(selected);
+                    return Err(err);
+                }
+            }
+        }
+        self.select(selected);
+        Ok(purged)
+    }
+
+    // 清空整个数据库（包括所有普通值和列表），只触发一次 dump，dump 失败时恢复原来的数据
+    pub fn clear(&mut self) -> Result<()> {
+        self.check_writable("clear", None)?;
+        let map = self.map.take();
+        let list_map = self.list_map.take();
+        let meta = self.meta.take();
+        match self.dumpdb("clear", None) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                self.map = map;
+                self.list_map = list_map;
+                self.meta = meta;
+                Err(err)
+            }
+        }
+    }
+
+    // 将键 old（普通值或者列表）重命名为 new，直接移动序列化后的字节数据，不需要知道值的具体类型。
+    // 如果 new 已经存在，它原有的值会被覆盖。整个操作只会触发一次 dump，dump 失败时会恢复原来的状态。
+    // 如果 old 不存在或者已经过期，返回 false。
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<bool> {
+        self.check_writable("rename", Some(old))?;
+        if !self.exists(old) {
+            return Ok(false);
+        }
+        if old == new {
+            return Ok(true);
+        }
+        self.check_key("rename", new)?;
+        self.check_copied_value("rename", old, new)?;
+
+        let value = self.map.remove(old);
+        let list = self.list_map.remove(old);
+
+        let replaced_value = self.map.remove(new);
+        let replaced_list = self.list_map.remove(new);
+        let replaced_meta = self.meta.remove(new);
+        if let Some(value) = value {
+            self.map.insert(String::from(new), value);
+        }
+        if let Some(list) = list {
+            self.list_map.insert(String::from(new), list);
+        }
+        // 重命名不会修改值，元数据保持不变
+        if let Some(meta) = self.meta.remove(old) {
+            self.meta.insert(String::from(new), meta);
+        }
+
+        match self.dumpdb("rename", Some(old)) {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                if let Some(value) = self.map.remove(new) {
+                    self.map.insert(String::from(old), value);
+                }
+                if let Some(list) = self.list_map.remove(new) {
+                    self.list_map.insert(String::from(old), list);
+                }
+                if let Some(meta) = self.meta.remove(new) {
+                    self.meta.insert(String::from(old), meta);
+                }
+                self.restore_meta(new, replaced_meta);
+                if let Some(value) = replaced_value {
+                    self.map.insert(String::from(new), value);
+                }
+                if let Some(list) = replaced_list {
+                    self.list_map.insert(String::from(new), list);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // rename 和 copy 共用：src 的值（列表为每一个元素）写入 dst 之前按照 dst 检查，
+    // 例如把键重命名为 "user:" 开头时需要通过 validate_with("user:", ...) 注册的检查函数
+    fn check_copied_value(&self, op: &'static str, src: &str, dst: &str) -> Result<()> {
+        if let Some(value) = self.map.get(src) {
+            self.check_value(op, dst, value)?;
+        }
+        if let Some(list) = self.list_map.get(src) {
+            for item in list {
+                self.check_value(op, dst, item)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 将键 src（普通值或者列表）的序列化数据复制一份存放到键 dst 下，不需要知道值的具体类型。
+    // 如果 dst 已经存在且 overwrite 为 false，则不做任何修改。
+    // 复制成功返回 true；src 不存在或者 dst 未被覆盖时返回 false。dump 失败时会恢复 dst 原来的值。
+    pub fn copy(&mut self, src: &str, dst: &str, overwrite: bool) -> Result<bool> {
+        self.check_writable("copy", Some(dst))?;
+        if !self.exists(src) || (self.exists(dst) && !overwrite) {
+            return Ok(false);
+        }
+        if src == dst {
+            return Ok(true);
+        }
+        self.check_key("copy", dst)?;
+        self.check_copied_value("copy", src, dst)?;
+
+        // 先复制 src 的值，腾出空间时 src 本身也可能被淘汰
+        let value = self.map.get(src).cloned();
+        let list = self.list_map.get(src).cloned();
+        let added = self.size_of(src).unwrap_or(0) - src.len() + dst.len();
+        let replaced = self.size_of(dst).unwrap_or(0);
+        self.reserve_memory("copy", dst, added, replaced)?;
+        self.record_access(src);
+        self.record_access(dst);
+        let replaced_value = self.map.remove(dst);
+        let replaced_list = self.list_map.remove(dst);
+        if let Some(value) = value {
+            self.map.insert(String::from(dst), value);
+        }
+        if let Some(list) = list {
+            self.list_map.insert(String::from(dst), list);
+        }
+        let replaced_meta = self.meta.insert(String::from(dst), KeyMetadata::now());
+
+        match self.dumpdb("copy", Some(dst)) {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                self.map.remove(dst);
+                self.list_map.remove(dst);
+                self.restore_meta(dst, replaced_meta);
+                if let Some(value) = replaced_value {
+                    self.map.insert(String::from(dst), value);
+                }
+                if let Some(list) = replaced_list {
+                    self.list_map.insert(String::from(dst), list);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // 将所有以 prefix 开头的键（包括普通值和列表）导出为一段序列化后的数据，
+    // 格式与数据库文件相同，可以通过另一个使用相同序列化方法的数据库的 import_keys 导入。
+    // 已经过期但还没有被清理的键不会被导出，import_keys 返回的数量因此等于导出时存在的键的数量。
+    pub fn export_keys(&self, prefix: &str) -> Result<Vec<u8>> {
+        self.export_matching(|key| key.starts_with(prefix))
+    }
+
+    // 与 export_keys 相同，但只导出一个键，键不存在时返回 None
+    pub fn export_key(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if !self.exists(key) {
+            return Ok(None);
+        }
+        self.export_matching(|name| name == key).map(Some)
+    }
+
+    fn export_matching<F>(&self, matches: F) -> Result<Vec<u8>>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let matches = |key: &str| matches(key) && !self.is_expired(key);
+        let map: KeyMap<Vec<u8>> = self
+            .map
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let list_map: KeyMap<DbList> = self
+            .list_map
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .map(|(key, list)| (key.clone(), list.clone()))
+            .collect();
+        let meta: KeyMap<KeyMetadata> = self
+            .meta
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .map(|(key, meta)| (key.clone(), meta.clone()))
+            .collect();
+
+        match self
+            .serializer
+            .serialize_db(&map, &list_map, &meta, &BTreeMap::new())
+        {
+            Ok(data) => Ok(data),
+            Err(err_str) => {
+                Err(Error::new(ErrorCode::Serialization(err_str)).with_op("export_keys"))
+            }
+        }
+    }
+
+    // 导入 export_keys 导出的数据，已经存在的同名键会被覆盖，返回导入的键的数量。
+    // 整个导入只会触发一次 dump，dump 失败时会恢复所有被修改的键。
+    pub fn import_keys(&mut self, data: &[u8]) -> Result<usize> {
+        let (map, list_map, meta, _) = match self.serializer.deserialize_db(data) {
+            Ok(maps) => maps,
+            Err(err_str) => {
+                return Err(Error::new(ErrorCode::Serialization(err_str)).with_op("import_keys"))
+            }
+        };
+        self.insert_all("import_keys", map, list_map, meta)
+    }
+
+    // 将 other 当前选择的逻辑数据库中的所有键（包括普通值和列表）合并到当前选择的逻辑数据库中，
+    // 返回写入的键的数量。同名的键按照 strategy 处理，other 中已经过期的键会被忽略，元数据随键一起复制。
+    // 两个数据库必须使用相同的序列化方法，否则返回 InvalidArgument 错误；使用 ErrorOnConflict 时同名的键返回 KeyExists 错误。
+    // 整个合并只会触发一次 dump，dump 失败时会恢复所有被修改的键。
+    pub fn merge_from(&mut self, other: &KeyValueDb, strategy: MergeStrategy) -> Result<usize> {
+        if other.serialization_method() != self.serialization_method() {
+            return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                "Cannot merge a {} database into a {} database",
+                other.serialization_method(),
+                self.serialization_method()
+            )))
+            .with_op("merge_from"));
+        }
+
+        let mut keys: Vec<&String> = Vec::new();
+        for key in other.map.keys().chain(other.list_map.keys()) {
+            if other.is_expired(key) {
+                continue;
+            }
+            if self.exists(key) {
+                match strategy {
+                    MergeStrategy::KeepExisting => continue,
+                    MergeStrategy::Overwrite => (),
+                    MergeStrategy::ErrorOnConflict => {
+                        return Err(Error::new(ErrorCode::KeyExists(format!(
+                            "Key '{}' exists in both databases",
+                            key
+                        )))
+                        .with_context("merge_from", key))
+                    }
+                }
+            }
+            keys.push(key);
+        }
+
+        let mut map = self.map.new_like();
+        let mut list_map = self.list_map.new_like();
+        let mut meta = self.meta.new_like();
+        for key in keys {
+            if let Some(value) = other.map.get(key) {
+                map.insert(key.clone(), value.clone());
+            }
+            if let Some(list) = other.list_map.get(key) {
+                list_map.insert(key.clone(), list.clone());
+            }
+            if let Some(key_meta) = other.meta.get(key) {
+                meta.insert(key.clone(), key_meta.clone());
+            }
+        }
+        self.insert_all("merge_from", map, list_map, meta)
+    }
+
+    // import_keys 和 merge_from 共用：写入所有的键，覆盖已经存在的同名键。
+    // 与 set 和 ladd 一样检查键的长度、值的大小和 validate_with 注册的检查函数
+    fn insert_all(
+        &mut self,
+        op: &'static str,
+        map: KeyMap<Vec<u8>>,
+        list_map: KeyMap<DbList>,
+        meta: KeyMap<KeyMetadata>,
+    ) -> Result<usize> {
+        self.check_writable(op, None)?;
+        // 先检查所有的键和值，任何一个不满足限制时数据库保持不变
+        for (key, value) in map.iter() {
+            self.check_key(op, key)?;
+            self.check_value(op, key, value)?;
+        }
+        for (name, list) in list_map.iter() {
+            self.check_key(op, name)?;
+            for item in list {
+                self.check_value(op, name, item)?;
+            }
+        }
+
+        let keys: Vec<String> = map.keys().chain(list_map.keys()).cloned().collect();
+        let added: usize = map
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .chain(
+                list_map
+                    .iter()
+                    .map(|(name, list)| name.len() + list.iter().map(Vec::len).sum::<usize>()),
+            )
+            .sum();
+        let replaced_bytes = keys.iter().filter_map(|key| self.size_of(key)).sum();
+        let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.reserve_memory_for(op, &key_refs, added, replaced_bytes)?;
+        for key in &keys {
+            self.record_access(key);
+        }
+
+        let mut replaced = Vec::new();
+        for key in &keys {
+            replaced.push((
+                key.clone(),
+                self.map.remove(key),
+                self.list_map.remove(key),
+                self.meta.remove(key),
+            ));
+        }
+        self.map.extend(map);
+        self.list_map.extend(list_map);
+        // 保留导出时的元数据，导出的数据中没有元数据的键视为刚刚创建
+        for key in &keys {
+            let key_meta = meta.get(key).cloned().unwrap_or_else(KeyMetadata::now);
+            self.meta.insert(key.clone(), key_meta);
+        }
+
+        match self.dumpdb(op, None) {
+            Ok(_) => Ok(keys.len()),
+            Err(err) => {
+                for (key, value, list, key_meta) in replaced {
+                    self.map.remove(&key);
+                    self.list_map.remove(&key);
+                    self.restore_meta(&key, key_meta);
+                    if let Some(value) = value {
+                        self.map.insert(key.clone(), value);
+                    }
+                    if let Some(list) = list {
+                        self.list_map.insert(key, list);
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn lcreate(&mut self, name: &str) -> Result<KeyValueDbListExtender<'_>> {
+        self.check_writable("lcreate", Some(name))?;
+        self.check_key("lcreate", name)?;
+        let replaced = self.size_of(name).unwrap_or(0);
+        self.reserve_memory("lcreate", name, name.len(), replaced)?;
+        self.record_access(name);
+        let new_list = DbList::new();
+        if self.map.contains_key(name) {
+            self.map.remove(name);
+        }
+        self.list_map.insert(String::from(name), new_list);
+        self.meta.insert(String::from(name), KeyMetadata::now());
+        self.dumpdb("lcreate", Some(name))?;
+        Ok(KeyValueDbListExtender {
+            db: self,
+            list_name: String::from(name),
+        })
+    }
+
+    pub fn lexists(&self, name: &str) -> bool {
+        self.list(name).is_some()
+    }
+
+    pub fn ladd<V>(&mut self, name: &str, value: &V) -> Result<KeyValueDbListExtender<'_>>
+    where
+        V: Serialize,
+    {
+        self.lextend(name, &[value])
+    }
+
+    // 在列表末尾添加 seq 中的所有元素。先序列化所有元素再修改列表：列表不存在或者已经过期时返回
+    // ListNotFound 错误，任何一个元素无法序列化或者超过大小限制时返回错误，这些情况下列表保持不变。
+    // 与 set 一样，dump 失败时恢复原来的列表。
+    pub fn lextend<'a, V, I>(&mut self, name: &str, seq: I) -> Result<KeyValueDbListExtender<'_>>
+    where
+        V: 'a + Serialize,
+        I: IntoIterator<Item = &'a V>,
+    {
+        self.check_writable("lextend", Some(name))?;
+        if self.is_expired(name) || !self.list_map.contains_key(name) {
+            return Err(Error::new(ErrorCode::ListNotFound(format!(
+                "List '{}' doesn't exist",
+                name
+            )))
+            .with_context("lextend", name));
+        }
+        let serialized = match seq
+            .into_iter()
+            .map(|x| self.serializer.serialize_data(x))
+            .collect::<std::result::Result<DbList, String>>()
+        {
+            Ok(serialized) => serialized,
+            Err(err_str) => {
+                return Err(
+                    Error::new(ErrorCode::Serialization(err_str)).with_context("lextend", name)
+                )
+            }
+        };
+        for item in &serialized {
+            self.check_value("lextend", name, item)?;
+        }
+        let current = self.size_of(name).unwrap_or(0);
+        let added = current + serialized.iter().map(Vec::len).sum::<usize>();
+        self.reserve_memory("lextend", name, added, current)?;
+        self.record_access(name);
+
+        let mut original_len = 0;
+        if let Some(list) = self.list_map.get_mut(name) {
+            original_len = list.len();
+            list.extend(serialized);
+        }
+        let original_meta = self.touch(name);
+        if let Err(err) = self.dumpdb("lextend", Some(name)) {
+            if let Some(list) = self.list_map.get_mut(name) {
+                list.truncate(original_len);
+            }
+            self.restore_meta(name, original_meta);
+            return Err(err);
+        }
+        Ok(KeyValueDbListExtender {
+            db: self,
+            list_name: String::from(name),
+        })
+    }
+
+    // 在列表的 pos 位置插入 value，原来在 pos 及之后的元素依次后移，pos 等于列表长度时添加到末尾。
+    // 列表不存在或者已经过期时返回 ListNotFound 错误，pos 大于列表长度时返回 InvalidArgument 错误，
+    // 这些情况下列表保持不变。与 lextend 一样，dump 失败时恢复原来的列表。
+    pub fn linsert<V>(&mut self, name: &str, pos: usize, value: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.check_writable("linsert", Some(name))?;
+        let len = self.existing_list("linsert", name)?.len();
+        if pos > len {
+            return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                "Position {} is out of range for a list of length {}",
+                pos, len
+            )))
+            .with_context("linsert", name));
+        }
+        let item = self.serialize_item("linsert", name, value)?;
+        self.insert_item("linsert", name, pos, item)
+    }
+
+    // 在列表中第一个等于 pivot 的元素之前插入 value，与 Redis 的 LINSERT BEFORE 相同。
+    // 元素按序列化之后的数据比较，与 lrem_value 相同。找不到 pivot 时返回 false，列表保持不变；
+    // 列表不存在或者已经过期时返回 ListNotFound 错误。
+    pub fn linsert_before<V>(&mut self, name: &str, pivot: &V, value: &V) -> Result<bool>
+    where
+        V: Serialize,
+    {
+        self.insert_near("linsert_before", name, pivot, value, 0)
+    }
+
+    // 与 linsert_before 相同，但插入在 pivot 之后
+    pub fn linsert_after<V>(&mut self, name: &str, pivot: &V, value: &V) -> Result<bool>
+    where
+        V: Serialize,
+    {
+        self.insert_near("linsert_after", name, pivot, value, 1)
+    }
+
+    // 在第一个等于 pivot 的元素的位置加上 offset 处插入 value
+    fn insert_near<V>(
+        &mut self,
+        op: &'static str,
+        name: &str,
+        pivot: &V,
+        value: &V,
+        offset: usize,
+    ) -> Result<bool>
+    where
+        V: Serialize,
+    {
+        self.check_writable(op, Some(name))?;
+        let pivot = self.serialize_item(op, name, pivot)?;
+        let item = self.serialize_item(op, name, value)?;
+        let list = self.existing_list(op, name)?;
+        let pos = match list.iter().position(|x| *x == pivot) {
+            Some(pos) => pos + offset,
+            None => return Ok(false),
+        };
+        self.insert_item(op, name, pos, item)?;
+        Ok(true)
+    }
+
+    // 没有过期的列表，不存在时返回 ListNotFound 错误
+    fn existing_list(&self, op: &'static str, name: &str) -> Result<&DbList> {
+        match self.list(name) {
+            Some(list) => Ok(list),
+            None => Err(Error::new(ErrorCode::ListNotFound(format!(
+                "List '{}' doesn't exist",
+                name
+            )))
+            .with_context(op, name)),
+        }
+    }
+
+    fn serialize_item<V>(&self, op: &'static str, name: &str, value: &V) -> Result<Vec<u8>>
+    where
+        V: Serialize,
+    {
+        match self.serializer.serialize_data(value) {
+            Ok(item) => Ok(item),
+            Err(err_str) => {
+                Err(Error::new(ErrorCode::Serialization(err_str)).with_context(op, name))
+            }
+        }
+    }
+
+    // 在已经存在的列表的 pos 位置插入序列化后的元素，dump 失败时删除插入的元素
+    fn insert_item(
+        &mut self,
+        op: &'static str,
+        name: &str,
+        pos: usize,
+        item: Vec<u8>,
+    ) -> Result<()> {
+        self.check_value(op, name, &item)?;
+        let current = self.size_of(name).unwrap_or(0);
+        self.reserve_memory(op, name, current + item.len(), current)?;
+        self.record_access(name);
+
+        if let Some(list) = self.list_map.get_mut(name) {
+            list.insert(pos, item);
+        }
+        let original_meta = self.touch(name);
+        if let Err(err) = self.dumpdb(op, Some(name)) {
+            if let Some(list) = self.list_map.get_mut(name) {
+                list.remove(pos);
+            }
+            self.restore_meta(name, original_meta);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // 将列表中 pos 位置的元素替换为 value，pos 可以是负数，见 ListIndex。
+    // pos 超出列表的范围时返回 false，列表保持不变。
+    // 列表不存在或者已经过期时返回 ListNotFound 错误。dump 失败时恢复原来的元素。
+    pub fn lset<V>(&mut self, name: &str, pos: impl ListIndex, value: &V) -> Result<bool>
+    where
+        V: Serialize,
+    {
+        self.check_writable("lset", Some(name))?;
+        let item = self.serialize_item("lset", name, value)?;
+        let list = self.existing_list("lset", name)?;
+        let (pos, replaced) = match pos.resolve(list.len()) {
+            Some(pos) => (pos, list[pos].len()),
+            None => return Ok(false),
+        };
+        self.check_value("lset", name, &item)?;
+        let current = self.size_of(name).unwrap_or(0);
+        self.reserve_memory("lset", name, current - replaced + item.len(), current)?;
+        self.record_access(name);
+
+        let original = match self.list_map.get_mut(name) {
+            Some(list) => mem::replace(&mut list[pos], item),
+            None => return Ok(false),
+        };
+        let original_meta = self.touch(name);
+        if let Err(err) = self.dumpdb("lset", Some(name)) {
+            if let Some(list) = self.list_map.get_mut(name) {
+                list[pos] = original;
+            }
+            self.restore_meta(name, original_meta);
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    // 用 values 替换列表的全部内容，列表不存在时创建列表（与 lcreate 相同，同名的普通值会被替换）。
+    // 先序列化所有元素再修改列表，任何一个元素无法序列化或者超过大小限制时返回错误，列表保持不变。
+    // 写入和 dump 的方式见 replace_list。
+    pub fn lset_all<V>(&mut self, name: &str, values: &[V]) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.check_writable("lset_all", Some(name))?;
+        self.check_key("lset_all", name)?;
+        let mut list = DbList::with_capacity(values.len());
+        for value in values {
+            let item = self.serialize_item("lset_all", name, value)?;
+            self.check_value("lset_all", name, &item)?;
+            list.push_back(item);
+        }
+        self.replace_list("lset_all", name, list)
+    }
+
+    // lset_all、ltrim、ldedup、lrem_values 和排序共用：用 new 替换名为 name 的列表，同名的普通值会被删除。
+    // 写入之前按照 with_max_memory 腾出空间，写入之后更新修改时间并只触发一次 dump，
+    // dump 失败时恢复原来的列表（或者普通值）和元数据
+    fn replace_list(&mut self, op: &'static str, name: &str, new: DbList) -> Result<()> {
+        let replaced = self.size_of(name).unwrap_or(0);
+        let added = name.len() + new.iter().map(Vec::len).sum::<usize>();
+        self.reserve_memory(op, name, added, replaced)?;
+        self.record_access(name);
+
+        let original_value = self.map.remove(name);
+        let original_list = self.list_map.insert(String::from(name), new);
+        let original_meta = self.touch(name);
+        if let Err(err) = self.dumpdb(op, Some(name)) {
+            match original_list {
+                Some(list) => self.list_map.insert(String::from(name), list),
+                None => self.list_map.remove(name),
+            };
+            if let Some(value) = original_value {
+                self.map.insert(String::from(name), value);
+            }
+            self.restore_meta(name, original_meta);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // 只保留列表中从 start 到 end（包括 end）的元素，与 Redis 的 LTRIM 相同，start 和 end 的含义见 lrange，
+    // 例如 ltrim(name, -100, -1) 只保留最后 100 个元素。范围为空时列表被清空，但列表本身仍然存在。
+    // 返回删除的元素个数，dump 的方式见 replace_list。列表不存在或者已经过期时返回 ListNotFound 错误。
+    pub fn ltrim(&mut self, name: &str, start: i64, end: i64) -> Result<usize> {
+        self.check_writable("ltrim", Some(name))?;
+        let list = self.existing_list("ltrim", name)?;
+        let range = list_range(list.len(), start, end);
+        let removed = list.len() - range.len();
+        if removed == 0 {
+            return Ok(0);
+        }
+        let trimmed = list.range(range).cloned().collect();
+        self.replace_list("ltrim", name, trimmed)?;
+        Ok(removed)
+    }
+
+    // 删除列表中重复的元素，每个值只保留第一次出现的位置，比较的是序列化之后的数据（与 lrem_value 相同）。
+    // 返回删除的元素个数，dump 的方式见 replace_list。列表不存在或者已经过期时返回 ListNotFound 错误。
+    pub fn ldedup(&mut self, name: &str) -> Result<usize> {
+        self.check_writable("ldedup", Some(name))?;
+        let list = self.existing_list("ldedup", name)?;
+        let mut seen = HashSet::with_capacity(list.len());
+        let deduped: DbList = list
+            .iter()
+            .filter(|item| seen.insert(*item))
+            .cloned()
+            .collect();
+        let removed = list.len() - deduped.len();
+        if removed == 0 {
+            return Ok(0);
+        }
+        self.replace_list("ldedup", name, deduped)?;
+        Ok(removed)
+    }
+
+    // 把列表中的元素按照 V 的顺序从小到大排序，相等的元素保持原来的先后顺序，整个操作只会触发一次 dump。
+    // 列表不存在或者已经过期时返回 ListNotFound 错误，有元素无法反序列化为 V 时返回 Deserialization 错误，
+    // 这两种情况下列表都保持不变。dump 的方式见 replace_list。
+    pub fn lsort<V>(&mut self, name: &str) -> Result<()>
+    where
+        V: DeserializeOwned + Ord,
+    {
+        self.sort_list("lsort", name, V::cmp)
+    }
+
+    // 与 lsort 相同，但是使用 compare 比较元素，例如 lsort_by(name, |a: &i64, b| b.cmp(a)) 从大到小排序
+    pub fn lsort_by<V, F>(&mut self, name: &str, compare: F) -> Result<()>
+    where
+        V: DeserializeOwned,
+        F: FnMut(&V, &V) -> std::cmp::Ordering,
+    {
+        self.sort_list("lsort_by", name, compare)
+    }
+
+    // 把列表中的所有元素反序列化为 V，列表不存在时返回 ListNotFound 错误，
+    // 有元素无法反序列化时返回 Deserialization 错误
+    fn decode_list<V>(&self, op: &'static str, name: &str) -> Result<Vec<V>>
+    where
+        V: DeserializeOwned,
+    {
+        let list = self.existing_list(op, name)?;
+        let mut decoded = Vec::with_capacity(list.len());
+        for (pos, item) in list.iter().enumerate() {
+            match self.serializer.deserialize_data::<V>(item) {
+                Some(value) => decoded.push(value),
+                None => {
+                    return Err(Error::new(ErrorCode::Deserialization(format!(
+                        "Cannot deserialize element {} of '{}' as {}",
+                        pos,
+                        name,
+                        std::any::type_name::<V>()
+                    )))
+                    .with_context(op, name))
+                }
+            }
+        }
+        Ok(decoded)
+    }
+
+    // 元素排序之后仍然使用原来的序列化数据，不需要重新序列化
+    fn sort_list<V, F>(&mut self, op: &'static str, name: &str, mut compare: F) -> Result<()>
+    where
+        V: DeserializeOwned,
+        F: FnMut(&V, &V) -> std::cmp::Ordering,
+    {
+        self.check_writable(op, Some(name))?;
+        let mut decoded: Vec<(V, usize)> =
+            self.decode_list(op, name)?.into_iter().zip(0..).collect();
+        decoded.sort_by(|(a, _), (b, _)| compare(a, b));
+
+        let list = self.existing_list(op, name)?;
+        let sorted = decoded.iter().map(|(_, pos)| list[*pos].clone()).collect();
+        self.replace_list(op, name, sorted)
+    }
+
+    // 读取列表中 pos 位置的元素，pos 可以是负数，-1 是最后一个元素，见 ListIndex
+    pub fn lget<V>(&self, name: &str, pos: impl ListIndex) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        match self.list(name) {
+            Some(list) => match pos.resolve(list.len()) {
+                Some(pos) => self.serializer.deserialize_data::<V>(&list[pos]),
                 None => None,
             },
             None => None,
         }
     }
 
+    // 返回列表中从 start 到 end（包括 end）的元素，与 Redis 的 LRANGE 相同，负数表示从末尾开始数，
+    // -1 是最后一个元素，超出列表的部分被忽略，例如 lrange(name, 0, -1) 返回整个列表。
+    // 列表不存在或者范围为空时返回空的 Vec，无法反序列化为 V 的元素被跳过。
+    pub fn lrange<V>(&self, name: &str, start: i64, end: i64) -> Vec<V>
+    where
+        V: DeserializeOwned,
+    {
+        match self.list(name) {
+            Some(list) => list
+                .range(list_range(list.len(), start, end))
+                .filter_map(|val| self.serializer.deserialize_data::<V>(val))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // 按顺序返回列表中的所有元素。与 lrange 不同，有元素无法反序列化为 V 时返回 Deserialization 错误，
+    // 而不是跳过这个元素；列表不存在或者已经过期时返回 ListNotFound 错误
+    pub fn lget_all<V>(&self, name: &str) -> Result<Vec<V>>
+    where
+        V: DeserializeOwned,
+    {
+        self.decode_list("lget_all", name)
+    }
+
+    // 返回列表中第一个等于 value 的元素的位置，与 lrem_value 相同，比较的是序列化之后的数据。
+    // 列表不存在、没有这个元素或者 value 无法序列化时返回 None
+    pub fn lpos<V>(&self, name: &str, value: &V) -> Option<usize>
+    where
+        V: Serialize,
+    {
+        let list = self.list(name)?;
+        let serialized_value = self.serializer.serialize_data(value).ok()?;
+        list.iter().position(|x| *x == serialized_value)
+    }
+
+    // 与 lpos 相同，但是按照从前到后的顺序返回所有等于 value 的元素的位置
+    pub fn lpos_all<V>(&self, name: &str, value: &V) -> Vec<usize>
+    where
+        V: Serialize,
+    {
+        let list = match self.list(name) {
+            Some(list) => list,
+            None => return Vec::new(),
+        };
+        match self.serializer.serialize_data(value) {
+            Ok(serialized_value) => list
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| **x == serialized_value)
+                .map(|(pos, _)| pos)
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
     pub fn llen(&self, name: &str) -> usize {
-        match self.list_map.get(name) {
+        match self.list(name) {
             Some(list) => list.len(),
             None => 0,
         }
     }
 
     pub fn lrem_list(&mut self, name: &str) -> Result<usize> {
+        self.check_writable("lrem_list", Some(name))?;
         let res = self.llen(name);
         match self.list_map.remove(name) {
-            Some(list) => match self.dumpdb() {
-                Ok(_) => Ok(res),
-                Err(err) => {
-                    self.list_map.insert(String::from(name), list);
-                    Err(err)
+            Some(list) => {
+                let meta = self.meta.remove(name);
+                match self.dumpdb("lrem_list", Some(name)) {
+                    Ok(_) => Ok(res),
+                    Err(err) => {
+                        self.list_map.insert(String::from(name), list);
+                        self.restore_meta(name, meta);
+                        Err(err)
+                    }
                 }
-            },
+            }
             None => Ok(res),
         }
     }
 
-    pub fn lpop<V>(&mut self, name: &str, pos: usize) -> Option<V>
+    // 删除并返回列表中 pos 位置的元素，pos 可以是负数，-1 是最后一个元素，见 ListIndex
+    pub fn lpop<V>(&mut self, name: &str, pos: impl ListIndex) -> Option<V>
     where
         V: DeserializeOwned,
     {
+        if self.is_expired(name) || self.read_only {
+            return None;
+        }
         match self.list_map.get_mut(name) {
             Some(list) => {
-                if pos < list.len() {
-                    let res = list.remove(pos);
-                    match self.dumpdb() {
+                let pos = pos.resolve(list.len())?;
+                if let Some(res) = list.remove(pos) {
+                    let original_meta = self.touch(name);
+                    match self.dumpdb("lpop", Some(name)) {
                         Ok(_) => self.serializer.deserialize_data::<V>(&res),
                         Err(_) => {
                             let same_list = self.list_map.get_mut(name).unwrap();
                             same_list.insert(pos, res);
+                            self.restore_meta(name, original_meta);
                             None
                         }
                     }
@@ -431,25 +3026,76 @@ impl KeyValueDb {
         }
     }
 
+    // 以下四个方法在列表的两端添加或者删除元素，可以把列表当作队列或者栈使用。
+    // 列表使用 VecDeque 保存，修改内存中的列表是 O(1) 的，但与其他写操作一样每次调用都会按照 dump 策略写入文件，
+    // 使用 AutoDump 时每次调用都会重写整个文件，耗时与数据库的大小成正比。
+    // 添加时列表不存在或者已经过期返回 ListNotFound 错误，删除时与 lpop 相同，列表不存在、为空或者 dump 失败时返回 None。
+    pub fn lpush_front<V>(&mut self, name: &str, value: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.push_item("lpush_front", name, value, true)
+    }
+
+    pub fn lpush_back<V>(&mut self, name: &str, value: &V) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.push_item("lpush_back", name, value, false)
+    }
+
+    pub fn lpop_front<V>(&mut self, name: &str) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        self.lpop(name, 0)
+    }
+
+    pub fn lpop_back<V>(&mut self, name: &str) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        self.lpop(name, -1)
+    }
+
+    fn push_item<V>(&mut self, op: &'static str, name: &str, value: &V, front: bool) -> Result<()>
+    where
+        V: Serialize,
+    {
+        self.check_writable(op, Some(name))?;
+        let len = self.existing_list(op, name)?.len();
+        let item = self.serialize_item(op, name, value)?;
+        self.insert_item(op, name, if front { 0 } else { len }, item)
+    }
+
     pub fn lrem_value<V>(&mut self, name: &str, value: &V) -> Result<bool>
     where
         V: Serialize,
     {
+        self.check_writable("lrem_value", Some(name))?;
+        if self.is_expired(name) {
+            return Ok(false);
+        }
         match self.list_map.get_mut(name) {
             Some(list) => {
                 let serialized_value = match self.serializer.serialize_data(&value) {
                     Ok(val) => val,
-                    Err(err_str) => return Err(Error::new(ErrorCode::Serialization(err_str))),
+                    Err(err_str) => {
+                        return Err(Error::new(ErrorCode::Serialization(err_str))
+                            .with_context("lrem_value", name))
+                    }
                 };
 
                 match list.iter().position(|x| *x == serialized_value) {
                     Some(pos) => {
                         list.remove(pos);
-                        match self.dumpdb() {
+                        let original_meta = self.touch(name);
+                        match self.dumpdb("lrem_value", Some(name)) {
                             Ok(_) => Ok(true),
                             Err(err) => {
                                 let same_list = self.list_map.get_mut(name).unwrap();
                                 same_list.insert(pos, serialized_value);
+                                self.restore_meta(name, original_meta);
                                 Err(err)
                             }
                         }
@@ -463,29 +3109,243 @@ impl KeyValueDb {
         }
     }
 
-    pub fn iter(&self) -> KeyValueDbIterator {
+    // 与 Redis 的 LREM 相同，删除列表中等于 value 的元素：count > 0 时从头开始删除最多 count 个，
+    // count < 0 时从末尾开始删除最多 -count 个，count 为 0 时删除所有等于 value 的元素。
+    // 返回删除的元素个数，dump 的方式见 replace_list。列表不存在或者已经过期时返回 ListNotFound 错误。
+    pub fn lrem_values<V>(&mut self, name: &str, value: &V, count: i64) -> Result<usize>
+    where
+        V: Serialize,
+    {
+        self.check_writable("lrem_values", Some(name))?;
+        let serialized_value = self.serialize_item("lrem_values", name, value)?;
+        let list = self.existing_list("lrem_values", name)?;
+
+        let limit = match count {
+            0 => usize::MAX,
+            count => usize::try_from(count.unsigned_abs()).unwrap_or(usize::MAX),
+        };
+        let mut matches: Vec<usize> = list
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| **x == serialized_value)
+            .map(|(pos, _)| pos)
+            .collect();
+        if count < 0 {
+            matches.reverse();
+        }
+        matches.truncate(limit);
+        if matches.is_empty() {
+            return Ok(0);
+        }
+
+        let removed: HashSet<usize> = matches.iter().copied().collect();
+        let remaining = list
+            .iter()
+            .enumerate()
+            .filter(|(pos, _)| !removed.contains(pos))
+            .map(|(_, item)| item.clone())
+            .collect();
+        self.replace_list("lrem_values", name, remaining)?;
+        Ok(matches.len())
+    }
+
+    pub fn iter(&self) -> KeyValueDbIterator<'_> {
+        KeyValueDbIterator {
+            map_iter: Box::new(self.map.iter().filter(|(key, _)| !self.is_expired(key))),
+            serializer: &self.serializer,
+            strip_len: 0,
+        }
+    }
+
+    // 遍历所有没有过期的键，先返回普通值再返回列表，直接返回序列化后的数据，不需要知道值的类型，
+    // 也没有反序列化的开销，适合备份、计算校验和或者复制数据。每一项的 size 与 size_of 相同。
+    // 数据的格式由 serialization_method 决定，可以用 set_raw 写入另一个使用相同格式的数据库。
+    pub fn iter_raw(&self) -> KeyValueDbRawIterator<'_> {
+        KeyValueDbRawIterator {
+            map_iter: Box::new(self.map.iter().filter(|(key, _)| !self.is_expired(key))),
+            list_iter: Box::new(
+                self.list_map
+                    .iter()
+                    .filter(|(name, _)| !self.is_expired(name)),
+            ),
+        }
+    }
+
+    // 与 iter 相同，但只遍历以 prefix 开头的键，例如 "user:42:"
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a str) -> KeyValueDbIterator<'a> {
+        KeyValueDbIterator {
+            map_iter: Box::new(
+                self.map
+                    .iter()
+                    .filter(move |(key, _)| key.starts_with(prefix) && !self.is_expired(key)),
+            ),
+            serializer: &self.serializer,
+            strip_len: 0,
+        }
+    }
+
+    // 按键的字典序遍历位于 range 范围内的普通键值对，例如 db.range("user:100".."user:200")。
+    // 使用 with_ordered_keys 时可以直接定位到范围的起点；否则需要检查所有的键并排序，结果的顺序相同。
+    pub fn range<'a, 'r, R>(&'a self, range: R) -> KeyValueDbIterator<'a>
+    where
+        R: RangeBounds<&'r str>,
+    {
+        let start: Bound<&str> = range.start_bound().cloned();
+        let end: Bound<&str> = range.end_bound().cloned();
         KeyValueDbIterator {
-            map_iter: self.map.iter(),
+            map_iter: Box::new(
+                self.map
+                    .range(start, end)
+                    .filter(|(key, _)| !self.is_expired(key)),
+            ),
             serializer: &self.serializer,
+            strip_len: 0,
+        }
+    }
+
+    // 遍历列表中的元素，列表不存在时 panic，不确定列表是否存在时使用 try_liter
+    pub fn liter(&self, name: &str) -> KeyValueDbListIterator<'_> {
+        match self.try_liter(name) {
+            Ok(iter) => iter,
+            Err(_) => panic!("List '{}' doesn't exist", name),
         }
     }
 
-    pub fn liter(&self, name: &str) -> KeyValueDbListIterator {
-        match self.list_map.get(name) {
-            Some(list) => KeyValueDbListIterator {
+    // 与 liter 相同，但列表不存在或者已经过期时返回 ListNotFound 错误
+    pub fn try_liter(&self, name: &str) -> Result<KeyValueDbListIterator<'_>> {
+        match self.list(name) {
+            Some(list) => Ok(KeyValueDbListIterator {
                 list_iter: list.iter(),
                 serializer: &self.serializer,
-            },
-            None => panic!("List '{}' doesn't exist", name),
+            }),
+            None => Err(Error::new(ErrorCode::ListNotFound(format!(
+                "List '{}' doesn't exist",
+                name
+            )))
+            .with_context("liter", name)),
+        }
+    }
+
+    // 只遍历列表中位于 range 范围内的元素，例如 liter_range(name, 40..50) 返回第 5 页的 10 个元素，
+    // 不需要遍历前面的元素。超出列表的部分被忽略，范围为空时返回一个空的迭代器。
+    // 列表不存在或者已经过期时返回 ListNotFound 错误。
+    pub fn liter_range<R>(&self, name: &str, range: R) -> Result<KeyValueDbListIterator<'_>>
+    where
+        R: RangeBounds<usize>,
+    {
+        let list = self.existing_list("liter_range", name)?;
+        let len = list.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        let end = end.min(len);
+        Ok(KeyValueDbListIterator {
+            list_iter: list.range(start.min(end)..end),
+            serializer: &self.serializer,
+        })
+    }
+}
+
+// 记录在元数据中的类型名：去掉引用，并把 str 视为 String，使写入 &str 的值可以按 String 读取
+fn type_tag<V: ?Sized>() -> &'static str {
+    match std::any::type_name::<V>().trim_start_matches('&') {
+        "str" => std::any::type_name::<String>(),
+        name => name,
+    }
+}
+
+// 将 lrange 的 start 和 end（包括 end，负数从末尾开始数）转换为长度为 len 的列表中的下标范围
+fn list_range(len: usize, start: i64, end: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let start = (if start < 0 { len + start } else { start }).clamp(0, len);
+    let end = (if end < 0 { len + end } else { end })
+        .saturating_add(1)
+        .clamp(0, len);
+    start as usize..end.max(start) as usize
+}
+
+// cleanup_temp_files 只删除超过这个时间没有被修改过的临时文件
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60);
+
+const DEFAULT_TEMP_SUFFIX: &str = ".temp.";
+
+const DEFAULT_LOCK_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 先写入同一目录下的临时文件，临时文件名为 path 加上 suffix 和当前时间戳，再重命名为 path，
+// 保证 path 要么是原来的文件，要么是完整的新文件。失败时尽量删除临时文件。
+// 临时文件名使用 OsString 拼接，path 不是合法的 UTF-8 时也可以使用。
+fn write_atomically(path: &Path, suffix: &str, data: Vec<u8>) -> Result<()> {
+    let mut temp_file_path = path.as_os_str().to_os_string();
+    temp_file_path.push(suffix);
+    temp_file_path.push(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string(),
+    );
+    let temp_file_path = PathBuf::from(temp_file_path);
+
+    match fs::write(&temp_file_path, data) {
+        Ok(_) => (),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_file_path);
+            return Err(Error::new(ErrorCode::Io(err)).with_path(&temp_file_path));
+        }
+    }
+
+    match fs::rename(&temp_file_path, path) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_file_path);
+            Err(Error::new(ErrorCode::Io(err)).with_path(path))
         }
     }
 }
 
+// scan 的 cursor 是上一次返回的最后一个键的十六进制表示，"0" 表示从头开始。
+// 十六进制表示的长度总是偶数，因此不会与 "0" 混淆，也不会包含空格，可以直接在文本协议中传递。
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<Option<String>> {
+    if cursor == "0" {
+        return Some(None);
+    }
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..cursor.len())
+        .step_by(2)
+        .map(|i| {
+            cursor
+                .get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        })
+        .collect();
+    bytes
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .map(Some)
+}
+
 // Drop 实现的作用是，如果 self.dump_policy 不是 NeverDump 或 DumpUponRequest 时，
 // 则尝试进行一次 dump 操作来保存数据库的内容。
 // 这是为了确保即使程序意外崩溃或被非正常终止，数据库中的数据也能够被尽可能地保存下来，避免数据丢失的情况发生。
+// temporary 创建的数据库不会 dump，而是直接删除它的临时目录。
 impl Drop for KeyValueDb {
     fn drop(&mut self) {
+        if let Some(dir) = self.temp_dir.take() {
+            let _ = fs::remove_dir_all(dir);
+            return;
+        }
         if !matches!(
             self.dump_policy,
             KeyValueDbDumpPolicy::NeverDump | KeyValueDbDumpPolicy::DumpUponRequest
@@ -494,4 +3354,380 @@ impl Drop for KeyValueDb {
             let _ = self.dump();
         }
     }
-}
\ No newline at end of file
+}
+
+// 测试使用 JSON 格式的内存数据库
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+    use crate::iterators::KeyValueDbRawItem;
+    use std::thread;
+
+    fn memory_db() -> KeyValueDb {
+        KeyValueDb::in_memory(SerializationMethod::Json)
+    }
+
+    fn is_type<T>(result: Result<T>, expected: ErrorType) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(err) => mem::discriminant(&err.get_type()) == mem::discriminant(&expected),
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported_as_invalid_argument() {
+        let mut db = memory_db();
+        assert!(is_type(
+            db.rate_limit("limit", 0, Duration::from_secs(1)),
+            ErrorType::InvalidArgument
+        ));
+        assert!(is_type(
+            db.lock("lock", Duration::MAX),
+            ErrorType::InvalidArgument
+        ));
+        assert!(is_type(
+            db.scan("not a cursor", 10),
+            ErrorType::InvalidArgument
+        ));
+
+        db.lcreate("list").unwrap();
+        assert!(is_type(
+            db.linsert("list", 1, &1),
+            ErrorType::InvalidArgument
+        ));
+        assert_eq!(db.llen("list"), 0);
+    }
+
+    #[test]
+    fn wrong_types_are_reported_as_type_mismatch() {
+        let mut db = memory_db();
+        db.lcreate("list").unwrap();
+        db.set("text", &"abc").unwrap();
+
+        assert!(is_type(db.incr("list", 1), ErrorType::TypeMismatch));
+        assert!(is_type(db.incr("text", 1), ErrorType::TypeMismatch));
+        assert!(is_type(
+            db.rate_limit("list", 1, Duration::from_secs(1)),
+            ErrorType::TypeMismatch
+        ));
+        assert!(is_type(
+            db.lock("text", Duration::from_secs(1)),
+            ErrorType::TypeMismatch
+        ));
+        assert!(is_type(db.pqlen("text"), ErrorType::TypeMismatch));
+        assert!(is_type(db.strlen("list"), ErrorType::TypeMismatch));
+    }
+
+    #[test]
+    fn lock_token_overflow_is_an_error() {
+        let mut db = memory_db();
+        let released = LockState {
+            token: u64::MAX,
+            held_until: None,
+        };
+        db.set("lock", &released).unwrap();
+        assert!(is_type(
+            db.lock("lock", Duration::from_secs(1)),
+            ErrorType::LimitExceeded
+        ));
+        assert_eq!(db.get::<LockState>("lock").unwrap().token, u64::MAX);
+    }
+
+    #[test]
+    fn import_checks_every_key_and_value_before_writing() {
+        let mut source = memory_db();
+        source.set("a", &1).unwrap();
+        source.set("long key", &2).unwrap();
+        let data = source.export_keys("").unwrap();
+
+        let mut db = memory_db().with_max_key_len(3);
+        assert!(is_type(db.import_keys(&data), ErrorType::LimitExceeded));
+        assert_eq!(db.total_keys(), 0);
+
+        let mut db = memory_db().with_max_value_bytes(1);
+        db.lcreate("list").unwrap();
+        let mut source = memory_db();
+        source.set("a", &1).unwrap();
+        source.lcreate("list").unwrap();
+        source.ladd("list", &100).unwrap();
+        assert!(is_type(
+            db.merge_from(&source, MergeStrategy::Overwrite),
+            ErrorType::LimitExceeded
+        ));
+        assert!(!db.exists("a"));
+        assert_eq!(db.llen("list"), 0);
+
+        let mut db = memory_db();
+        db.validate_with("a", |_| Err(String::from("rejected")));
+        assert!(is_type(db.import_keys(&data), ErrorType::ValidationFailed));
+        assert_eq!(db.total_keys(), 0);
+    }
+
+    #[test]
+    fn copy_and_import_respect_the_memory_limit() {
+        // 每个键占用 2 个字节："k" 和 "1"
+        let mut db = memory_db().with_max_memory(4, KeyValueDbEvictionPolicy::RejectWrites);
+        db.set("a", &1).unwrap();
+        db.set("b", &1).unwrap();
+        assert!(is_type(
+            db.copy("a", "c", false),
+            ErrorType::OutOfMemoryBudget
+        ));
+        assert!(!db.exists("c"));
+        assert!(db.copy("a", "b", true).unwrap());
+
+        let mut source = memory_db();
+        source.set("c", &1).unwrap();
+        let data = source.export_keys("").unwrap();
+        assert!(is_type(db.import_keys(&data), ErrorType::OutOfMemoryBudget));
+        assert!(!db.exists("c"));
+
+        let mut db = memory_db().with_max_memory(4, KeyValueDbEvictionPolicy::Lru);
+        db.set("a", &1).unwrap();
+        db.set("b", &1).unwrap();
+        db.get::<i32>("a");
+        assert_eq!(db.import_keys(&data).unwrap(), 1);
+        assert!(db.exists("a"));
+        assert!(!db.exists("b"));
+        assert!(db.total_bytes() <= 4);
+    }
+
+    #[test]
+    fn rename_and_copy_validate_the_destination() {
+        let mut db = memory_db();
+        db.validate_with("user:", |raw| match raw {
+            b"\"ok\"" => Ok(()),
+            _ => Err(String::from("not ok")),
+        });
+        db.set("draft", &"bad").unwrap();
+        db.lcreate("drafts").unwrap();
+        db.ladd("drafts", &"ok").unwrap();
+        db.ladd("drafts", &"bad").unwrap();
+
+        assert!(is_type(
+            db.rename("draft", "user:1"),
+            ErrorType::ValidationFailed
+        ));
+        assert!(db.exists("draft") && !db.exists("user:1"));
+        assert!(is_type(
+            db.copy("drafts", "user:2", false),
+            ErrorType::ValidationFailed
+        ));
+        assert!(!db.exists("user:2"));
+
+        db.set("draft", &"ok").unwrap();
+        assert!(db.rename("draft", "user:1").unwrap());
+    }
+
+    #[test]
+    fn lrem_values_requires_an_existing_list() {
+        let mut db = memory_db();
+        assert!(is_type(
+            db.lrem_values("list", &1, 0),
+            ErrorType::ListNotFound
+        ));
+        db.lcreate("list").unwrap();
+        db.lextend("list", &[1, 2, 1]).unwrap();
+        assert_eq!(db.lrem_values("list", &1, 0).unwrap(), 2);
+        assert_eq!(db.llen("list"), 1);
+    }
+
+    #[test]
+    fn list_rewrites_replace_the_list_and_record_access() {
+        let mut db = memory_db().with_max_memory(12, KeyValueDbEvictionPolicy::Lru);
+        db.lset_all("l", &[3, 1, 3, 2]).unwrap();
+        db.set("a", &1).unwrap();
+        assert_eq!(db.ldedup("l").unwrap(), 1);
+        db.lsort::<i32>("l").unwrap();
+        assert_eq!(db.lrange::<i32>("l", 0, -1), vec![1, 2, 3]);
+        assert_eq!(db.ltrim("l", 1, -1).unwrap(), 1);
+        assert_eq!(db.lrem_values("l", &3, 0).unwrap(), 1);
+        assert_eq!(db.lrange::<i32>("l", 0, -1), vec![2]);
+
+        // "l" 刚刚被修改过，腾出空间时先淘汰 "a"
+        db.set("bb", &22222222).unwrap();
+        assert!(db.exists("l"));
+        assert!(!db.exists("a"));
+    }
+
+    #[test]
+    fn iter_raw_returns_values_and_lists() {
+        let mut db = memory_db();
+        db.set("a", &1).unwrap();
+        db.lset_all("l", &[10, 20]).unwrap();
+
+        let items: Vec<KeyValueDbRawItem> = db.iter_raw().collect();
+        assert_eq!(items.len(), 2);
+        for item in &items {
+            assert_eq!(Some(item.size()), db.size_of(item.get_key()));
+        }
+        let value = items.iter().find(|item| !item.is_list()).unwrap();
+        assert_eq!(value.get_value(), Some(&b"1"[..]));
+        assert!(value.get_items().is_none());
+        let list = items.iter().find(|item| item.is_list()).unwrap();
+        assert_eq!(list.get_key(), "l");
+        assert!(list.get_value().is_none());
+        let list_items: Vec<&[u8]> = list.get_items().unwrap().collect();
+        assert_eq!(list_items, vec![&b"10"[..], &b"20"[..]]);
+    }
+
+    #[test]
+    fn export_skips_expired_keys() {
+        let mut db = memory_db();
+        db.set("user:a", &1).unwrap();
+        db.set_with_ttl("user:b", &2, Duration::from_millis(1))
+            .unwrap();
+        db.set("other", &3).unwrap();
+        thread::sleep(Duration::from_millis(5));
+
+        let data = db.export_keys("user:").unwrap();
+        let mut target = memory_db();
+        assert_eq!(target.import_keys(&data).unwrap(), 1);
+        assert!(target.exists("user:a"));
+        assert!(!target.exists("user:b"));
+    }
+
+    #[test]
+    fn reject_writes_keeps_the_database_unchanged() {
+        // 每个键占用 2 个字节："k" 和 "1"
+        let mut db = memory_db().with_max_memory(4, KeyValueDbEvictionPolicy::RejectWrites);
+        db.set("a", &1).unwrap();
+        db.set("b", &1).unwrap();
+        assert!(is_type(db.set("c", &1), ErrorType::OutOfMemoryBudget));
+        assert!(!db.exists("c"));
+        assert!(db.exists("a") && db.exists("b"));
+        assert!(is_type(db.lcreate("l"), ErrorType::OutOfMemoryBudget));
+
+        // 替换同样大小的值不会超过上限
+        db.set("a", &2).unwrap();
+        assert_eq!(db.total_bytes(), 4);
+
+        // 单个值超过上限时即使可以淘汰其他的键也返回错误
+        let mut db = memory_db().with_max_memory(4, KeyValueDbEvictionPolicy::Lru);
+        db.set("a", &1).unwrap();
+        assert!(is_type(db.set("b", &1234), ErrorType::OutOfMemoryBudget));
+        assert!(db.exists("a"));
+    }
+
+    #[test]
+    fn lru_and_lfu_evict_in_order() {
+        let mut db = memory_db().with_max_memory(6, KeyValueDbEvictionPolicy::Lru);
+        db.set("a", &1).unwrap();
+        db.set("b", &1).unwrap();
+        db.set("c", &1).unwrap();
+        db.get::<i32>("a");
+        db.set("d", &1).unwrap();
+        assert!(!db.exists("b"));
+        db.set("e", &1).unwrap();
+        assert!(!db.exists("c"));
+        assert!(db.exists("a") && db.exists("d") && db.exists("e"));
+
+        let mut db = memory_db().with_max_memory(6, KeyValueDbEvictionPolicy::Lfu);
+        db.set("a", &1).unwrap();
+        db.set("b", &1).unwrap();
+        db.set("c", &1).unwrap();
+        for _ in 0..3 {
+            db.get::<i32>("a");
+            db.get::<i32>("c");
+        }
+        db.get::<i32>("b");
+        db.set("d", &1).unwrap();
+        assert!(!db.exists("b"));
+        // "d" 只被写入过一次，是访问次数最少的键
+        db.set("e", &1).unwrap();
+        assert!(!db.exists("d"));
+        assert!(db.exists("a") && db.exists("c") && db.exists("e"));
+    }
+
+    #[test]
+    fn failed_set_over_a_list_keeps_the_list() {
+        let mut db =
+            KeyValueDb::temporary(KeyValueDbDumpPolicy::AutoDump, SerializationMethod::Json)
+                .unwrap();
+        db.lcreate("key").unwrap();
+        db.ladd("key", &1).unwrap();
+        // 删除数据库所在的目录，之后的 dump 都会失败
+        fs::remove_dir_all(db.path().unwrap().parent().unwrap()).unwrap();
+
+        assert!(is_type(db.set("key", &2), ErrorType::Io));
+        assert!(db.lexists("key"));
+        assert_eq!(db.lget::<i32>("key", 0), Some(1));
+        assert_eq!(db.get::<i32>("key"), None);
+        assert_eq!(db.total_keys(), 1);
+    }
+
+    #[test]
+    fn total_keys_skips_expired_keys() {
+        let mut db = memory_db();
+        db.set("a", &1).unwrap();
+        db.set_with_ttl("b", &2, Duration::from_millis(10)).unwrap();
+        db.lcreate("list").unwrap();
+        db.expire("list", Duration::from_millis(10)).unwrap();
+        assert_eq!(db.total_keys(), 3);
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(db.total_keys(), 1);
+        assert_eq!(db.total_keys(), db.get_all().len());
+    }
+
+    #[test]
+    fn rename_and_rem_treat_expired_keys_as_missing() {
+        let mut db = memory_db();
+        db.set_with_ttl("old", &1, Duration::from_millis(10))
+            .unwrap();
+        db.set("new", &2).unwrap();
+        db.set_with_ttl("gone", &3, Duration::from_millis(10))
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(!db.rename("old", "new").unwrap());
+        assert!(!db.rename("old", "old").unwrap());
+        assert_eq!(db.get::<i32>("new"), Some(2));
+        assert_eq!(db.ttl("new"), None);
+        assert!(!db.rem("gone").unwrap());
+        assert!(!db.exists("gone"));
+    }
+
+    #[test]
+    fn incr_and_decr_overflow_is_a_limit_error() {
+        let mut db = memory_db();
+        db.set("n", &i64::MAX).unwrap();
+        assert!(is_type(db.incr("n", 1), ErrorType::LimitExceeded));
+        assert!(is_type(db.decr("n", i64::MIN), ErrorType::LimitExceeded));
+        db.set("m", &i64::MIN).unwrap();
+        assert!(is_type(db.decr("m", 1), ErrorType::LimitExceeded));
+        assert_eq!(db.get::<i64>("n"), Some(i64::MAX));
+        assert_eq!(db.get::<i64>("m"), Some(i64::MIN));
+    }
+
+    #[test]
+    fn merge_conflicts_report_the_key() {
+        let mut source = memory_db();
+        source.set("a", &1).unwrap();
+        source.set("b", &2).unwrap();
+        let mut db = memory_db();
+        db.set("b", &3).unwrap();
+
+        let err = db
+            .merge_from(&source, MergeStrategy::ErrorOnConflict)
+            .unwrap_err();
+        assert!(matches!(err.get_type(), ErrorType::KeyExists));
+        assert_eq!(err.key(), Some("b"));
+        assert!(!db.exists("a"));
+        assert_eq!(db.get::<i32>("b"), Some(3));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn merging_another_format_is_an_invalid_argument() {
+        let mut source = KeyValueDb::in_memory(SerializationMethod::Bin);
+        source.set("a", &1).unwrap();
+        let mut db = memory_db();
+        assert!(is_type(
+            db.merge_from(&source, MergeStrategy::Overwrite),
+            ErrorType::InvalidArgument
+        ));
+        assert!(!db.exists("a"));
+    }
+}
@@ -1,16 +1,61 @@
 // 该模块导出了 KeyValueDb crate 中的所有公共接口，
 // 包括了对数据库的读写、数据迭代器、序列化方法、错误等。
 
+pub use self::bucket::KeyValueDbBucket;
+pub use self::cache::{Cache, KeyValueDbCache};
+pub use self::config::ConfigStore;
+pub use self::database::KeyValueDbDatabase;
+pub use self::entry::{KeyValueDbEntry, KeyValueDbOccupiedEntry, KeyValueDbVacantEntry};
 pub use self::extenders::KeyValueDbListExtender;
+pub use self::flags::{FeatureFlag, FeatureFlags, Rollout};
+pub use self::formats::FormatReport;
+pub use self::histogram::Histogram;
 pub use self::iterators::{
     KeyValueDbIterator, KeyValueDbIteratorItem, KeyValueDbListIterator, KeyValueDbListIteratorItem,
+    KeyValueDbRawItem, KeyValueDbRawIterator,
 };
-pub use self::keyvaluedb::{KeyValueDb, KeyValueDbDumpPolicy};
+pub use self::jobqueue::{Job, KeyValueDbJobQueue};
+pub use self::keyvaluedb::{
+    KeyValueDb, KeyValueDbDumpPolicy, KeyValueDbEvictionPolicy, MergeStrategy,
+};
+pub use self::listindex::ListIndex;
+pub use self::manager::{DbManager, DbManagerStats};
+pub use self::metadata::KeyMetadata;
+pub use self::ratelimit::RateLimitDecision;
+pub use self::readhandle::KeyValueDbReadHandle;
+pub use self::schedule::KeyValueDbScheduler;
 pub use self::serialization::SerializationMethod;
+pub use self::sweeper::KeyValueDbSweeper;
 
+mod background;
+mod bucket;
+mod buffer;
+mod cache;
+mod config;
+mod database;
+mod entry;
+mod eviction;
 mod extenders;
+mod flags;
+mod formats;
+mod glob;
+mod histogram;
 mod iterators;
+mod jobqueue;
+mod keymap;
 mod keyvaluedb;
+mod listindex;
+mod lock;
+mod manager;
+mod metadata;
+mod pqueue;
+mod ratelimit;
+mod readhandle;
+mod schedule;
 mod serialization;
+mod sweeper;
 
+pub mod coordination;
 pub mod error;
+pub mod keys;
+pub mod protocol;
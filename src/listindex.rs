@@ -0,0 +1,34 @@
+// lget、lpop 和 lset 的位置参数。非负数从列表开头数，负数与 Python 和 Redis 相同，从列表末尾数，
+// -1 是最后一个元素，例如 db.lget::<String>("events", -1) 返回最新添加的元素，不需要先调用 llen。
+pub trait ListIndex {
+    // 转换为长度为 len 的列表中的下标，超出范围时返回 None
+    fn resolve(self, len: usize) -> Option<usize>;
+}
+
+macro_rules! unsigned_index {
+    ($($t:ty),*) => {
+        $(impl ListIndex for $t {
+            fn resolve(self, len: usize) -> Option<usize> {
+                usize::try_from(self).ok().filter(|pos| *pos < len)
+            }
+        })*
+    };
+}
+
+macro_rules! signed_index {
+    ($($t:ty),*) => {
+        $(impl ListIndex for $t {
+            fn resolve(self, len: usize) -> Option<usize> {
+                if self < 0 {
+                    let back = usize::try_from(self.unsigned_abs()).ok()?;
+                    len.checked_sub(back)
+                } else {
+                    usize::try_from(self).ok().filter(|pos| *pos < len)
+                }
+            }
+        })*
+    };
+}
+
+unsigned_index!(usize, u32, u64);
+signed_index!(isize, i32, i64);
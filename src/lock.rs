@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+// 基于租约的锁，作为一个普通的值保存在数据库中。
+// token 是最近一次加锁时分配的隔离令牌（fencing token），每次加锁都会加一，锁被释放或者过期之后仍然保留，
+// 因此同一把锁的令牌总是递增的：持有锁的一方在写入共享资源时带上令牌，资源拒绝比已经见过的令牌更小的写入，
+// 就可以避免租约过期之后仍然以为自己持有锁的一方覆盖新的持有者的写入。
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct LockState {
+    pub(crate) token: u64,
+    // 租约的到期时间，锁被释放时为 None
+    pub(crate) held_until: Option<SystemTime>,
+}
+
+impl LockState {
+    pub(crate) fn is_held(&self, now: SystemTime) -> bool {
+        match self.held_until {
+            Some(held_until) => held_until > now,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::background::BackgroundTask;
+use crate::error::{Error, ErrorCode, Result};
+use crate::keyvaluedb::{KeyValueDb, KeyValueDbDumpPolicy};
+use crate::serialization::SerializationMethod;
+
+type DbHandle = Arc<Mutex<KeyValueDb>>;
+type DbTable = Arc<Mutex<HashMap<String, DbHandle>>>;
+
+// 管理同一个目录下的多个数据库，例如每个租户一个数据库文件。
+// 名为 name 的数据库保存在 dir/<name>.db，第一次访问时才会打开（文件不存在时创建一个空的数据库）。
+// 所有数据库都使用 DumpUponRequest 策略，修改不会立即写入文件，而是由 close、close_all 写入；
+// 使用 with_dump_interval 时还会由一个共享的后台线程定期写入有修改的数据库。
+// DbManager 被 drop 时会调用 close_all。
+pub struct DbManager {
+    dir: PathBuf,
+    serialization_method: SerializationMethod,
+    dbs: DbTable,
+    dump_thread: Option<BackgroundTask>,
+}
+
+// 所有已经打开的数据库的统计信息
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbManagerStats {
+    pub open_dbs: usize,
+    pub total_keys: usize,
+    pub total_bytes: usize,
+}
+
+impl DbManager {
+    pub fn new<P: Into<PathBuf>>(dir: P, serialization_method: SerializationMethod) -> DbManager {
+        DbManager {
+            dir: dir.into(),
+            serialization_method,
+            dbs: Arc::new(Mutex::new(HashMap::new())),
+            dump_thread: None,
+        }
+    }
+
+    // 启动一个后台线程，每隔 interval 将所有有修改的数据库写入文件。
+    // 写入失败的数据库会在下一次继续尝试，错误会在 close 或者 close_all 时返回。
+    pub fn with_dump_interval(mut self, interval: Duration) -> DbManager {
+        self.stop_dump_thread();
+
+        let dbs = Arc::clone(&self.dbs);
+        self.dump_thread = Some(BackgroundTask::every(interval, move || {
+            for db in handles(&dbs) {
+                let mut db = db.lock().unwrap();
+                if db.is_dirty() {
+                    let _ = db.dump();
+                }
+            }
+        }));
+        self
+    }
+
+    // 返回名为 name 的数据库，还没有打开时先打开它。
+    // name 只能包含字母、数字、'-' 和 '_'，否则返回 InvalidArgument 错误。
+    pub fn db(&self, name: &str) -> Result<Arc<Mutex<KeyValueDb>>> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::new(ErrorCode::InvalidArgument(format!(
+                "Invalid database name '{}'",
+                name
+            ))));
+        }
+
+        let mut dbs = self.dbs.lock().unwrap();
+        if let Some(db) = dbs.get(name) {
+            return Ok(Arc::clone(db));
+        }
+
+        let path = self.dir.join(format!("{}.db", name));
+        let db = if path.exists() {
+            KeyValueDb::load(
+                &path,
+                KeyValueDbDumpPolicy::DumpUponRequest,
+                self.serialization_method,
+            )?
+        } else {
+            KeyValueDb::new(
+                &path,
+                KeyValueDbDumpPolicy::DumpUponRequest,
+                self.serialization_method,
+            )
+        };
+        let db = Arc::new(Mutex::new(db));
+        dbs.insert(String::from(name), Arc::clone(&db));
+        Ok(db)
+    }
+
+    // 返回所有已经打开的数据库的名字
+    pub fn open_dbs(&self) -> Vec<String> {
+        self.dbs.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn stats(&self) -> DbManagerStats {
+        let dbs = handles(&self.dbs);
+        let mut stats = DbManagerStats {
+            open_dbs: dbs.len(),
+            total_keys: 0,
+            total_bytes: 0,
+        };
+        for db in dbs {
+            let db = db.lock().unwrap();
+            stats.total_keys += db.total_keys();
+            stats.total_bytes += db.total_bytes();
+        }
+        stats
+    }
+
+    // 将数据库写入文件并关闭它，数据库没有打开时返回 false。
+    // 其他地方仍然持有的 Arc 可以继续使用，但它的修改不会再被自动写入。写入失败时数据库保持打开。
+    pub fn close(&self, name: &str) -> Result<bool> {
+        let db = match self.dbs.lock().unwrap().get(name) {
+            Some(db) => Arc::clone(db),
+            None => return Ok(false),
+        };
+        db.lock().unwrap().dump()?;
+        self.dbs.lock().unwrap().remove(name);
+        Ok(true)
+    }
+
+    // 停止后台线程，将所有数据库写入文件并关闭它们。
+    // 某个数据库写入失败时继续写入其余的数据库，写入失败的数据库保持打开，返回第一个错误。
+    pub fn close_all(&mut self) -> Result<()> {
+        self.stop_dump_thread();
+
+        let mut first_error = None;
+        for name in self.open_dbs() {
+            if let Err(err) = self.close(&name) {
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn stop_dump_thread(&mut self) {
+        if let Some(mut task) = self.dump_thread.take() {
+            task.stop();
+        }
+    }
+}
+
+// 复制一份所有数据库的引用，避免在写入文件期间一直锁住整个表
+fn handles(dbs: &DbTable) -> Vec<DbHandle> {
+    dbs.lock().unwrap().values().cloned().collect()
+}
+
+impl Drop for DbManager {
+    fn drop(&mut self) {
+        let _ = self.close_all();
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+// 每个键（普通值或者列表）的元数据，随数据库文件一起保存。
+// created 是键被创建的时间，modified 是键的值最近一次被写入的时间，
+// 对列表来说，添加或删除元素都算作一次写入。
+// expires_at 是键的过期时间，None 表示永不过期；过期的键对 get、exists、iter 等方法不可见。
+// type_tag 是写入时记录的值的类型名，只有使用 with_type_tags 时才会记录，见 get_checked。
+// 字段只能通过同名的方法读取，以后添加新的元数据不会破坏调用方的代码。
+// 修改字段之后需要增加 serialization::FORMAT_VERSION，并在读取旧版本文件时转换旧的格式。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyMetadata {
+    pub(crate) created: SystemTime,
+    pub(crate) modified: SystemTime,
+    // 旧版本的 JSON、YAML 和 CBOR 文件中没有这一项
+    #[serde(default)]
+    pub(crate) expires_at: Option<SystemTime>,
+    #[serde(default)]
+    pub(crate) type_tag: Option<String>,
+}
+
+impl KeyMetadata {
+    // 新创建的键，created 和 modified 都是当前时间
+    pub(crate) fn now() -> KeyMetadata {
+        let now = SystemTime::now();
+        KeyMetadata {
+            created: now,
+            modified: now,
+            expires_at: None,
+            type_tag: None,
+        }
+    }
+
+    // 键被重新写入，保留 created 和 expires_at，modified 更新为当前时间
+    pub(crate) fn touched(&self) -> KeyMetadata {
+        KeyMetadata {
+            modified: SystemTime::now(),
+            ..self.clone()
+        }
+    }
+
+    pub fn created(&self) -> SystemTime {
+        self.created
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    pub fn type_tag(&self) -> Option<&str> {
+        self.type_tag.as_deref()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= SystemTime::now(),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// 优先队列中的一个元素，value 是使用数据库的序列化方法序列化后的数据
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PqEntry {
+    pub(crate) priority: i64,
+    // 加入队列的顺序，优先级相同时先加入的元素先出队
+    pub(crate) seq: u64,
+    pub(crate) value: Vec<u8>,
+}
+
+impl PartialEq for PqEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PqEntry {}
+
+impl PartialOrd for PqEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PqEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+// 优先队列，作为一个普通的值保存在数据库中，heap 按照二叉堆的数组顺序序列化
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct PriorityQueue {
+    pub(crate) next_seq: u64,
+    pub(crate) heap: BinaryHeap<PqEntry>,
+}
+
+impl PriorityQueue {
+    pub(crate) fn push(&mut self, priority: i64, value: Vec<u8>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(PqEntry {
+            priority,
+            seq,
+            value,
+        });
+    }
+}
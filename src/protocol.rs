@@ -0,0 +1,678 @@
+// 客户端与服务端共用的网络协议类型。
+// 客户端发送 Request（包含协议版本号和一条 Command），服务端返回一条 Response，
+// 每条消息占一行，使用 JSON 编码。
+// 为了兼容旧的客户端（例如 netcat 脚本），服务端同时接受 v1 文本协议：
+// 每行一条形如 "SET key value" 的命令，响应为一行纯文本。
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "json")]
+use crate::error::{Error, ErrorCode, Result};
+use crate::keys;
+use crate::keyvaluedb::KeyValueDb;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+
+// 当前协议版本号，服务端会拒绝版本号不一致的请求
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// SCAN 没有指定 COUNT 时每次返回的键的数量
+pub const DEFAULT_SCAN_COUNT: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    // 把字符串 value 保存在 key 下
+    Set {
+        key: String,
+        value: String,
+    },
+
+    // 读取 key 下保存的字符串
+    Get {
+        key: String,
+    },
+
+    // 一次读取多个键的字符串
+    MGet {
+        keys: Vec<String>,
+    },
+
+    // 从数据库中删除 key
+    Del {
+        key: String,
+    },
+
+    // 删除数据库中的所有键
+    FlushDb,
+
+    // 把所有以 prefix 开头的键移动到 target 地址的服务端
+    Migrate {
+        target: String,
+        prefix: String,
+    },
+
+    // 保存另一个服务端导出的键，由 Migrate 发送
+    Import {
+        data: Vec<u8>,
+    },
+
+    // 在后台把数据库的快照保存到 path，path 是相对于服务端备份目录的路径
+    Backup {
+        path: String,
+    },
+
+    // 上一次备份成功的 Unix 时间（秒）
+    LastSave,
+
+    // 导出一个普通值或者列表以及它的键
+    Dump {
+        key: String,
+    },
+
+    // 保存 Dump 导出的键，覆盖同名的已有的键
+    Restore {
+        payload: DumpPayload,
+    },
+
+    // 返回 cursor 之后最多 count 个键，第一次使用游标 "0"
+    Scan {
+        cursor: String,
+        count: usize,
+    },
+
+    // 保存数据库并停止服务端
+    Shutdown,
+
+    // 把这个连接切换到编号为 index 的逻辑数据库，编号从 0 开始
+    Select {
+        index: usize,
+    },
+
+    // 把 key 下保存的整数加上 delta，键不存在时视为 0
+    Incr {
+        key: String,
+        delta: i64,
+    },
+
+    // 把 key 下保存的整数减去 delta，键不存在时视为 0
+    Decr {
+        key: String,
+        delta: i64,
+    },
+
+    // 让已经存在的键在 seconds 秒之后过期
+    Expire {
+        key: String,
+        seconds: u64,
+    },
+
+    // key 剩余的生存时间（秒），没有过期时间时为 -1，键不存在时为 -2
+    Ttl {
+        key: String,
+    },
+
+    // 删除 key 的过期时间
+    Persist {
+        key: String,
+    },
+
+    // 获取名为 name 的锁，租约为 ttl_ms 毫秒，返回 fencing token，锁已经被持有时返回 Nil
+    Lock {
+        name: String,
+        ttl_ms: u64,
+    },
+
+    // 释放用 token 获取的名为 name 的锁
+    Unlock {
+        name: String,
+        token: u64,
+    },
+
+    // 把用 token 获取的名为 name 的锁的租约延长到从现在开始的 ttl_ms 毫秒
+    Renew {
+        name: String,
+        token: u64,
+        ttl_ms: u64,
+    },
+
+    // 从 key 下的限流器中取一个令牌，每 period_ms 毫秒最多允许 max 个请求
+    RateLimit {
+        key: String,
+        max: u64,
+        period_ms: u64,
+    },
+
+    // 读取列表 name 中从 start 到 stop（包括 stop）的字符串元素，负数从列表的末尾开始数
+    LRange {
+        name: String,
+        start: i64,
+        stop: i64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    // 命令执行成功，没有需要返回的数据
+    Ok,
+
+    // 请求的键不存在
+    Nil,
+
+    // 一个值
+    Value(String),
+
+    // 每个请求的键对应一项，不存在的键为 None
+    Values(Vec<Option<String>>),
+
+    // 整数结果，例如受影响的键的个数
+    Integer(i64),
+
+    // Dump 导出的键
+    Dump(DumpPayload),
+
+    // Scan 返回的一页键，cursor 为 "0" 时遍历结束
+    Scan {
+        cursor: String,
+        keys: Vec<String>,
+    },
+
+    // RateLimit 的结果，允许请求时 retry_after_ms 为 0
+    RateLimit {
+        allowed: bool,
+        remaining: u64,
+        retry_after_ms: u64,
+    },
+
+    // 命令执行失败
+    Error(String),
+}
+
+// DUMP 导出的一个键，data 是只包含该键的数据库文件格式的数据，
+// format 记录了数据使用的序列化方法，checksum 用于在 RESTORE 时检查数据在传输中是否损坏。
+// 在 v1 文本协议中表示为一行 "<format> <checksum> <十六进制数据>"。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DumpPayload {
+    pub format: String,
+    pub checksum: u32,
+    pub data: Vec<u8>,
+}
+
+impl DumpPayload {
+    pub fn new(format: String, data: Vec<u8>) -> DumpPayload {
+        DumpPayload {
+            format,
+            checksum: checksum(&data),
+            data,
+        }
+    }
+
+    // 检查 checksum 是否与数据一致
+    pub fn is_valid(&self) -> bool {
+        self.checksum == checksum(&self.data)
+    }
+}
+
+// 计算数据的 FNV-1a 32 位校验和
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x01000193)
+    })
+}
+
+impl fmt::Display for DumpPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} ", self.format, self.checksum)?;
+        for byte in &self.data {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DumpPayload {
+    type Err = String;
+
+    fn from_str(line: &str) -> std::result::Result<Self, Self::Err> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() != 3 {
+            return Err(String::from("Expected '<format> <checksum> <data>'"));
+        }
+
+        let checksum = match tokens[1].parse::<u32>() {
+            Ok(checksum) => checksum,
+            Err(err) => return Err(format!("Invalid checksum: {}", err)),
+        };
+
+        let hex = tokens[2];
+        if !hex.len().is_multiple_of(2) {
+            return Err(String::from("Invalid data: odd number of hex digits"));
+        }
+        let mut data = Vec::with_capacity(hex.len() / 2);
+        for i in (0..hex.len()).step_by(2) {
+            match hex
+                .get(i..i + 2)
+                .map(|digits| u8::from_str_radix(digits, 16))
+            {
+                Some(Ok(byte)) => data.push(byte),
+                _ => return Err(String::from("Invalid data: not a hex string")),
+            }
+        }
+
+        Ok(DumpPayload {
+            format: String::from(tokens[0]),
+            checksum,
+            data,
+        })
+    }
+}
+
+// 解码 v1 文本协议中的一个参数。无法解码的参数（例如 "50%off"）按原样使用，
+// 与支持转义之前的服务端相同，这样旧的客户端发送的包含 '%' 的键仍然可以使用
+fn unescape_token(token: &str) -> String {
+    keys::unescape(token).unwrap_or_else(|| String::from(token))
+}
+
+// 一次请求，携带协议版本号，便于服务端识别不兼容的客户端
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub command: Command,
+}
+
+impl Request {
+    // 使用当前协议版本号创建请求
+    pub fn new(command: Command) -> Request {
+        Request {
+            version: PROTOCOL_VERSION,
+            command,
+        }
+    }
+}
+
+// 从命令行文本解析出命令，例如 "SET key some value"、"GET key"、"MGET k1 k2"、"DEL key"。
+// 命令名不区分大小写，SET 的值可以包含空格。Import 只能通过 JSON 协议发送。
+// SCAN 的格式为 "SCAN <cursor> [COUNT <count>]"，RATELIMIT 的格式为 "RATELIMIT <key> <max> <period_ms>"，
+// LOCK、UNLOCK 和 RENEW 的格式为 "LOCK <name> <ttl_ms>"、"UNLOCK <name> <token>" 和
+// "RENEW <name> <token> <ttl_ms>"，LRANGE 的格式为 "LRANGE <name> <start> <stop>"。
+// 键和其他的参数用 keys::unescape 解码，包含空格或者 '%' 的键需要先用 keys::escape_key 转义，例如 "GET a%20b"，
+// 无法解码的参数按原样使用，见 unescape_token。
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> std::result::Result<Self, Self::Err> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let name = match tokens.first() {
+            Some(name) => name.to_uppercase(),
+            None => return Err(String::from("Empty command")),
+        };
+
+        let key = || match tokens.get(1) {
+            Some(key) => Ok(unescape_token(key)),
+            None => Err(format!("{} requires an argument", name)),
+        };
+
+        // INCR 和 DECR 的第二个参数，省略时为 1
+        let delta = || match tokens.get(2) {
+            Some(delta) => delta
+                .parse::<i64>()
+                .map_err(|err| format!("Invalid delta: {}", err)),
+            None => Ok(1),
+        };
+
+        match name.as_str() {
+            "SET" => Ok(Command::Set {
+                key: key()?,
+                value: tokens[2..].join(" "),
+            }),
+            "GET" => Ok(Command::Get { key: key()? }),
+            "MGET" => {
+                key()?;
+                Ok(Command::MGet {
+                    keys: tokens[1..].iter().map(|key| unescape_token(key)).collect(),
+                })
+            }
+            "DEL" => Ok(Command::Del { key: key()? }),
+            "FLUSHDB" => Ok(Command::FlushDb),
+            "MIGRATE" => match tokens.get(2) {
+                Some(prefix) => Ok(Command::Migrate {
+                    target: key()?,
+                    prefix: unescape_token(prefix),
+                }),
+                None => Err(String::from("MIGRATE requires a target and a prefix")),
+            },
+            "BACKUP" | "BGSAVE" => Ok(Command::Backup { path: key()? }),
+            "LASTSAVE" => Ok(Command::LastSave),
+            "SHUTDOWN" => Ok(Command::Shutdown),
+            "SELECT" => match key()?.parse::<usize>() {
+                Ok(index) => Ok(Command::Select { index }),
+                Err(err) => Err(format!("Invalid database index: {}", err)),
+            },
+            "INCR" | "INCRBY" => Ok(Command::Incr {
+                key: key()?,
+                delta: delta()?,
+            }),
+            "DECR" | "DECRBY" => Ok(Command::Decr {
+                key: key()?,
+                delta: delta()?,
+            }),
+            "EXPIRE" => match tokens.get(2) {
+                Some(seconds) => Ok(Command::Expire {
+                    key: key()?,
+                    seconds: seconds
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid seconds: {}", err))?,
+                }),
+                None => Err(String::from("EXPIRE requires a key and seconds")),
+            },
+            "TTL" => Ok(Command::Ttl { key: key()? }),
+            "PERSIST" => Ok(Command::Persist { key: key()? }),
+            "DUMP" => Ok(Command::Dump { key: key()? }),
+            "RESTORE" => Ok(Command::Restore {
+                payload: tokens[1..].join(" ").parse()?,
+            }),
+            "SCAN" => {
+                let count = match (tokens.get(2), tokens.get(3)) {
+                    (None, _) => DEFAULT_SCAN_COUNT,
+                    (Some(option), Some(count)) if option.eq_ignore_ascii_case("COUNT") => {
+                        match count.parse::<usize>() {
+                            Ok(count) => count,
+                            Err(err) => return Err(format!("Invalid count: {}", err)),
+                        }
+                    }
+                    _ => return Err(String::from("Expected 'SCAN <cursor> [COUNT <count>]'")),
+                };
+                Ok(Command::Scan {
+                    cursor: key()?,
+                    count,
+                })
+            }
+            "LOCK" => match tokens.get(2) {
+                Some(ttl_ms) => Ok(Command::Lock {
+                    name: key()?,
+                    ttl_ms: ttl_ms
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid ttl: {}", err))?,
+                }),
+                None => Err(String::from("Expected 'LOCK <name> <ttl_ms>'")),
+            },
+            "UNLOCK" => match tokens.get(2) {
+                Some(token) => Ok(Command::Unlock {
+                    name: key()?,
+                    token: token
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid token: {}", err))?,
+                }),
+                None => Err(String::from("Expected 'UNLOCK <name> <token>'")),
+            },
+            "RENEW" => match (tokens.get(2), tokens.get(3)) {
+                (Some(token), Some(ttl_ms)) => Ok(Command::Renew {
+                    name: key()?,
+                    token: token
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid token: {}", err))?,
+                    ttl_ms: ttl_ms
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid ttl: {}", err))?,
+                }),
+                _ => Err(String::from("Expected 'RENEW <name> <token> <ttl_ms>'")),
+            },
+            "RATELIMIT" => match (tokens.get(2), tokens.get(3)) {
+                (Some(max), Some(period_ms)) => Ok(Command::RateLimit {
+                    key: key()?,
+                    max: max
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid max: {}", err))?,
+                    period_ms: period_ms
+                        .parse::<u64>()
+                        .map_err(|err| format!("Invalid period: {}", err))?,
+                }),
+                _ => Err(String::from("Expected 'RATELIMIT <key> <max> <period_ms>'")),
+            },
+            "LRANGE" => match (tokens.get(2), tokens.get(3)) {
+                (Some(start), Some(stop)) => Ok(Command::LRange {
+                    name: key()?,
+                    start: start
+                        .parse::<i64>()
+                        .map_err(|err| format!("Invalid start: {}", err))?,
+                    stop: stop
+                        .parse::<i64>()
+                        .map_err(|err| format!("Invalid stop: {}", err))?,
+                }),
+                _ => Err(String::from("Expected 'LRANGE <name> <start> <stop>'")),
+            },
+            _ => Err(format!("Invalid command '{}'", tokens[0])),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Response::Ok => f.write_str("OK"),
+            Response::Nil => f.write_str("nil"),
+            Response::Value(value) => f.write_str(value),
+            Response::Values(values) => {
+                let values: Vec<&str> = values
+                    .iter()
+                    .map(|value| value.as_deref().unwrap_or("nil"))
+                    .collect();
+                f.write_str(&values.join(" "))
+            }
+            Response::Integer(num) => write!(f, "{}", num),
+            Response::Dump(payload) => write!(f, "{}", payload),
+            // 与请求相同，键用 keys::escape_key 转义
+            Response::Scan { cursor, keys } => {
+                f.write_str(&keys::escape_key(cursor))?;
+                for key in keys {
+                    write!(f, " {}", keys::escape_key(key))?;
+                }
+                Ok(())
+            }
+            // 与 Redis 的 CL.THROTTLE 类似，依次为是否被拒绝（0 或 1）、剩余次数和需要等待的毫秒数
+            Response::RateLimit {
+                allowed,
+                remaining,
+                retry_after_ms,
+            } => write!(f, "{} {} {}", !allowed as u8, remaining, retry_after_ms),
+            Response::Error(err) => write!(f, "ERR {}", err),
+        }
+    }
+}
+
+// 在数据库上执行一条命令并返回响应。
+// Migrate、Backup、LastSave、Shutdown 和 Select 依赖服务端的状态，需要由服务端自己处理，这里会返回错误。
+pub fn execute(db: &mut KeyValueDb, command: Command) -> Response {
+    match command {
+        Command::Set { key, value } => match db.set(&key, &value) {
+            Ok(_) => Response::Ok,
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Get { key } => match db.get::<String>(&key) {
+            Some(value) => Response::Value(value),
+            None => Response::Nil,
+        },
+        Command::MGet { keys } => {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            let values = db.get_multiple::<String>(&keys);
+            Response::Values(keys.iter().map(|key| values[*key].clone()).collect())
+        }
+        Command::Del { key } => match db.rem(&key) {
+            Ok(true) => Response::Ok,
+            Ok(false) => Response::Nil,
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::FlushDb => match db.clear() {
+            Ok(_) => Response::Ok,
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Import { data } => match db.import_keys(&data) {
+            Ok(count) => Response::Integer(count as i64),
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Dump { key } => match db.export_key(&key) {
+            Ok(Some(data)) => Response::Dump(DumpPayload::new(
+                db.serialization_method().to_string(),
+                data,
+            )),
+            Ok(None) => Response::Nil,
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Restore { payload } => {
+            if !payload.is_valid() {
+                Response::Error(String::from("Checksum mismatch"))
+            } else if payload.format != db.serialization_method().to_string() {
+                Response::Error(format!(
+                    "Payload format {} doesn't match the database format {}",
+                    payload.format,
+                    db.serialization_method()
+                ))
+            } else {
+                match db.import_keys(&payload.data) {
+                    Ok(_) => Response::Ok,
+                    Err(err) => Response::Error(err.to_string()),
+                }
+            }
+        }
+        Command::Incr { key, delta } => incr(db, &key, Some(delta)),
+        Command::Decr { key, delta } => incr(db, &key, delta.checked_neg()),
+        Command::Scan { cursor, count } => match db.scan(&cursor, count) {
+            Ok((cursor, keys)) => Response::Scan { cursor, keys },
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Expire { key, seconds } => match db.expire(&key, Duration::from_secs(seconds)) {
+            Ok(expired) => Response::Integer(expired as i64),
+            Err(err) => Response::Error(err.to_string()),
+        },
+        // 与 Redis 相同，剩余时间四舍五入到秒
+        Command::Ttl { key } => match db.ttl(&key) {
+            Some(ttl) => Response::Integer(((ttl.as_millis() + 500) / 1000) as i64),
+            None if db.exists(&key) => Response::Integer(-1),
+            None => Response::Integer(-2),
+        },
+        Command::Persist { key } => match db.persist(&key) {
+            Ok(persisted) => Response::Integer(persisted as i64),
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Lock { name, ttl_ms } => match db.lock(&name, Duration::from_millis(ttl_ms)) {
+            Ok(Some(token)) => Response::Integer(token as i64),
+            Ok(None) => Response::Nil,
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Unlock { name, token } => match db.unlock(&name, token) {
+            Ok(released) => Response::Integer(released as i64),
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::Renew {
+            name,
+            token,
+            ttl_ms,
+        } => match db.renew_lock(&name, token, Duration::from_millis(ttl_ms)) {
+            Ok(renewed) => Response::Integer(renewed as i64),
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::RateLimit {
+            key,
+            max,
+            period_ms,
+        } => match db.rate_limit(&key, max, Duration::from_millis(period_ms)) {
+            Ok(decision) => Response::RateLimit {
+                allowed: decision.allowed,
+                remaining: decision.remaining,
+                // 向上取整，避免被拒绝的请求返回 0
+                retry_after_ms: decision.retry_after.map_or(0, |retry_after| {
+                    retry_after.as_micros().div_ceil(1000) as u64
+                }),
+            },
+            Err(err) => Response::Error(err.to_string()),
+        },
+        Command::LRange { name, start, stop } => Response::Values(
+            db.lrange::<String>(&name, start, stop)
+                .into_iter()
+                .map(Some)
+                .collect(),
+        ),
+        Command::Migrate { .. }
+        | Command::Backup { .. }
+        | Command::LastSave
+        | Command::Shutdown
+        | Command::Select { .. } => {
+            Response::Error(String::from("Command is only supported by the server"))
+        }
+    }
+}
+
+// 通过 SET 写入的值都是字符串，因此新建的计数器也以字符串的形式保存，这样 GET 可以读取它，
+// 已有的值由 KeyValueDb::incr 处理，它会保持值原来的类型。delta 为 None 表示 DECR 的 delta 取反后溢出。
+fn incr(db: &mut KeyValueDb, key: &str, delta: Option<i64>) -> Response {
+    let delta = match delta {
+        Some(delta) => delta,
+        None => return Response::Error(format!("Decrementing '{}' would overflow", key)),
+    };
+    if db.exists(key) {
+        match db.incr(key, delta) {
+            Ok(value) => Response::Integer(value),
+            Err(err) => Response::Error(err.to_string()),
+        }
+    } else {
+        match db.set(key, &delta.to_string()) {
+            Ok(_) => Response::Integer(delta),
+            Err(err) => Response::Error(err.to_string()),
+        }
+    }
+}
+
+// 判断一行请求是否为 v1 文本协议，JSON 编码的请求总是以 '{' 开头
+pub fn is_v1_text(line: &str) -> bool {
+    !line.trim_start().starts_with('{')
+}
+
+// 将 v1 文本命令转换为 Command
+pub fn parse_v1(line: &str) -> std::result::Result<Command, String> {
+    line.parse::<Command>()
+}
+
+// 将响应编码为 v1 文本协议的一行纯文本
+pub fn encode_v1(response: &Response) -> String {
+    format!("{}\n", response)
+}
+
+// 将一条消息编码为以换行符结尾的一行 JSON 文本
+#[cfg(feature = "json")]
+pub fn encode<T: Serialize>(message: &T) -> Result<String> {
+    match serde_json::to_string(message) {
+        Ok(mut line) => {
+            line.push('\n');
+            Ok(line)
+        }
+        Err(err) => Err(Error::new(ErrorCode::Serialization(err.to_string()))),
+    }
+}
+
+// 从一行 JSON 文本解码出一条消息
+#[cfg(feature = "json")]
+pub fn decode<T: DeserializeOwned>(line: &str) -> Result<T> {
+    match serde_json::from_str(line.trim()) {
+        Ok(message) => Ok(message),
+        Err(err) => Err(Error::new(ErrorCode::Serialization(err.to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_arguments_are_unescaped_when_possible() {
+        let get = |line: &str| match line.parse::<Command>() {
+            Ok(Command::Get { key }) => key,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(get("GET a%20b"), "a b");
+        assert_eq!(get("GET 100%25"), "100%");
+        // 支持转义之前的客户端发送的包含 '%' 的键
+        assert_eq!(get("GET 50%off"), "50%off");
+        assert_eq!(get("GET 50%"), "50%");
+    }
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+// rate_limit 的结果。allowed 为 false 时请求应被拒绝，retry_after 是至少需要等待多久才会有新的令牌。
+// remaining 是本次请求之后还剩下的令牌数，即在不等待的情况下还可以通过多少个请求。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub remaining: u64,
+    pub retry_after: Option<Duration>,
+}
+
+// 令牌桶，作为一个普通的值保存在数据库中。
+// 桶的容量为 max，每隔 per / max 补充一个令牌，每个请求消耗一个令牌。
+// 使用 SystemTime 而不是 Instant，因为桶需要随数据库文件保存，在进程重启之后继续生效。
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    updated: SystemTime,
+}
+
+impl TokenBucket {
+    // 新建一个装满令牌的桶
+    pub(crate) fn full(max: u64, now: SystemTime) -> TokenBucket {
+        TokenBucket {
+            tokens: max as f64,
+            updated: now,
+        }
+    }
+
+    // 先按照上次更新以来经过的时间补充令牌，再尝试取出一个令牌。
+    // 只有取出了令牌时桶才会被修改，被拒绝的请求不会改变桶的状态。
+    pub(crate) fn take(&mut self, max: u64, per: Duration, now: SystemTime) -> RateLimitDecision {
+        let rate = max as f64 / per.as_secs_f64();
+        // 系统时间被调回时不补充令牌
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        let tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(max as f64);
+
+        if tokens >= 1.0 {
+            self.tokens = tokens - 1.0;
+            self.updated = now;
+            RateLimitDecision {
+                allowed: true,
+                remaining: self.tokens as u64,
+                retry_after: None,
+            }
+        } else {
+            RateLimitDecision {
+                allowed: false,
+                remaining: 0,
+                retry_after: Some(Duration::from_secs_f64((1.0 - tokens) / rate)),
+            }
+        }
+    }
+
+    // 桶重新装满所需的时间，之后的桶与一个新建的桶没有区别，可以让这个键过期
+    pub(crate) fn refill_time(&self, max: u64, per: Duration) -> Duration {
+        let missing = (max as f64 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / max as f64 * per.as_secs_f64())
+    }
+}
@@ -0,0 +1,245 @@
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, TryLockError};
+
+use crate::listindex::ListIndex;
+use crate::metadata::KeyMetadata;
+use crate::serialization::{DbList, SerializationMethod, Serializer};
+
+// 发布给读句柄的 0 号逻辑数据库的副本
+#[derive(Default)]
+pub(crate) struct ReadSnapshot {
+    pub(crate) map: HashMap<String, Vec<u8>>,
+    pub(crate) list_map: HashMap<String, DbList>,
+    pub(crate) meta: HashMap<String, KeyMetadata>,
+}
+
+impl ReadSnapshot {
+    fn is_expired(&self, key: &str) -> bool {
+        self.meta.get(key).is_some_and(KeyMetadata::is_expired)
+    }
+}
+
+// KeyValueDb 与它的所有读句柄共享的最新快照，每次发布新的快照时 version 加一。
+// 读句柄只在 version 变化之后才需要获取 Mutex，其余的读取只有一次原子操作。
+// Mutex 只在交换 Arc 时持有，读句柄用 try_lock 获取，不会等待正在发布快照的 KeyValueDb。
+pub(crate) struct Published {
+    version: AtomicU64,
+    snapshot: Mutex<Arc<ReadSnapshot>>,
+}
+
+impl Published {
+    pub(crate) fn new(snapshot: ReadSnapshot) -> Published {
+        Published {
+            version: AtomicU64::new(0),
+            snapshot: Mutex::new(Arc::new(snapshot)),
+        }
+    }
+
+    pub(crate) fn publish(&self, snapshot: ReadSnapshot) {
+        let snapshot = Arc::new(snapshot);
+        let old = mem::replace(&mut *self.snapshot.lock().unwrap(), snapshot);
+        self.version.fetch_add(1, Ordering::Release);
+        // 旧的快照可能是最后一个引用，在释放锁之后再释放它的内存
+        drop(old);
+    }
+}
+
+// KeyValueDb::read_handle 返回的只读句柄，读取的是最近一次 dump 时 0 号逻辑数据库的内容，
+// 不需要获取 KeyValueDb 本身的锁，适合读多写少的服务端在每个线程中持有一个：
+//
+//     let mut reader = db.lock().unwrap().read_handle();
+//     thread::spawn(move || reader.get::<String>("config:mode"));
+//
+// 读取方法需要 &mut self，用于在数据库发布新的快照之后更新句柄中缓存的快照；
+// 需要在多个线程中读取时 clone 句柄。读取方法不会阻塞：数据库正在发布新的快照时继续读取当前的快照，
+// 下一次读取时再换用新的快照。快照在进程中保存一份完整的副本，每次 dump 都需要复制整个 0 号逻辑数据库，
+// 其他逻辑数据库不会发布给读句柄。
+pub struct KeyValueDbReadHandle {
+    shared: Arc<Published>,
+    version: u64,
+    snapshot: Arc<ReadSnapshot>,
+    serializer: Serializer,
+}
+
+impl KeyValueDbReadHandle {
+    pub(crate) fn new(shared: Arc<Published>, method: SerializationMethod) -> KeyValueDbReadHandle {
+        let version = shared.version.load(Ordering::Acquire);
+        let snapshot = Arc::clone(&shared.snapshot.lock().unwrap());
+        KeyValueDbReadHandle {
+            shared,
+            version,
+            snapshot,
+            serializer: Serializer::new(method),
+        }
+    }
+
+    // 数据库发布了新的快照时换用新的快照。锁正在被发布快照的 KeyValueDb 持有时不等待，
+    // 继续使用当前的快照，version 保持不变，下一次读取时再尝试
+    fn refresh(&mut self) {
+        let version = self.shared.version.load(Ordering::Acquire);
+        if version == self.version {
+            return;
+        }
+        let snapshot = match self.shared.snapshot.try_lock() {
+            Ok(snapshot) => Arc::clone(&snapshot),
+            Err(TryLockError::Poisoned(err)) => Arc::clone(&err.into_inner()),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        self.snapshot = snapshot;
+        self.version = version;
+    }
+
+    pub fn get<V>(&mut self, key: &str) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        self.refresh();
+        if self.snapshot.is_expired(key) {
+            return None;
+        }
+        self.snapshot
+            .map
+            .get(key)
+            .and_then(|value| self.serializer.deserialize_data(value))
+    }
+
+    pub fn exists(&mut self, key: &str) -> bool {
+        self.refresh();
+        let snapshot = &self.snapshot;
+        !snapshot.is_expired(key)
+            && (snapshot.map.contains_key(key) || snapshot.list_map.contains_key(key))
+    }
+
+    pub fn lget<V>(&mut self, name: &str, pos: impl ListIndex) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        self.refresh();
+        if self.snapshot.is_expired(name) {
+            return None;
+        }
+        self.snapshot
+            .list_map
+            .get(name)
+            .and_then(|list| pos.resolve(list.len()).map(|pos| &list[pos]))
+            .and_then(|value| self.serializer.deserialize_data(value))
+    }
+
+    pub fn llen(&mut self, name: &str) -> usize {
+        self.refresh();
+        match self.snapshot.list_map.get(name) {
+            Some(list) if !self.snapshot.is_expired(name) => list.len(),
+            _ => 0,
+        }
+    }
+
+    // 与 KeyValueDb::get_all 相同，返回所有没有过期的键（包括普通值和列表）
+    pub fn get_all(&mut self) -> Vec<String> {
+        self.refresh();
+        let snapshot = &self.snapshot;
+        snapshot
+            .map
+            .keys()
+            .chain(snapshot.list_map.keys())
+            .filter(|key| !snapshot.is_expired(key))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Clone for KeyValueDbReadHandle {
+    fn clone(&self) -> KeyValueDbReadHandle {
+        KeyValueDbReadHandle {
+            shared: Arc::clone(&self.shared),
+            version: self.version,
+            snapshot: Arc::clone(&self.snapshot),
+            serializer: Serializer::new(self.serializer.method()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::keyvaluedb::{KeyValueDb, KeyValueDbDumpPolicy};
+
+    fn snapshot(value: &str) -> ReadSnapshot {
+        let mut snapshot = ReadSnapshot::default();
+        snapshot
+            .map
+            .insert(String::from("key"), format!("\"{}\"", value).into_bytes());
+        snapshot
+    }
+
+    #[test]
+    fn readers_do_not_wait_for_a_publish() {
+        let shared = Arc::new(Published::new(snapshot("old")));
+        let mut reader = KeyValueDbReadHandle::new(Arc::clone(&shared), SerializationMethod::Json);
+        shared.publish(snapshot("new"));
+
+        // 模拟正在发布快照：读句柄继续读取缓存的快照，而不是等待锁
+        let guard = shared.snapshot.lock().unwrap();
+        assert_eq!(reader.get::<String>("key").as_deref(), Some("old"));
+        drop(guard);
+        assert_eq!(reader.get::<String>("key").as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn handles_see_writes_only_after_a_dump() {
+        let mut db = KeyValueDb::temporary(
+            KeyValueDbDumpPolicy::DumpUponRequest,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.set("key", &"old").unwrap();
+        let mut reader = db.read_handle();
+        assert_eq!(reader.get::<String>("key").as_deref(), Some("old"));
+
+        db.set("key", &"new").unwrap();
+        db.set("other", &1).unwrap();
+        db.lcreate("list").unwrap();
+        db.ladd("list", &1).unwrap();
+        // 还没有 dump，读句柄读取的仍然是旧的快照
+        assert_eq!(reader.get::<String>("key").as_deref(), Some("old"));
+        assert!(!reader.exists("other"));
+        assert_eq!(reader.llen("list"), 0);
+
+        db.dump().unwrap();
+        assert_eq!(reader.get::<String>("key").as_deref(), Some("new"));
+        assert_eq!(reader.lget::<i32>("list", -1), Some(1));
+        let mut keys = reader.get_all();
+        keys.sort();
+        assert_eq!(keys, vec!["key", "list", "other"]);
+
+        // 只有 0 号逻辑数据库被发布
+        db.select(1);
+        db.set("elsewhere", &1).unwrap();
+        db.dump().unwrap();
+        assert!(!reader.exists("elsewhere"));
+        assert!(reader.exists("other"));
+    }
+
+    #[test]
+    fn readers_keep_the_old_snapshot_alive_until_they_refresh() {
+        let shared = Arc::new(Published::new(snapshot("old")));
+        let mut reader = KeyValueDbReadHandle::new(Arc::clone(&shared), SerializationMethod::Json);
+        let mut other = reader.clone();
+        let old = Arc::downgrade(&reader.snapshot);
+
+        shared.publish(snapshot("new"));
+        // 发布之后 Published 不再持有旧的快照，但两个读句柄仍然在使用它
+        assert!(!Arc::ptr_eq(
+            &shared.snapshot.lock().unwrap(),
+            &reader.snapshot
+        ));
+        assert_eq!(old.strong_count(), 2);
+
+        assert_eq!(reader.get::<String>("key").as_deref(), Some("new"));
+        assert_eq!(old.strong_count(), 1);
+        assert_eq!(other.get::<String>("key").as_deref(), Some("new"));
+        assert!(old.upgrade().is_none());
+    }
+}
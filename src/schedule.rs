@@ -0,0 +1,155 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::background::BackgroundTask;
+use crate::keyvaluedb::KeyValueDb;
+
+// 定时器保存在以此为前缀的键中，定时器 key 保存在键 "schedule:<key>" 中
+pub(crate) const SCHEDULE_PREFIX: &str = "schedule:";
+
+// schedule 和 schedule_to_list 创建的定时器，作为一个普通的值保存在数据库中，随数据库文件一起保存。
+// payload 是使用数据库的序列化方法序列化后的数据
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ScheduledEntry {
+    pub(crate) due: SystemTime,
+    // 到期时把 payload 追加到这个列表的末尾，None 表示交给 take_due 的调用者处理
+    pub(crate) target: Option<String>,
+    pub(crate) payload: Vec<u8>,
+}
+
+pub(crate) fn schedule_key(key: &str) -> String {
+    format!("{}{}", SCHEDULE_PREFIX, key)
+}
+
+// 在后台线程中每隔 tick 调用一次 take_due：到期的 schedule_to_list 定时器的数据被追加到目标列表，
+// 到期的 schedule 定时器依次交给 handler 处理，handler 在数据库的锁之外调用。
+// 定时器的精度就是 tick，进程重启期间到期的定时器在重启后的第一个 tick 触发。
+// 定时器在调用 handler 之前就已经从数据库中删除，handler 执行期间进程退出时这个定时器不会再次触发。
+// KeyValueDbScheduler 被 drop 时停止后台线程。例如：
+//
+//     let db = Arc::new(Mutex::new(db));
+//     db.lock().unwrap().schedule("reminder:42", at, &"call back")?;
+//     let _scheduler = KeyValueDbScheduler::start(Arc::clone(&db), Duration::from_secs(1),
+//         |key, message: String| println!("{}: {}", key, message));
+pub struct KeyValueDbScheduler {
+    task: BackgroundTask,
+}
+
+impl KeyValueDbScheduler {
+    pub fn start<V, F>(db: Arc<Mutex<KeyValueDb>>, tick: Duration, mut handler: F) -> Self
+    where
+        V: DeserializeOwned + 'static,
+        F: FnMut(&str, V) + Send + 'static,
+    {
+        let task = BackgroundTask::every(tick, move || {
+            // 出错时（例如 dump 失败）定时器保持不变，在下一个 tick 继续尝试
+            let fired = db.lock().unwrap().take_due::<V>().unwrap_or_default();
+            for (key, payload) in fired {
+                handler(&key, payload);
+            }
+        });
+        KeyValueDbScheduler { task }
+    }
+
+    // 停止后台线程，等待正在执行的 handler 返回
+    pub fn stop(mut self) {
+        self.task.stop();
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::keyvaluedb::KeyValueDbDumpPolicy;
+    use crate::serialization::SerializationMethod;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn memory_db() -> KeyValueDb {
+        KeyValueDb::in_memory(SerializationMethod::Json)
+    }
+
+    #[test]
+    fn scheduler_fires_entries_at_their_due_time() {
+        let db = Arc::new(Mutex::new(memory_db()));
+        let start = SystemTime::now();
+        let first = start + Duration::from_millis(30);
+        let second = start + Duration::from_millis(80);
+        {
+            let mut db = db.lock().unwrap();
+            db.schedule("second", second, &2).unwrap();
+            db.schedule("first", first, &1).unwrap();
+        }
+
+        let (sender, fired) = mpsc::channel();
+        let scheduler = KeyValueDbScheduler::start(
+            Arc::clone(&db),
+            Duration::from_millis(5),
+            move |key, n: i32| {
+                sender
+                    .send((String::from(key), n, SystemTime::now()))
+                    .unwrap()
+            },
+        );
+        let wait = Duration::from_secs(5);
+        let (key, n, at) = fired.recv_timeout(wait).unwrap();
+        assert_eq!((key.as_str(), n), ("first", 1));
+        assert!(at >= first);
+        let (key, n, at) = fired.recv_timeout(wait).unwrap();
+        assert_eq!((key.as_str(), n), ("second", 2));
+        assert!(at >= second);
+        scheduler.stop();
+
+        // 触发之后定时器被删除
+        assert_eq!(db.lock().unwrap().scheduled_at("first"), None);
+        assert!(fired.try_recv().is_err());
+    }
+
+    #[test]
+    fn unscheduled_entries_never_fire() {
+        let mut db = memory_db();
+        let at = SystemTime::now() + Duration::from_millis(10);
+        db.schedule("a", at, &1).unwrap();
+        db.schedule("b", at, &2).unwrap();
+        assert_eq!(db.scheduled_at("a"), Some(at));
+        assert!(db.take_due::<i32>().unwrap().is_empty());
+
+        assert!(db.unschedule("a").unwrap());
+        assert!(!db.unschedule("a").unwrap());
+        assert_eq!(db.scheduled_at("a"), None);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(db.take_due::<i32>().unwrap(), vec![(String::from("b"), 2)]);
+        assert!(db.take_due::<i32>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn scheduled_entries_survive_a_reload() {
+        let mut db =
+            KeyValueDb::temporary(KeyValueDbDumpPolicy::AutoDump, SerializationMethod::Json)
+                .unwrap();
+        let at = SystemTime::now() + Duration::from_millis(20);
+        db.schedule("reminder", at, &"call back").unwrap();
+        db.schedule_to_list("job", at, "queue", &7).unwrap();
+
+        let path = db.path().unwrap().to_path_buf();
+        let mut loaded = KeyValueDb::load(
+            path,
+            KeyValueDbDumpPolicy::NeverDump,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        assert_eq!(loaded.scheduled_at("reminder"), Some(at));
+        assert_eq!(loaded.scheduled_at("job"), Some(at));
+
+        thread::sleep(Duration::from_millis(30));
+        let fired = loaded.take_due::<String>().unwrap();
+        assert_eq!(
+            fired,
+            vec![(String::from("reminder"), String::from("call back"))]
+        );
+        assert_eq!(loaded.lget::<i32>("queue", 0), Some(7));
+        assert_eq!(loaded.scheduled_at("job"), None);
+    }
+}
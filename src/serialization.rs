@@ -1,11 +1,126 @@
 use serde::{de::DeserializeOwned, Serialize};
+#[cfg(any(feature = "json", feature = "yaml"))]
 use std::collections::HashMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 
-type DbMap = HashMap<String, Vec<u8>>;
-type DbListMap = HashMap<String, Vec<Vec<u8>>>;
+#[cfg(feature = "bincode")]
+use bincode::Options;
+
+use crate::keymap::KeyMap;
+use crate::metadata::KeyMetadata;
+
+type DbMap = KeyMap<Vec<u8>>;
+// 列表使用 VecDeque 保存，在两端添加和删除元素都是 O(1)，序列化后的格式与 Vec 相同
+pub(crate) type DbList = VecDeque<Vec<u8>>;
+type DbListMap = KeyMap<DbList>;
+type DbMetaMap = KeyMap<KeyMetadata>;
+
+// 一个逻辑数据库的普通值、列表和元数据。
+// 0 号数据库保存在数据库文件的前三项中，与旧版本的文件格式相同；其他编号的数据库保存在第四项中。
+pub(crate) type Keyspace = (DbMap, DbListMap, DbMetaMap);
+pub(crate) type KeyspaceRef<'a> = (&'a DbMap, &'a DbListMap, &'a DbMetaMap);
+type OtherDbs = BTreeMap<usize, Keyspace>;
+type OtherDbRefs<'a> = BTreeMap<usize, KeyspaceRef<'a>>;
+type Db = (DbMap, DbListMap, DbMetaMap, OtherDbs);
+
+// 数据库文件以一行 "KVSTORE <版本号>" 开头，之后是用序列化方法序列化的 (普通值, 列表, 元数据, 其他编号的数据库)。
+// 文件的格式（包括 KeyMetadata 的字段）发生变化时需要增加 FORMAT_VERSION，
+// 读取时按照版本号选择格式，不认识的版本号返回错误，而不是猜测文件的格式。
+// 没有这一行的文件是加入版本号之前写入的，其中只有 (普通值, 列表)，由各个序列化方法的 deserialize_legacy_db 读取。
+const FILE_MAGIC: &[u8] = b"KVSTORE ";
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+// 返回文件头中的版本号和之后的数据，没有文件头时返回 None
+fn split_header(ser_db: &[u8]) -> Result<Option<(u32, &[u8])>, String> {
+    let rest = match ser_db.strip_prefix(FILE_MAGIC) {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+    let end = match rest.iter().position(|byte| *byte == b'\n') {
+        Some(end) => end,
+        None => return Err(String::from("Truncated file header")),
+    };
+    match std::str::from_utf8(&rest[..end]).map(str::parse::<u32>) {
+        Ok(Ok(version)) => Ok(Some((version, &rest[end + 1..]))),
+        _ => Err(String::from("Invalid version in file header")),
+    }
+}
+
+// 文本格式（Json 和 Yaml）中普通值和列表都以字符串的形式保存
+#[cfg(any(feature = "json", feature = "yaml"))]
+type TextMap = HashMap<String, String>;
+#[cfg(any(feature = "json", feature = "yaml"))]
+type TextListMap = HashMap<String, Vec<String>>;
+#[cfg(any(feature = "json", feature = "yaml"))]
+type TextOtherDbs = BTreeMap<usize, (TextMap, TextListMap, DbMetaMap)>;
+#[cfg(any(feature = "json", feature = "yaml"))]
+type TextMapRef<'a> = HashMap<&'a str, &'a str>;
+#[cfg(any(feature = "json", feature = "yaml"))]
+type TextListMapRef<'a> = HashMap<&'a str, Vec<&'a str>>;
+
+// 将普通值和列表转换为字符串，文本格式中的数据都是由 serialize_data 生成的合法 UTF-8
+#[cfg(any(feature = "json", feature = "yaml"))]
+fn bytes_to_text<'a>(
+    map: &'a DbMap,
+    list_map: &'a DbListMap,
+) -> (TextMapRef<'a>, TextListMapRef<'a>) {
+    let mut text_map: TextMapRef = HashMap::new();
+    for (key, value) in map.iter() {
+        text_map.insert(key, std::str::from_utf8(value).unwrap());
+    }
 
-#[derive(Debug)]
+    let mut text_list_map: TextListMapRef = HashMap::new();
+    for (key, list) in list_map.iter() {
+        let text_list: Vec<&str> = list
+            .iter()
+            .map(|item| std::str::from_utf8(item).unwrap())
+            .collect();
+        text_list_map.insert(key, text_list);
+    }
+    (text_map, text_list_map)
+}
+
+// 将其他编号的数据库转换为文本格式
+#[cfg(any(feature = "json", feature = "yaml"))]
+fn other_dbs_to_text<'a>(
+    others: &OtherDbRefs<'a>,
+) -> BTreeMap<usize, (TextMapRef<'a>, TextListMapRef<'a>, &'a DbMetaMap)> {
+    others
+        .iter()
+        .map(|(index, (map, list_map, meta_map))| {
+            let (text_map, text_list_map) = bytes_to_text(map, list_map);
+            (*index, (text_map, text_list_map, *meta_map))
+        })
+        .collect()
+}
+
+#[cfg(any(feature = "json", feature = "yaml"))]
+fn text_to_other_dbs(text_dbs: TextOtherDbs) -> OtherDbs {
+    text_dbs
+        .into_iter()
+        .map(|(index, (text_map, text_list_map, meta_map))| {
+            let (map, list_map) = text_to_bytes(text_map, text_list_map);
+            (index, (map, list_map, meta_map))
+        })
+        .collect()
+}
+
+// 将文本格式中读取到的字符串转换回字节数据
+#[cfg(any(feature = "json", feature = "yaml"))]
+fn text_to_bytes(text_map: TextMap, text_list_map: TextListMap) -> (DbMap, DbListMap) {
+    let byte_map: DbMap = text_map
+        .into_iter()
+        .map(|(key, value)| (key, value.into_bytes()))
+        .collect();
+    let byte_list_map: DbListMap = text_list_map
+        .into_iter()
+        .map(|(key, list)| (key, list.into_iter().map(String::into_bytes).collect()))
+        .collect();
+    (byte_map, byte_list_map)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SerializationMethod {
     /// [JSON serialization](https://crates.io/crates/serde_json)
     Json,
@@ -20,7 +135,6 @@ pub enum SerializationMethod {
     Cbor,
 }
 
-
 impl From<i32> for SerializationMethod {
     fn from(item: i32) -> Self {
         match item {
@@ -52,10 +166,7 @@ impl JsonSerializer {
     where
         V: DeserializeOwned,
     {
-        match serde_json::from_str(std::str::from_utf8(ser_data).unwrap()) {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        serde_json::from_str(std::str::from_utf8(ser_data).unwrap()).ok()
     }
 
     fn serialize_data<V>(&self, data: &V) -> Result<Vec<u8>, String>
@@ -68,47 +179,49 @@ impl JsonSerializer {
         }
     }
 
-    fn serialize_db(&self, map: &DbMap, list_map: &DbListMap) -> Result<Vec<u8>, String> {
-        let mut json_map: HashMap<&str, &str> = HashMap::new();
-        for (key, value) in map.iter() {
-            json_map.insert(key, std::str::from_utf8(value).unwrap());
-        }
+    fn serialize_db(
+        &self,
+        map: &DbMap,
+        list_map: &DbListMap,
+        meta_map: &DbMetaMap,
+        others: &OtherDbRefs,
+    ) -> Result<Vec<u8>, String> {
+        let (json_map, json_list_map) = bytes_to_text(map, list_map);
+        let json_others = other_dbs_to_text(others);
 
-        let mut json_list_map: HashMap<&str, Vec<&str>> = HashMap::new();
-        for (key, list) in list_map.iter() {
-            let json_list: Vec<&str> = list
-                .iter()
-                .map(|item| std::str::from_utf8(item).unwrap())
-                .collect();
-            json_list_map.insert(key, json_list);
+        match serde_json::to_string(&(json_map, json_list_map, meta_map, json_others)) {
+            Ok(ser_db) => Ok(ser_db.into_bytes()),
+            Err(err) => Err(err.to_string()),
         }
+    }
 
-        match serde_json::to_string(&(json_map, json_list_map)) {
-            Ok(ser_db) => Ok(ser_db.into_bytes()),
+    fn deserialize_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match serde_json::from_slice::<(TextMap, TextListMap, DbMetaMap, TextOtherDbs)>(ser_db) {
+            Ok((json_map, json_list_map, meta_map, json_others)) => {
+                let (byte_map, byte_list_map) = text_to_bytes(json_map, json_list_map);
+                Ok((
+                    byte_map,
+                    byte_list_map,
+                    meta_map,
+                    text_to_other_dbs(json_others),
+                ))
+            }
             Err(err) => Err(err.to_string()),
         }
     }
 
-    fn deserialize_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap), String> {
-        match serde_json::from_str::<(HashMap<String, String>, HashMap<String, Vec<String>>)>(
-            std::str::from_utf8(ser_db).unwrap(),
-        ) {
+    // 没有文件头的旧版本文件，其中只有普通值和列表，没有元数据和其他编号的数据库
+    fn deserialize_legacy_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match serde_json::from_slice::<(TextMap, TextListMap)>(ser_db) {
             Ok((json_map, json_list_map)) => {
-                let mut byte_map: DbMap = HashMap::new();
-                for (key, value) in json_map.iter() {
-                    byte_map.insert(key.to_string(), value.as_bytes().to_vec());
-                }
-
-                let mut byte_list_map: DbListMap = HashMap::new();
-                for (key, list) in json_list_map.iter() {
-                    let byte_list: Vec<Vec<u8>> =
-                        list.iter().map(|item| item.as_bytes().to_vec()).collect();
-                    byte_list_map.insert(key.to_string(), byte_list);
-                }
-
-                Ok((byte_map, byte_list_map))
+                let (byte_map, byte_list_map) = text_to_bytes(json_map, json_list_map);
+                Ok((
+                    byte_map,
+                    byte_list_map,
+                    DbMetaMap::default(),
+                    OtherDbs::new(),
+                ))
             }
-
             Err(err) => Err(err.to_string()),
         }
     }
@@ -127,10 +240,7 @@ impl YamlSerializer {
     where
         V: DeserializeOwned,
     {
-        match serde_yaml::from_str(std::str::from_utf8(ser_data).unwrap()) {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        serde_yaml::from_str(std::str::from_utf8(ser_data).unwrap()).ok()
     }
 
     fn serialize_data<V>(&self, data: &V) -> Result<Vec<u8>, String>
@@ -143,47 +253,49 @@ impl YamlSerializer {
         }
     }
 
-    fn serialize_db(&self, map: &DbMap, list_map: &DbListMap) -> Result<Vec<u8>, String> {
-        let mut yaml_map: HashMap<&str, &str> = HashMap::new();
-        for (key, value) in map.iter() {
-            yaml_map.insert(key, std::str::from_utf8(value).unwrap());
-        }
+    fn serialize_db(
+        &self,
+        map: &DbMap,
+        list_map: &DbListMap,
+        meta_map: &DbMetaMap,
+        others: &OtherDbRefs,
+    ) -> Result<Vec<u8>, String> {
+        let (yaml_map, yaml_list_map) = bytes_to_text(map, list_map);
+        let yaml_others = other_dbs_to_text(others);
 
-        let mut yaml_list_map: HashMap<&str, Vec<&str>> = HashMap::new();
-        for (key, list) in list_map.iter() {
-            let yaml_list: Vec<&str> = list
-                .iter()
-                .map(|item| std::str::from_utf8(item).unwrap())
-                .collect();
-            yaml_list_map.insert(key, yaml_list);
+        match serde_yaml::to_string(&(yaml_map, yaml_list_map, meta_map, yaml_others)) {
+            Ok(ser_db) => Ok(ser_db.into_bytes()),
+            Err(err) => Err(err.to_string()),
         }
+    }
 
-        match serde_yaml::to_string(&(yaml_map, yaml_list_map)) {
-            Ok(ser_db) => Ok(ser_db.into_bytes()),
+    fn deserialize_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match serde_yaml::from_slice::<(TextMap, TextListMap, DbMetaMap, TextOtherDbs)>(ser_db) {
+            Ok((yaml_map, yaml_list_map, meta_map, yaml_others)) => {
+                let (byte_map, byte_list_map) = text_to_bytes(yaml_map, yaml_list_map);
+                Ok((
+                    byte_map,
+                    byte_list_map,
+                    meta_map,
+                    text_to_other_dbs(yaml_others),
+                ))
+            }
             Err(err) => Err(err.to_string()),
         }
     }
 
-    fn deserialize_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap), String> {
-        match serde_yaml::from_str::<(HashMap<String, String>, HashMap<String, Vec<String>>)>(
-            std::str::from_utf8(ser_db).unwrap(),
-        ) {
+    // 没有文件头的旧版本文件，与 Json 相同，其中只有普通值和列表
+    fn deserialize_legacy_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match serde_yaml::from_slice::<(TextMap, TextListMap)>(ser_db) {
             Ok((yaml_map, yaml_list_map)) => {
-                let mut byte_map: DbMap = HashMap::new();
-                for (key, value) in yaml_map.iter() {
-                    byte_map.insert(key.to_string(), value.as_bytes().to_vec());
-                }
-
-                let mut byte_list_map: DbListMap = HashMap::new();
-                for (key, list) in yaml_list_map.iter() {
-                    let byte_list: Vec<Vec<u8>> =
-                        list.iter().map(|item| item.as_bytes().to_vec()).collect();
-                    byte_list_map.insert(key.to_string(), byte_list);
-                }
-
-                Ok((byte_map, byte_list_map))
+                let (byte_map, byte_list_map) = text_to_bytes(yaml_map, yaml_list_map);
+                Ok((
+                    byte_map,
+                    byte_list_map,
+                    DbMetaMap::default(),
+                    OtherDbs::new(),
+                ))
             }
-
             Err(err) => Err(err.to_string()),
         }
     }
@@ -202,10 +314,7 @@ impl BincodeSerializer {
     where
         V: DeserializeOwned,
     {
-        match bincode::deserialize(ser_data) {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        bincode::deserialize(ser_data).ok()
     }
 
     fn serialize_data<V>(&self, data: &V) -> Result<Vec<u8>, String>
@@ -218,13 +327,39 @@ impl BincodeSerializer {
         }
     }
 
-    fn serialize_db(&self, map: &DbMap, list_map: &DbListMap) -> Result<Vec<u8>, String> {
-        self.serialize_data(&(map, list_map))
+    fn serialize_db(
+        &self,
+        map: &DbMap,
+        list_map: &DbListMap,
+        meta_map: &DbMetaMap,
+        others: &OtherDbRefs,
+    ) -> Result<Vec<u8>, String> {
+        self.serialize_data(&(map, list_map, meta_map, others))
     }
 
-    fn deserialize_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap), String> {
-        match self.deserialize_data(ser_db) {
-            Some((map, list_map)) => Ok((map, list_map)),
+    // Bincode 会忽略多余的数据，读取数据库文件时要求文件中的数据被完整地读取
+    fn decode_exact<V>(ser_db: &[u8]) -> Option<V>
+    where
+        V: DeserializeOwned,
+    {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(ser_db)
+            .ok()
+    }
+
+    fn deserialize_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match BincodeSerializer::decode_exact(ser_db) {
+            Some(db) => Ok(db),
+            None => Err(String::from("Cannot deserialize DB")),
+        }
+    }
+
+    // 没有文件头的旧版本文件，其中只有普通值和列表，要求恰好读完整个文件
+    fn deserialize_legacy_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match BincodeSerializer::decode_exact(ser_db) {
+            Some((map, list_map)) => Ok((map, list_map, DbMetaMap::default(), OtherDbs::new())),
             None => Err(String::from("Cannot deserialize DB")),
         }
     }
@@ -243,10 +378,7 @@ impl CborSerializer {
     where
         V: DeserializeOwned,
     {
-        match serde_cbor::from_slice(ser_data) {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        serde_cbor::from_slice(ser_data).ok()
     }
 
     fn serialize_data<V>(&self, data: &V) -> Result<Vec<u8>, String>
@@ -259,13 +391,27 @@ impl CborSerializer {
         }
     }
 
-    fn serialize_db(&self, map: &DbMap, list_map: &DbListMap) -> Result<Vec<u8>, String> {
-        self.serialize_data(&(map, list_map))
+    fn serialize_db(
+        &self,
+        map: &DbMap,
+        list_map: &DbListMap,
+        meta_map: &DbMetaMap,
+        others: &OtherDbRefs,
+    ) -> Result<Vec<u8>, String> {
+        self.serialize_data(&(map, list_map, meta_map, others))
+    }
+
+    fn deserialize_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match serde_cbor::from_slice(ser_db) {
+            Ok(db) => Ok(db),
+            Err(err) => Err(err.to_string()),
+        }
     }
 
-    fn deserialize_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap), String> {
+    // 没有文件头的旧版本文件，其中只有普通值和列表
+    fn deserialize_legacy_db(&self, ser_db: &[u8]) -> Result<Db, String> {
         match self.deserialize_data(ser_db) {
-            Some((map, list_map)) => Ok((map, list_map)),
+            Some((map, list_map)) => Ok((map, list_map, DbMetaMap::default(), OtherDbs::new())),
             None => Err(String::from("Cannot deserialize DB")),
         }
     }
@@ -348,51 +494,328 @@ impl Serializer {
         }
     }
 
+    // 序列化之后的数据以文件头开始，见 FORMAT_VERSION
     pub(crate) fn serialize_db(
         &self,
         map: &DbMap,
         list_map: &DbListMap,
+        meta_map: &DbMetaMap,
+        others: &OtherDbRefs,
     ) -> Result<Vec<u8>, String> {
+        let payload = self.serialize_payload(map, list_map, meta_map, others)?;
+        let mut ser_db = format!("KVSTORE {}\n", FORMAT_VERSION).into_bytes();
+        ser_db.extend_from_slice(&payload);
+        Ok(ser_db)
+    }
+
+    fn serialize_payload(
+        &self,
+        map: &DbMap,
+        list_map: &DbListMap,
+        meta_map: &DbMetaMap,
+        others: &OtherDbRefs,
+    ) -> Result<Vec<u8>, String> {
+        #[allow(unreachable_patterns)]
+        match self.ser_method {
+            #[cfg(feature = "json")]
+            SerializationMethod::Json => self
+                .json_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "bincode")]
+            SerializationMethod::Bin => self
+                .bincode_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "yaml")]
+            SerializationMethod::Yaml => self
+                .yaml_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "cbor")]
+            SerializationMethod::Cbor => self
+                .cbor_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "json")]
+            _ => self
+                .json_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "bincode")]
+            _ => self
+                .bincode_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "yaml")]
+            _ => self
+                .yaml_serializer
+                .serialize_db(map, list_map, meta_map, others),
+            #[cfg(feature = "cbor")]
+            _ => self
+                .cbor_serializer
+                .serialize_db(map, list_map, meta_map, others),
+        }
+    }
+
+    pub(crate) fn method(&self) -> SerializationMethod {
+        self.ser_method
+    }
+
+    // 检查调用者直接提供的序列化数据能否被写入数据库文件，
+    // Json 和 Yaml 会把数据当作字符串写入文件，因此必须是合法的 UTF-8
+    pub(crate) fn check_raw(&self, ser_data: &[u8]) -> Result<(), String> {
+        match self.ser_method {
+            #[cfg(feature = "bincode")]
+            SerializationMethod::Bin => Ok(()),
+            #[cfg(feature = "cbor")]
+            SerializationMethod::Cbor => Ok(()),
+            _ => match std::str::from_utf8(ser_data) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(err.to_string()),
+            },
+        }
+    }
+
+    // 按照文件头中的版本号读取，没有文件头的旧版本文件交给 deserialize_legacy_db
+    pub(crate) fn deserialize_db(&self, ser_db: &[u8]) -> Result<Db, String> {
+        match split_header(ser_db)? {
+            Some((FORMAT_VERSION, payload)) => self.deserialize_payload(payload),
+            Some((version, _)) => Err(format!(
+                "Unsupported DB file version {}, expected version {}",
+                version, FORMAT_VERSION
+            )),
+            None => self.deserialize_legacy_db(ser_db),
+        }
+    }
+
+    fn deserialize_payload(&self, payload: &[u8]) -> Result<Db, String> {
         #[allow(unreachable_patterns)]
         match self.ser_method {
             #[cfg(feature = "json")]
-            SerializationMethod::Json => self.json_serializer.serialize_db(map, list_map),
+            SerializationMethod::Json => self.json_serializer.deserialize_db(payload),
             #[cfg(feature = "bincode")]
-            SerializationMethod::Bin => self.bincode_serializer.serialize_db(map, list_map),
+            SerializationMethod::Bin => self.bincode_serializer.deserialize_db(payload),
             #[cfg(feature = "yaml")]
-            SerializationMethod::Yaml => self.yaml_serializer.serialize_db(map, list_map),
+            SerializationMethod::Yaml => self.yaml_serializer.deserialize_db(payload),
             #[cfg(feature = "cbor")]
-            SerializationMethod::Cbor => self.cbor_serializer.serialize_db(map, list_map),
+            SerializationMethod::Cbor => self.cbor_serializer.deserialize_db(payload),
             #[cfg(feature = "json")]
-            _ => self.json_serializer.serialize_db(map, list_map),
+            _ => self.json_serializer.deserialize_db(payload),
             #[cfg(feature = "bincode")]
-            _ => self.bincode_serializer.serialize_db(map, list_map),
+            _ => self.bincode_serializer.deserialize_db(payload),
             #[cfg(feature = "yaml")]
-            _ => self.yaml_serializer.serialize_db(map, list_map),
+            _ => self.yaml_serializer.deserialize_db(payload),
             #[cfg(feature = "cbor")]
-            _ => self.cbor_serializer.serialize_db(map, list_map),
+            _ => self.cbor_serializer.deserialize_db(payload),
         }
     }
 
-    pub(crate) fn deserialize_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap), String> {
+    fn deserialize_legacy_db(&self, ser_db: &[u8]) -> Result<Db, String> {
         #[allow(unreachable_patterns)]
         match self.ser_method {
             #[cfg(feature = "json")]
-            SerializationMethod::Json => self.json_serializer.deserialize_db(ser_db),
+            SerializationMethod::Json => self.json_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "bincode")]
-            SerializationMethod::Bin => self.bincode_serializer.deserialize_db(ser_db),
+            SerializationMethod::Bin => self.bincode_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "yaml")]
-            SerializationMethod::Yaml => self.yaml_serializer.deserialize_db(ser_db),
+            SerializationMethod::Yaml => self.yaml_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "cbor")]
-            SerializationMethod::Cbor => self.cbor_serializer.deserialize_db(ser_db),
+            SerializationMethod::Cbor => self.cbor_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "json")]
-            _ => self.json_serializer.deserialize_db(ser_db),
+            _ => self.json_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "bincode")]
-            _ => self.bincode_serializer.deserialize_db(ser_db),
+            _ => self.bincode_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "yaml")]
-            _ => self.yaml_serializer.deserialize_db(ser_db),
+            _ => self.yaml_serializer.deserialize_legacy_db(ser_db),
             #[cfg(feature = "cbor")]
-            _ => self.cbor_serializer.deserialize_db(ser_db),
+            _ => self.cbor_serializer.deserialize_legacy_db(ser_db),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    // 编译时启用的所有序列化方法
+    const METHODS: &[SerializationMethod] = &[
+        #[cfg(feature = "json")]
+        SerializationMethod::Json,
+        #[cfg(feature = "bincode")]
+        SerializationMethod::Bin,
+        #[cfg(feature = "yaml")]
+        SerializationMethod::Yaml,
+        #[cfg(feature = "cbor")]
+        SerializationMethod::Cbor,
+    ];
+
+    // 旧版本的文件中的键值和列表，文本格式中的值保存为字符串
+    type TextMaps = (HashMap<String, String>, HashMap<String, Vec<String>>);
+    type ByteMaps = (HashMap<String, Vec<u8>>, HashMap<String, Vec<Vec<u8>>>);
+
+    fn time(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn is_text(method: SerializationMethod) -> bool {
+        matches!(
+            method,
+            SerializationMethod::Json | SerializationMethod::Yaml
+        )
+    }
+
+    fn sample_keyspace() -> Keyspace {
+        let mut map = DbMap::default();
+        map.insert(String::from("key"), b"\"value\"".to_vec());
+        let mut list_map = DbListMap::default();
+        list_map.insert(
+            String::from("list"),
+            vec![b"1".to_vec(), b"2".to_vec()].into(),
+        );
+        let mut meta = DbMetaMap::default();
+        let mut key_meta = KeyMetadata::now();
+        key_meta.created = time(100);
+        key_meta.modified = time(200);
+        key_meta.expires_at = Some(time(4_000_000_000));
+        key_meta.type_tag = Some(String::from("alloc::string::String"));
+        meta.insert(String::from("key"), key_meta);
+        (map, list_map, meta)
+    }
+
+    // 用 method 序列化旧版本的文件内容，文本格式中的值保存为字符串
+    fn encode_legacy<T, B>(method: SerializationMethod, text: &T, binary: &B) -> Vec<u8>
+    where
+        T: Serialize,
+        B: Serialize,
+    {
+        let serializer = Serializer::new(method);
+        if is_text(method) {
+            serializer.serialize_data(text).unwrap()
+        } else {
+            serializer.serialize_data(binary).unwrap()
+        }
+    }
+
+    fn text_maps() -> TextMaps {
+        let map = [(String::from("key"), String::from("\"value\""))];
+        let list_map = [(
+            String::from("list"),
+            vec![String::from("1"), String::from("2")],
+        )];
+        (map.into_iter().collect(), list_map.into_iter().collect())
+    }
+
+    fn byte_maps() -> ByteMaps {
+        let map = [(String::from("key"), b"\"value\"".to_vec())];
+        let list_map = [(String::from("list"), vec![b"1".to_vec(), b"2".to_vec()])];
+        (map.into_iter().collect(), list_map.into_iter().collect())
+    }
+
+    fn assert_sample_data(db: &Db) {
+        assert_eq!(db.0.get("key"), Some(&b"\"value\"".to_vec()));
+        let list: Vec<&[u8]> =
+            db.1.get("list")
+                .unwrap()
+                .iter()
+                .map(Vec::as_slice)
+                .collect();
+        assert_eq!(list, vec![&b"1"[..], &b"2"[..]]);
+    }
+
+    #[test]
+    fn round_trip_every_method() {
+        for &method in METHODS {
+            let serializer = Serializer::new(method);
+            let (map, list_map, meta) = sample_keyspace();
+            let (other_map, other_list_map, other_meta) = sample_keyspace();
+            let mut others = OtherDbRefs::new();
+            others.insert(3, (&other_map, &other_list_map, &other_meta));
+
+            let ser_db = serializer
+                .serialize_db(&map, &list_map, &meta, &others)
+                .unwrap();
+            assert!(ser_db.starts_with(b"KVSTORE 1\n"), "{}", method);
+
+            let db = serializer.deserialize_db(&ser_db).unwrap();
+            assert_sample_data(&db);
+            assert_eq!(db.2.get("key"), meta.get("key"), "{}", method);
+            let other = db.3.get(&3).unwrap();
+            assert_eq!(other.0.get("key"), Some(&b"\"value\"".to_vec()));
+            assert_eq!(other.2.get("key"), meta.get("key"), "{}", method);
+        }
+    }
+
+    #[test]
+    fn loads_files_without_metadata() {
+        for &method in METHODS {
+            let ser_db = encode_legacy(method, &text_maps(), &byte_maps());
+            let db = Serializer::new(method).deserialize_db(&ser_db).unwrap();
+            assert_sample_data(&db);
+            assert_eq!(db.2.len(), 0, "{}", method);
+            assert!(db.3.is_empty(), "{}", method);
+        }
+    }
+
+    // 没有文件头的文件只能是只有普通值和列表的旧格式，带有元数据的布局不会被猜测读取
+    #[test]
+    fn unversioned_file_with_metadata_is_an_error() {
+        for &method in METHODS {
+            let (text_map, text_list_map) = text_maps();
+            let (byte_map, byte_list_map) = byte_maps();
+            let (_, _, meta) = sample_keyspace();
+            let ser_db = encode_legacy(
+                method,
+                &(text_map, text_list_map, &meta),
+                &(byte_map, byte_list_map, &meta),
+            );
+            assert!(
+                Serializer::new(method).deserialize_db(&ser_db).is_err(),
+                "{}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_version_is_an_error() {
+        for &method in METHODS {
+            let serializer = Serializer::new(method);
+            let (map, list_map, meta) = sample_keyspace();
+            let ser_db = serializer
+                .serialize_db(&map, &list_map, &meta, &OtherDbRefs::new())
+                .unwrap();
+            let future = [b"KVSTORE 2\n", &ser_db[b"KVSTORE 1\n".len()..]].concat();
+            let err = serializer.deserialize_db(&future).err().unwrap();
+            assert!(err.contains("version 2"), "{}: {}", method, err);
+        }
+    }
+
+    // 带有文件头的文件只按照当前的格式读取，不会退回到丢弃元数据的旧格式
+    #[test]
+    fn versioned_file_in_another_layout_is_an_error() {
+        for &method in METHODS {
+            let ser_db = encode_legacy(method, &text_maps(), &byte_maps());
+            let versioned = [&b"KVSTORE 1\n"[..], &ser_db].concat();
+            assert!(
+                Serializer::new(method).deserialize_db(&versioned).is_err(),
+                "{}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        for &method in METHODS {
+            let serializer = Serializer::new(method);
+            let (map, list_map, meta) = sample_keyspace();
+            let ser_db = serializer
+                .serialize_db(&map, &list_map, &meta, &OtherDbRefs::new())
+                .unwrap();
+            let truncated = &ser_db[..ser_db.len() - 4];
+            assert!(serializer.deserialize_db(truncated).is_err(), "{}", method);
+            assert!(
+                serializer.deserialize_db(b"KVSTORE 1").is_err(),
+                "{}",
+                method
+            );
         }
     }
 }
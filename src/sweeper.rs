@@ -0,0 +1,29 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::background::BackgroundTask;
+use crate::keyvaluedb::KeyValueDb;
+
+// 在后台线程中每隔 interval 调用一次 purge_expired，删除已经过期的键，
+// 是否写入文件由数据库的存储策略决定。purge_expired 失败时会在下一次继续尝试。
+// KeyValueDbSweeper 被 drop 时停止后台线程。例如：
+//
+//     let db = Arc::new(Mutex::new(db));
+//     let _sweeper = KeyValueDbSweeper::start(Arc::clone(&db), Duration::from_secs(60));
+pub struct KeyValueDbSweeper {
+    task: BackgroundTask,
+}
+
+impl KeyValueDbSweeper {
+    pub fn start(db: Arc<Mutex<KeyValueDb>>, interval: Duration) -> KeyValueDbSweeper {
+        let task = BackgroundTask::every(interval, move || {
+            let _ = db.lock().unwrap().purge_expired();
+        });
+        KeyValueDbSweeper { task }
+    }
+
+    // 停止后台线程，等待正在进行的清理完成
+    pub fn stop(mut self) {
+        self.task.stop();
+    }
+}